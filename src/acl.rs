@@ -0,0 +1,207 @@
+//! Conversion between the textual POSIX ACLs stored in `SCHILY.acl.access`
+//! and `SCHILY.acl.default` pax records by star and GNU tar, and the binary
+//! format of the `system.posix_acl_access` and `system.posix_acl_default`
+//! extended attributes on Linux.
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::ptr;
+
+use crate::other;
+
+pub const PAX_ACL_ACCESS: &str = "SCHILY.acl.access";
+pub const PAX_ACL_DEFAULT: &str = "SCHILY.acl.default";
+pub const XATTR_ACL_ACCESS: &str = "system.posix_acl_access";
+pub const XATTR_ACL_DEFAULT: &str = "system.posix_acl_default";
+
+// Constants of the `posix_acl_xattr` format from the Linux headers.
+const ACL_XATTR_VERSION: u32 = 2;
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+/// A single entry of an ACL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AclEntry {
+    tag: u16,
+    id: u32,
+    perm: u16,
+}
+
+/// Parses an ACL in the long or short text form, as documented in `acl(5)`,
+/// which is what star and GNU tar store in pax records.
+///
+/// Entries are separated by commas or newlines. Named users and groups may
+/// be followed by a numeric ID, as written by star, which takes precedence
+/// over the name.
+pub fn parse(text: &str) -> io::Result<Vec<AclEntry>> {
+    let mut entries = Vec::new();
+    for entry in text.split(&[',', '\n'][..]) {
+        let entry = match entry.find('#') {
+            Some(i) => &entry[..i],
+            None => entry,
+        };
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let invalid = || other(&format!("invalid ACL entry `{}`", entry));
+
+        let fields = entry.split(':').collect::<Vec<_>>();
+        let (tag, qualifier, perm, id) = match fields[..] {
+            [tag, perm] => (tag, "", perm, None),
+            [tag, qualifier, perm] => (tag, qualifier, perm, None),
+            [tag, qualifier, perm, id] => (tag, qualifier, perm, Some(id)),
+            _ => return Err(invalid()),
+        };
+        let named = !qualifier.is_empty();
+        let tag = match tag {
+            "user" | "u" if named => ACL_USER,
+            "user" | "u" => ACL_USER_OBJ,
+            "group" | "g" if named => ACL_GROUP,
+            "group" | "g" => ACL_GROUP_OBJ,
+            "mask" | "m" if !named => ACL_MASK,
+            "other" | "o" if !named => ACL_OTHER,
+            _ => return Err(invalid()),
+        };
+        let id = if !named {
+            ACL_UNDEFINED_ID
+        } else {
+            match id {
+                Some(id) => id.parse().map_err(|_| invalid())?,
+                None => match qualifier.parse() {
+                    Ok(id) => id,
+                    Err(_) => lookup_id(qualifier, tag == ACL_GROUP).ok_or_else(|| {
+                        other(&format!("unknown user or group in ACL entry `{}`", entry))
+                    })?,
+                },
+            }
+        };
+        let mut bits = 0;
+        for c in perm.chars() {
+            bits |= match c {
+                'r' => 4,
+                'w' => 2,
+                'x' => 1,
+                '-' => 0,
+                _ => return Err(invalid()),
+            };
+        }
+        entries.push(AclEntry {
+            tag,
+            id,
+            perm: bits,
+        });
+    }
+    Ok(entries)
+}
+
+/// Formats an ACL in the long text form with numeric IDs, separating entries
+/// with commas.
+pub fn to_text(entries: &[AclEntry]) -> String {
+    let mut text = String::new();
+    for entry in entries {
+        if !text.is_empty() {
+            text.push(',');
+        }
+        let (tag, named) = match entry.tag {
+            ACL_USER_OBJ => ("user", false),
+            ACL_USER => ("user", true),
+            ACL_GROUP_OBJ => ("group", false),
+            ACL_GROUP => ("group", true),
+            ACL_MASK => ("mask", false),
+            _ => ("other", false),
+        };
+        text.push_str(tag);
+        text.push(':');
+        if named {
+            text.push_str(&entry.id.to_string());
+        }
+        text.push(':');
+        text.push(if entry.perm & 4 != 0 { 'r' } else { '-' });
+        text.push(if entry.perm & 2 != 0 { 'w' } else { '-' });
+        text.push(if entry.perm & 1 != 0 { 'x' } else { '-' });
+    }
+    text
+}
+
+/// Encodes an ACL as the value of a `system.posix_acl_*` extended attribute.
+pub fn to_xattr(entries: &[AclEntry]) -> Vec<u8> {
+    // The kernel requires the entries to be sorted by tag and then ID.
+    let mut entries = entries.to_vec();
+    entries.sort_by_key(|e| (e.tag, e.id));
+
+    let mut data = Vec::with_capacity(4 + 8 * entries.len());
+    data.extend_from_slice(&ACL_XATTR_VERSION.to_le_bytes());
+    for entry in entries {
+        data.extend_from_slice(&entry.tag.to_le_bytes());
+        data.extend_from_slice(&entry.perm.to_le_bytes());
+        data.extend_from_slice(&entry.id.to_le_bytes());
+    }
+    data
+}
+
+/// Decodes the value of a `system.posix_acl_*` extended attribute.
+pub fn from_xattr(data: &[u8]) -> io::Result<Vec<AclEntry>> {
+    let malformed = || other("malformed ACL extended attribute");
+    if data.len() < 4 {
+        return Err(malformed());
+    }
+    let chunks = data[4..].chunks_exact(8);
+    if !chunks.remainder().is_empty() {
+        return Err(malformed());
+    }
+    let mut version = [0; 4];
+    version.copy_from_slice(&data[..4]);
+    if u32::from_le_bytes(version) != ACL_XATTR_VERSION {
+        return Err(other("unsupported ACL extended attribute version"));
+    }
+    Ok(chunks
+        .map(|chunk| AclEntry {
+            tag: u16::from_le_bytes([chunk[0], chunk[1]]),
+            perm: u16::from_le_bytes([chunk[2], chunk[3]]),
+            id: u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+        })
+        .collect())
+}
+
+/// Looks up the numeric ID of the user or group called `name`.
+fn lookup_id(name: &str, group: bool) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    unsafe {
+        if group {
+            let mut grp: libc::group = mem::zeroed();
+            let mut result = ptr::null_mut();
+            let rc = libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            );
+            if rc == 0 && !result.is_null() {
+                return Some(grp.gr_gid);
+            }
+        } else {
+            let mut pwd: libc::passwd = mem::zeroed();
+            let mut result = ptr::null_mut();
+            let rc = libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            );
+            if rc == 0 && !result.is_null() {
+                return Some(pwd.pw_uid);
+            }
+        }
+    }
+    None
+}
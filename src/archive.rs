@@ -1,17 +1,38 @@
 use std::cell::{Cell, RefCell};
 use std::cmp;
+use std::collections::{BTreeSet, HashMap};
 use std::convert::TryFrom;
-use std::fs;
+use std::fs::File;
 use std::io::prelude::*;
 use std::io::{self, SeekFrom};
 use std::marker;
-use std::path::Path;
+use std::mem;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
-use crate::entry::{EntryFields, EntryIo};
+#[cfg(any(
+    feature = "bzip2",
+    feature = "flate2",
+    feature = "ruzstd",
+    feature = "xz2",
+    feature = "zstd"
+))]
+use crate::compression::{Compression, Decompressor};
+use crate::entry::{
+    self, Durability, EntryFields, EntryIo, EntryMeta, Outcome, OverwritePolicy, Region,
+    XattrFilter, XattrWarning,
+};
 use crate::error::TarError;
 use crate::other;
 use crate::pax::*;
-use crate::{Entry, GnuExtSparseHeader, GnuSparseHeader, Header};
+use crate::plan::{PlannedEntry, Planner};
+use crate::shared::{ReadAt, SharedArchive, SharedEntry};
+use crate::target::{FsTarget, UnpackTarget};
+use crate::{ArchiveIndex, Entry, GnuExtSparseHeader, GnuSparseHeader, Header};
 
 /// A top-level representation of an archive file.
 ///
@@ -24,10 +45,17 @@ pub struct ArchiveInner<R: ?Sized> {
     pos: Cell<u64>,
     mask: u32,
     unpack_xattrs: bool,
+    xattr_filter: XattrFilter,
+    xattr_best_effort: bool,
+    xattr_warnings: Vec<XattrWarning>,
+    unpack_acls: bool,
     preserve_permissions: bool,
     preserve_ownerships: bool,
     preserve_mtime: bool,
-    overwrite: bool,
+    preserve_atime: bool,
+    overwrite: OverwritePolicy,
+    atomic: bool,
+    durability: Durability,
     ignore_zeros: bool,
     obj: RefCell<R>,
 }
@@ -38,7 +66,58 @@ pub struct Entries<'a, R: 'a + Read> {
     _ignored: marker::PhantomData<&'a Archive<R>>,
 }
 
-trait SeekRead: Read + Seek {}
+/// The action to take for an entry when unpacking an archive with
+/// [`Archive::unpack_with`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnpackAction {
+    /// Unpack the entry to the location given by its path.
+    Extract,
+    /// Do not unpack the entry.
+    Skip,
+    /// Unpack the entry as if it had the given path instead.
+    ///
+    /// The path is sanitized in the same way as the paths of entries
+    /// themselves, so it cannot be used to write outside of the destination.
+    Rename(PathBuf),
+}
+
+/// A set of callbacks invoked for each entry by [`Archive::unpack_with`].
+///
+/// Both methods have default implementations, so a visitor only needs to
+/// implement the hooks that it is interested in.
+pub trait UnpackVisitor {
+    /// Invoked for each entry before it is unpacked, deciding what should be
+    /// done with it.
+    ///
+    /// Directories are unpacked after all other entries, so they are only
+    /// passed to the visitor once every other entry has been unpacked.
+    /// Returning an error aborts the unpacking of the archive. The default
+    /// implementation unpacks every entry.
+    fn before_unpack<R: Read>(&mut self, entry: &Entry<'_, R>) -> io::Result<UnpackAction> {
+        let _ = entry;
+        Ok(UnpackAction::Extract)
+    }
+
+    /// Invoked after an entry has been unpacked to `dst`.
+    ///
+    /// `file` is the file which was written for regular files, and `None`
+    /// for directories, links and other kinds of entries, whose type can be
+    /// found in the header of `entry`. This is not invoked for entries which
+    /// weren't unpacked because the overwrite policy says to keep what
+    /// already exists at `dst`.
+    /// Returning an error aborts the unpacking of the archive.
+    fn after_unpack<R: Read>(
+        &mut self,
+        entry: &Entry<'_, R>,
+        dst: &Path,
+        file: Option<File>,
+    ) -> io::Result<()> {
+        let _ = (entry, dst, file);
+        Ok(())
+    }
+}
+
+pub trait SeekRead: Read + Seek {}
 impl<R: Read + Seek> SeekRead for R {}
 
 struct EntriesFields<'a> {
@@ -47,6 +126,7 @@ struct EntriesFields<'a> {
     next: u64,
     done: bool,
     raw: bool,
+    global_pax: Option<PaxMap>,
 }
 
 impl<R: Read> Archive<R> {
@@ -56,10 +136,17 @@ impl<R: Read> Archive<R> {
             inner: ArchiveInner {
                 mask: u32::MIN,
                 unpack_xattrs: false,
+                xattr_filter: XattrFilter::new(),
+                xattr_best_effort: false,
+                xattr_warnings: Vec::new(),
+                unpack_acls: false,
                 preserve_permissions: false,
                 preserve_ownerships: false,
                 preserve_mtime: true,
-                overwrite: true,
+                preserve_atime: false,
+                overwrite: OverwritePolicy::Unlink,
+                atomic: false,
+                durability: Durability::Unsynced,
                 ignore_zeros: false,
                 obj: RefCell::new(obj),
                 pos: Cell::new(0),
@@ -107,7 +194,117 @@ impl<R: Read> Archive<R> {
     /// ```
     pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
         let me: &mut Archive<dyn Read> = self;
-        me._unpack(dst.as_ref())
+        me._unpack(&mut FsTarget::new(), dst.as_ref())
+    }
+
+    /// Unpacks the contents tarball into `dst` inside of `target`, rather
+    /// than into the local filesystem.
+    ///
+    /// This behaves exactly like [`Archive::unpack`], including its
+    /// protection against writing outside of `dst`, but every file, directory
+    /// and link is created through the given [`UnpackTarget`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, MemoryTarget};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// let mut target = MemoryTarget::new();
+    /// ar.unpack_into(&mut target, "/").unwrap();
+    /// let readme = target.get("/README").unwrap();
+    /// println!("{}", String::from_utf8_lossy(readme.contents().unwrap()));
+    /// ```
+    pub fn unpack_into<T: UnpackTarget, P: AsRef<Path>>(
+        &mut self,
+        target: &mut T,
+        dst: P,
+    ) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack(target, dst.as_ref())
+    }
+
+    /// Unpacks the contents tarball into the specified `dst`, consulting
+    /// `visitor` for each entry.
+    ///
+    /// This behaves like [`Archive::unpack`], including the handling of
+    /// directories which are created after all other entries, but the visitor
+    /// decides before each entry whether it is extracted, skipped, or
+    /// extracted under a different path. After an entry has been written the
+    /// visitor is notified with where it was written to.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use std::io::{self, Read};
+    /// use tar::{Archive, Entry, UnpackAction, UnpackVisitor};
+    ///
+    /// struct OnlyBin;
+    ///
+    /// impl UnpackVisitor for OnlyBin {
+    ///     fn before_unpack<R: Read>(&mut self, entry: &Entry<R>) -> io::Result<UnpackAction> {
+    ///         if entry.path()?.starts_with("bin") {
+    ///             Ok(UnpackAction::Extract)
+    ///         } else {
+    ///             Ok(UnpackAction::Skip)
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.unpack_with("foo", &mut OnlyBin).unwrap();
+    /// ```
+    pub fn unpack_with<P: AsRef<Path>, V: UnpackVisitor>(
+        &mut self,
+        dst: P,
+        visitor: &mut V,
+    ) -> io::Result<()> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack_with(&mut FsTarget::new(), dst.as_ref(), |unpacker, fields| {
+            let entry = fields.into_entry::<io::Empty>();
+            let path = match visitor.before_unpack(&entry)? {
+                UnpackAction::Extract => None,
+                UnpackAction::Skip => return Ok(()),
+                UnpackAction::Rename(path) => Some(path),
+            };
+            unpack_visited(unpacker, entry, path, visitor)
+        })
+    }
+
+    /// Works out what unpacking the contents of this tarball into `dst` would
+    /// do, without modifying the filesystem.
+    ///
+    /// The entries are listed in the order in which [`Archive::unpack`] would
+    /// process them, so directories come last, along with the action which
+    /// would be taken for each of them. Paths are sanitized in the same way
+    /// as when unpacking, and the configured overwrite policy is taken into
+    /// account for paths which already exist. Symlinks created by earlier
+    /// entries are followed when checking whether later entries would be
+    /// written outside of `dst`.
+    ///
+    /// The filesystem is only inspected and never modified, but if it changes
+    /// before the archive is actually unpacked then the plan may no longer be
+    /// accurate. If unpacking would fail at some entry then the plan ends with
+    /// that entry.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::{Archive, PlannedAction};
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// for entry in ar.unpack_plan("foo").unwrap() {
+    ///     if let PlannedAction::Escape { .. } = entry.action() {
+    ///         println!("{} is malicious", entry.path().display());
+    ///     }
+    /// }
+    /// ```
+    pub fn unpack_plan<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<Vec<PlannedEntry>> {
+        let me: &mut Archive<dyn Read> = self;
+        me._unpack_plan(dst.as_ref())
     }
 
     /// Set the mask of the permission bits when unpacking this entry.
@@ -135,6 +332,62 @@ impl<R: Read> Archive<R> {
         self.inner.unpack_xattrs = unpack_xattrs;
     }
 
+    /// Configure which extended attributes are restored when unpacking this
+    /// archive, if `set_unpack_xattrs` is enabled.
+    ///
+    /// All attributes are restored by default.
+    pub fn set_xattr_filter(&mut self, filter: XattrFilter) {
+        self.inner.xattr_filter = filter;
+    }
+
+    /// Indicate whether extended attributes are restored on a best effort
+    /// basis when unpacking this archive.
+    ///
+    /// Unpacking normally fails if any attribute can't be set, for example
+    /// because the filesystem doesn't support it or because setting it
+    /// requires privileges. When enabled such attributes are skipped instead,
+    /// and the unpacking methods of the archive collect them to be retrieved
+    /// with `take_xattr_warnings`.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_xattr_best_effort(&mut self, best_effort: bool) {
+        self.inner.xattr_best_effort = best_effort;
+    }
+
+    /// Returns the extended attributes which were skipped while unpacking
+    /// this archive in best effort mode, clearing them from the archive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.set_unpack_xattrs(true);
+    /// ar.set_xattr_best_effort(true);
+    /// ar.unpack("foo").unwrap();
+    /// for warning in ar.take_xattr_warnings() {
+    ///     eprintln!("warning: {}", warning);
+    /// }
+    /// ```
+    pub fn take_xattr_warnings(&mut self) -> Vec<XattrWarning> {
+        mem::take(&mut self.inner.xattr_warnings)
+    }
+
+    /// Indicate whether POSIX ACLs are restored when unpacking this archive.
+    ///
+    /// ACLs are read from the `SCHILY.acl.access` and `SCHILY.acl.default`
+    /// pax records written by star and GNU tar, and applied through the
+    /// `system.posix_acl_access` and `system.posix_acl_default` extended
+    /// attributes.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Linux, when the `acl` feature of this crate is enabled.
+    pub fn set_unpack_acls(&mut self, unpack_acls: bool) {
+        self.inner.unpack_acls = unpack_acls;
+    }
+
     /// Indicate whether extended permissions (like suid on Unix) are preserved
     /// when unpacking this entry.
     ///
@@ -154,8 +407,49 @@ impl<R: Read> Archive<R> {
     }
 
     /// Indicate whether files and symlinks should be overwritten on extraction.
+    ///
+    /// This is a shorthand for `set_overwrite_policy` with
+    /// `OverwritePolicy::Unlink` if `overwrite` is true and
+    /// `OverwritePolicy::KeepOld` otherwise.
     pub fn set_overwrite(&mut self, overwrite: bool) {
-        self.inner.overwrite = overwrite;
+        self.inner.overwrite = if overwrite {
+            OverwritePolicy::Unlink
+        } else {
+            OverwritePolicy::KeepOld
+        };
+    }
+
+    /// Configure what happens when an entry is unpacked to a path which
+    /// already exists.
+    ///
+    /// This is `OverwritePolicy::Unlink` by default.
+    pub fn set_overwrite_policy(&mut self, policy: OverwritePolicy) {
+        self.inner.overwrite = policy;
+    }
+
+    /// Indicate whether regular files are unpacked atomically.
+    ///
+    /// When enabled the contents of each file are written to a temporary
+    /// file in the destination directory, which is renamed over the final
+    /// path only once its contents and metadata are complete. Readers never
+    /// observe a partially written file, and an interrupted unpack leaves any
+    /// previous file in place.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_atomic(&mut self, atomic: bool) {
+        self.inner.atomic = atomic;
+    }
+
+    /// Configure whether unpacking this archive syncs the unpacked files, and
+    /// the directories containing them, to stable storage.
+    ///
+    /// With `Durability::SyncAll` the directories are synced once all
+    /// entries have been unpacked, so a directory containing many files is
+    /// only synced a single time.
+    ///
+    /// This is `Durability::Unsynced` by default.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.inner.durability = durability;
     }
 
     /// Indicate whether access time information is preserved when unpacking
@@ -166,6 +460,19 @@ impl<R: Read> Archive<R> {
         self.inner.preserve_mtime = preserve;
     }
 
+    /// Indicate whether the access times recorded in this archive are
+    /// restored when unpacking it.
+    ///
+    /// When enabled the access time recorded for each entry, in pax
+    /// extensions or a GNU header, is restored, falling back to the
+    /// modification time of entries which don't record one. Otherwise the
+    /// access time is set to the modification time if that is preserved.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_preserve_atime(&mut self, preserve: bool) {
+        self.inner.preserve_atime = preserve;
+    }
+
     /// Ignore zeroed headers, which would otherwise indicate to the archive that it has no more
     /// entries.
     ///
@@ -175,6 +482,62 @@ impl<R: Read> Archive<R> {
     }
 }
 
+#[cfg(any(
+    feature = "bzip2",
+    feature = "flate2",
+    feature = "ruzstd",
+    feature = "xz2",
+    feature = "zstd"
+))]
+impl<R: Read> Archive<Decompressor<R>> {
+    /// Create a new archive reading from `obj`, which is transparently
+    /// decompressed according to the compression format detected from its
+    /// first bytes.
+    ///
+    /// Decoders are provided by the `flate2` (gzip), `bzip2`, `xz2` and `zstd`
+    /// features of this crate, or `ruzstd` for a pure-Rust zstd decoder.
+    /// Archives which aren't compressed are read as they are.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the first bytes fails, or if the archive is
+    /// compressed in a format whose decoder isn't enabled.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::open_auto(File::open("foo.tar.gz").unwrap()).unwrap();
+    /// println!("compressed with {:?}", ar.compression());
+    /// ar.unpack("foo").unwrap();
+    /// ```
+    pub fn open_auto(obj: R) -> io::Result<Archive<Decompressor<R>>> {
+        Ok(Archive::new(Decompressor::new(obj)?))
+    }
+
+    /// Returns the compression format which was detected for this archive.
+    pub fn compression(&self) -> Compression {
+        self.inner.obj.borrow().compression()
+    }
+}
+
+#[cfg(any(
+    feature = "bzip2",
+    feature = "flate2",
+    feature = "ruzstd",
+    feature = "xz2",
+    feature = "zstd"
+))]
+impl Archive<Decompressor<std::fs::File>> {
+    /// Opens the archive file at `path`, which is transparently decompressed
+    /// like with [`Archive::open_auto`].
+    pub fn open_path<P: AsRef<Path>>(path: P) -> io::Result<Archive<Decompressor<std::fs::File>>> {
+        Archive::open_auto(std::fs::File::open(path)?)
+    }
+}
+
 impl<R: Seek + Read> Archive<R> {
     /// Construct an iterator over the entries in this archive for a seekable
     /// reader. Seek will be used to efficiently skip over file contents.
@@ -191,6 +554,261 @@ impl<R: Seek + Read> Archive<R> {
             _ignored: marker::PhantomData,
         })
     }
+
+    /// Construct an iterator over the entries in this archive, starting with
+    /// the header at `offset`.
+    ///
+    /// Offsets are relative to the start of the archive, which is where the
+    /// reader was positioned when this archive was created, and match those
+    /// returned by `Entry::raw_header_position`. This allows resuming the
+    /// iteration at a previously seen entry, iterating over the archive again
+    /// from `offset` 0 once it has been read, or reading an archive embedded
+    /// at some offset in a larger file by seeking to it before creating the
+    /// `Archive`.
+    ///
+    /// Seek is used to efficiently skip over file contents, as with
+    /// [`Archive::entries_with_seek`]. Global pax headers before `offset` are
+    /// not taken into account.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if `offset` isn't a multiple
+    /// of 512, as headers are always aligned to blocks.
+    pub fn entries_from(&mut self, offset: u64) -> io::Result<Entries<'_, R>> {
+        let me: &mut Archive<dyn SeekRead> = self;
+        me.seek_to(offset)?;
+        let me: &Archive<dyn Read> = self;
+        let me_seekable: &Archive<dyn SeekRead> = self;
+        Ok(Entries {
+            fields: me.entries_at(Some(me_seekable), offset),
+            _ignored: marker::PhantomData,
+        })
+    }
+
+    /// Reads through all of the entries of this archive, returning the
+    /// position of the blocks of zeros which end it, or of the end of the
+    /// reader if they are missing.
+    pub(crate) fn end_position(&mut self) -> io::Result<u64> {
+        let mut entries = self.entries_with_seek()?;
+        for entry in &mut entries {
+            entry?;
+        }
+        Ok(entries.next_header_position())
+    }
+
+    /// Loads an index of this archive which was persisted with
+    /// [`ArchiveIndex::write_to`], checking that it matches the archive.
+    ///
+    /// Every entry of the index is checked against the header at its
+    /// position, so an index of a different or since modified archive is
+    /// rejected with an error. The position of the underlying reader is left
+    /// unchanged.
+    pub fn load_index<I: Read>(&mut self, index: I) -> io::Result<ArchiveIndex> {
+        let index = ArchiveIndex::read_from(index)?;
+        let me: &mut Archive<dyn SeekRead> = self;
+        me.with_base(|reader, base| index.verify(reader, base))?;
+        Ok(index)
+    }
+
+    /// Loads the index embedded in this archive by [`ArchiveIndex::embed`],
+    /// checking that it matches the archive.
+    ///
+    /// Returns `None` if the archive has no embedded index. The position of
+    /// the underlying reader is left unchanged.
+    pub fn load_embedded_index(&mut self) -> io::Result<Option<ArchiveIndex>> {
+        let me: &mut Archive<dyn SeekRead> = self;
+        me.with_base(|reader, base| ArchiveIndex::find_embedded(reader, base))
+    }
+}
+
+impl<R: Read + Seek + Send> Archive<R> {
+    /// Unpacks the contents tarball into the specified `dst`, writing regular
+    /// files from several threads at the same time.
+    ///
+    /// This behaves like [`Archive::unpack`], including its protection
+    /// against writing outside of `dst`, but is faster for archives of many
+    /// small files, where most of the time is spent creating files rather
+    /// than reading the archive. The archive is first indexed, then the
+    /// parent directories of all regular files are created, and the files are
+    /// written by `threads` threads, or by as many threads as there are CPUs
+    /// if `threads` is 0. Links, other special files and regular files whose
+    /// path is also used by other entries are then unpacked in the order in
+    /// which they appear in the archive, and directories are unpacked last.
+    ///
+    /// Reads of the archive are still serialized; only the rest of the work
+    /// is done in parallel. The archive must not have been read from yet.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::Archive;
+    ///
+    /// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+    /// ar.unpack_parallel("foo", 0).unwrap();
+    /// ```
+    pub fn unpack_parallel<P: AsRef<Path>>(&mut self, dst: P, threads: usize) -> io::Result<()> {
+        let me: &mut Archive<dyn SeekRead + Send> = self;
+        me._unpack_parallel(dst.as_ref(), threads)
+    }
+}
+
+impl Archive<dyn SeekRead + Send + '_> {
+    fn _unpack_parallel(&mut self, dst: &Path, threads: usize) -> io::Result<()> {
+        if self.inner.pos.get() != 0 {
+            return Err(other(
+                "cannot call unpack_parallel unless archive is at position 0",
+            ));
+        }
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let options = UnpackOptions::new(&self.inner);
+        let reader = self.inner.obj.get_mut();
+        let base = reader.stream_position()?;
+        let mut ar = SharedArchive::new(LockedReader {
+            reader: Mutex::new(reader),
+            base,
+        });
+        ar.set_ignore_zeros(self.inner.ignore_zeros);
+        let entries = ar
+            .entries()
+            .map_err(|e| TarError::new("failed to iterate over archive", e))?;
+
+        let mut target = FsTarget::new();
+        let mut unpacker = Unpacker::new(&mut target, dst, options.durability)?;
+        let dst = unpacker.dst.clone();
+
+        // Find where every entry goes. Regular files are only written in
+        // parallel if no other entry but directories uses their path or the
+        // path of one of their parents, so that the order in which entries
+        // are unpacked can't make a difference.
+        let mut directories = Vec::new();
+        let mut others = Vec::new();
+        let mut paths = HashMap::new();
+        for entry in entries {
+            let file_dst = options.fields(&entry).unpack_in_dst(&dst, None)?;
+            if entry.header().entry_type() == crate::EntryType::Directory {
+                directories.push(entry);
+            } else {
+                if let Some(ref file_dst) = file_dst {
+                    *paths.entry(file_dst.clone()).or_insert(0) += 1;
+                }
+                others.push((entry, file_dst));
+            }
+        }
+        let (files, others): (Vec<_>, Vec<_>) =
+            others.into_iter().partition(|(entry, file_dst)| {
+                let kind = entry.header().entry_type();
+                let file_dst = match *file_dst {
+                    Some(ref file_dst) if *file_dst != dst => file_dst,
+                    _ => return false,
+                };
+                (kind.is_file() || kind.is_gnu_sparse())
+                    && !entry.path_bytes().ends_with(b"/")
+                    && paths[file_dst] == 1
+                    && file_dst
+                        .ancestors()
+                        .skip(1)
+                        .take_while(|p| *p != dst)
+                        .all(|p| !paths.contains_key(p))
+            });
+
+        let mut created = BTreeSet::new();
+        for (entry, file_dst) in &files {
+            let parent = file_dst.as_deref().and_then(Path::parent).unwrap();
+            if created.insert(parent) {
+                options
+                    .fields(entry)
+                    .ensure_dir_created(unpacker.target, &dst, parent)
+                    .map_err(|e| {
+                        TarError::new(format!("failed to create `{}`", parent.display()), e)
+                    })?;
+            }
+        }
+
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let results = thread::scope(|s| {
+            let workers = (0..cmp::min(threads, files.len()))
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut target = FsTarget::new();
+                        let mut unpacker = Unpacker::new(&mut target, &dst, options.durability)?;
+                        while !failed.load(Ordering::Relaxed) {
+                            let (entry, _) = match files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                                Some(file) => file,
+                                None => break,
+                            };
+                            if let Err(e) = unpacker.unpack(&mut options.fields(entry), None) {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(e);
+                            }
+                        }
+                        unpacker.finish()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect::<Vec<_>>()
+        });
+        let mut warnings = Vec::new();
+        for result in results {
+            warnings.extend(result?);
+        }
+
+        for (entry, _) in others {
+            unpacker.unpack(&mut options.fields(&entry), None)?;
+        }
+        for dir in directories {
+            unpacker.unpack(&mut options.fields(&dir), None)?;
+        }
+        warnings.extend(unpacker.finish()?);
+        self.inner.xattr_warnings.extend(warnings);
+        Ok(())
+    }
+}
+
+impl Archive<dyn SeekRead + '_> {
+    /// Returns the offset in the underlying reader at which the archive
+    /// starts.
+    fn base(&mut self) -> io::Result<u64> {
+        let pos = self.inner.pos.get();
+        self.inner
+            .obj
+            .get_mut()
+            .stream_position()?
+            .checked_sub(pos)
+            .ok_or_else(|| other("archive position is out of sync with its reader"))
+    }
+
+    /// Runs `f` with the underlying reader and the offset in it at which the
+    /// archive starts, restoring the position of the reader afterwards.
+    fn with_base<T>(
+        &mut self,
+        f: impl FnOnce(&mut dyn SeekRead, u64) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let base = self.base()?;
+        let reader = self.inner.obj.get_mut();
+        let saved = base + self.inner.pos.get();
+        let ret = f(reader, base);
+        reader.seek(SeekFrom::Start(saved))?;
+        ret
+    }
+
+    /// Moves the reader to `offset` from the start of the archive.
+    fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        if offset & 511 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("offset {} is not at a header boundary", offset),
+            ));
+        }
+        self.inner.seek_to(offset)
+    }
 }
 
 impl Archive<dyn Read + '_> {
@@ -204,18 +822,219 @@ impl Archive<dyn Read + '_> {
                  position 0",
             ));
         }
-        Ok(EntriesFields {
+        Ok(self.entries_at(seekable_archive, 0))
+    }
+
+    fn entries_at<'a>(
+        &'a self,
+        seekable_archive: Option<&'a Archive<dyn SeekRead + 'a>>,
+        next: u64,
+    ) -> EntriesFields<'a> {
+        EntriesFields {
             archive: self,
             seekable_archive,
             done: false,
-            next: 0,
+            next,
             raw: false,
+            global_pax: None,
+        }
+    }
+
+    /// Calls `f` with every entry of the archive until it returns `false`.
+    ///
+    /// Any directory entries are delayed until the end (they will be created
+    /// if needed by descendants), to ensure that directory permissions do not
+    /// interfer with descendant extraction.
+    fn for_each_entry_dirs_last(
+        &self,
+        mut f: impl FnMut(EntryFields<'_>) -> io::Result<bool>,
+    ) -> io::Result<()> {
+        let mut directories = Vec::new();
+        for entry in self._entries(None)? {
+            let file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+            if file.header().entry_type() == crate::EntryType::Directory {
+                directories.push(file);
+            } else if !f(EntryFields::from(file))? {
+                return Ok(());
+            }
+        }
+        for dir in directories {
+            if !f(EntryFields::from(dir))? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn _unpack<T: UnpackTarget + ?Sized>(&mut self, target: &mut T, dst: &Path) -> io::Result<()> {
+        self._unpack_with(target, dst, |unpacker, mut fields| {
+            unpacker.unpack(&mut fields, None).map(drop)
         })
     }
 
-    fn _unpack(&mut self, dst: &Path) -> io::Result<()> {
-        if dst.symlink_metadata().is_err() {
-            fs::create_dir_all(&dst)
+    /// Unpacks every entry with `f`, in the order of
+    /// `for_each_entry_dirs_last`.
+    fn _unpack_with<T: UnpackTarget + ?Sized>(
+        &mut self,
+        target: &mut T,
+        dst: &Path,
+        mut f: impl FnMut(&mut Unpacker<'_, T>, EntryFields<'_>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut unpacker = Unpacker::new(target, dst, self.inner.durability)?;
+        self.for_each_entry_dirs_last(|fields| f(&mut unpacker, fields).map(|()| true))?;
+        let warnings = unpacker.finish()?;
+        self.inner.xattr_warnings.extend(warnings);
+        Ok(())
+    }
+}
+
+impl Archive<dyn Read + '_> {
+    fn _unpack_plan(&mut self, dst: &Path) -> io::Result<Vec<PlannedEntry>> {
+        let mut planner = Planner::new(dst);
+
+        // Directories are planned last, just like they're unpacked last.
+        self.for_each_entry_dirs_last(|fields| planner.plan(&fields))?;
+        Ok(planner.into_plan())
+    }
+}
+
+/// The settings of an archive which apply to unpacking its entries, so that
+/// they can be shared with the threads of `Archive::unpack_parallel` and
+/// with archives read asynchronously.
+pub(crate) struct UnpackOptions {
+    pub mask: u32,
+    pub unpack_xattrs: bool,
+    pub xattr_filter: XattrFilter,
+    pub xattr_best_effort: bool,
+    pub unpack_acls: bool,
+    pub preserve_permissions: bool,
+    pub preserve_ownerships: bool,
+    pub preserve_mtime: bool,
+    pub preserve_atime: bool,
+    pub overwrite: OverwritePolicy,
+    pub atomic: bool,
+    pub durability: Durability,
+}
+
+impl Default for UnpackOptions {
+    /// Returns the settings of a new `Archive`.
+    fn default() -> UnpackOptions {
+        UnpackOptions::new(&Archive::new(io::empty()).inner)
+    }
+}
+
+impl UnpackOptions {
+    fn new<R: ?Sized>(inner: &ArchiveInner<R>) -> UnpackOptions {
+        UnpackOptions {
+            mask: inner.mask,
+            unpack_xattrs: inner.unpack_xattrs,
+            xattr_filter: inner.xattr_filter.clone(),
+            xattr_best_effort: inner.xattr_best_effort,
+            unpack_acls: inner.unpack_acls,
+            preserve_permissions: inner.preserve_permissions,
+            preserve_ownerships: inner.preserve_ownerships,
+            preserve_mtime: inner.preserve_mtime,
+            preserve_atime: inner.preserve_atime,
+            overwrite: inner.overwrite,
+            atomic: inner.atomic,
+            durability: inner.durability,
+        }
+    }
+
+    /// Creates the fields of an entry to unpack `entry` with these settings,
+    /// reading its contents through its own handle.
+    fn fields<'e, S: ReadAt + ?Sized + 'e>(&self, entry: &'e SharedEntry<S>) -> EntryFields<'e> {
+        let data = entry
+            .meta()
+            .regions
+            .iter()
+            .map(|region| match region.archive_pos {
+                Some(_) => {
+                    let mut reader = entry.clone();
+                    reader.set_position(region.offset);
+                    let reader: Box<dyn Read + 'e> = Box::new(reader);
+                    EntryIo::Reader(reader.take(region.len))
+                }
+                None => EntryIo::Pad(io::repeat(0).take(region.len)),
+            })
+            .collect();
+        self.detached_fields(entry.meta(), data)
+    }
+
+    /// Creates the fields of an entry described by `meta` to unpack it with
+    /// these settings, reading its contents from `data`.
+    pub(crate) fn detached_fields<'e>(
+        &self,
+        meta: &EntryMeta,
+        data: Vec<EntryIo<'e>>,
+    ) -> EntryFields<'e> {
+        EntryFields {
+            long_pathname: Some(meta.path.clone()),
+            long_linkname: meta.link_name.clone(),
+            pax_extensions: None,
+            pax: meta.pax.clone(),
+            mask: self.mask,
+            header: meta.header.clone(),
+            size: meta.size,
+            header_pos: meta.header_pos,
+            file_pos: meta.file_pos,
+            data,
+            regions: meta.regions.clone(),
+            read_pos: 0,
+            stored_size: meta.size,
+            sparse: meta.sparse,
+            archive: None,
+            seekable_archive: None,
+            unpack_xattrs: self.unpack_xattrs,
+            xattr_filter: self.xattr_filter.clone(),
+            xattr_best_effort: self.xattr_best_effort,
+            xattr_warnings: Vec::new(),
+            unpack_acls: self.unpack_acls,
+            preserve_permissions: self.preserve_permissions,
+            preserve_mtime: self.preserve_mtime,
+            preserve_atime: self.preserve_atime,
+            overwrite: self.overwrite,
+            atomic: self.atomic,
+            durability: self.durability,
+            preserve_ownerships: self.preserve_ownerships,
+        }
+    }
+}
+
+/// Shares a seekable reader between threads through `ReadAt`, seeking it
+/// under a lock for every read.
+struct LockedReader<'r> {
+    reader: Mutex<&'r mut (dyn SeekRead + Send)>,
+    base: u64,
+}
+
+impl ReadAt for LockedReader<'_> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let mut reader = self
+            .reader
+            .lock()
+            .map_err(|_| other("archive reader was poisoned"))?;
+        reader.seek(SeekFrom::Start(self.base + offset))?;
+        reader.read(buf)
+    }
+}
+
+/// The shared state of unpacking all the entries of an archive into a
+/// destination directory.
+struct Unpacker<'t, T: ?Sized> {
+    target: &'t mut T,
+    dst: PathBuf,
+    durability: Durability,
+    dirty_dirs: BTreeSet<PathBuf>,
+    xattr_warnings: Vec<XattrWarning>,
+}
+
+impl<'t, T: UnpackTarget + ?Sized> Unpacker<'t, T> {
+    /// Creates the destination directory `dst` if needed.
+    fn new(target: &'t mut T, dst: &Path, durability: Durability) -> io::Result<Unpacker<'t, T>> {
+        if target.symlink_metadata(dst).is_err() {
+            target
+                .create_dir_all(dst)
                 .map_err(|e| TarError::new(format!("failed to create `{}`", dst.display()), e))?;
         }
 
@@ -224,25 +1043,69 @@ impl Archive<dyn Read + '_> {
         // extended-length path with a 32,767 character limit. Otherwise all
         // unpacked paths over 260 characters will fail on creation with a
         // NotFound exception.
-        let dst = &dst.canonicalize().unwrap_or(dst.to_path_buf());
+        let dst = target.canonicalize(dst).unwrap_or(dst.to_path_buf());
 
-        // Delay any directory entries until the end (they will be created if needed by
-        // descendants), to ensure that directory permissions do not interfer with descendant
-        // extraction.
-        let mut directories = Vec::new();
-        for entry in self._entries(None)? {
-            let mut file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
-            if file.header().entry_type() == crate::EntryType::Directory {
-                directories.push(file);
-            } else {
-                file.unpack_in(dst)?;
+        Ok(Unpacker {
+            target,
+            dst,
+            durability,
+            dirty_dirs: BTreeSet::new(),
+            xattr_warnings: Vec::new(),
+        })
+    }
+
+    /// Unpacks an entry inside of the destination, optionally at `path`
+    /// instead of its own path, returning where it was unpacked to.
+    fn unpack(
+        &mut self,
+        fields: &mut EntryFields<'_>,
+        path: Option<&Path>,
+    ) -> io::Result<Option<(PathBuf, Outcome<T::File>)>> {
+        let file_dst = match fields.unpack_in_dst(&self.dst, path)? {
+            Some(file_dst) => file_dst,
+            None => return Ok(None),
+        };
+        // Entries with an effectively empty path aren't unpacked at all
+        if self.dst == file_dst {
+            return Ok(None);
+        }
+        let unpacked = fields.unpack_in_at(self.target, &self.dst, &file_dst);
+        self.xattr_warnings.append(&mut fields.xattr_warnings);
+        let unpacked = unpacked?;
+        if self.durability == Durability::SyncAll {
+            for dir in file_dst.ancestors().skip(1) {
+                if !self.dirty_dirs.insert(dir.to_path_buf()) || dir == self.dst {
+                    break;
+                }
             }
         }
-        for mut dir in directories {
-            dir.unpack_in(dst)?;
+        Ok(Some((file_dst, unpacked)))
+    }
+
+    /// Syncs all directories which were modified, if requested, returning
+    /// the extended attributes which were skipped.
+    fn finish(self) -> io::Result<Vec<XattrWarning>> {
+        // Sync the deepest directories first so their parents are only
+        // synced once their contents are.
+        for dir in self.dirty_dirs.iter().rev() {
+            entry::sync_dir(self.target, dir)?;
         }
+        Ok(self.xattr_warnings)
+    }
+}
 
-        Ok(())
+fn unpack_visited<V: UnpackVisitor>(
+    unpacker: &mut Unpacker<'_, FsTarget>,
+    entry: Entry<'_, io::Empty>,
+    path: Option<PathBuf>,
+    visitor: &mut V,
+) -> io::Result<()> {
+    let mut fields = EntryFields::from(entry);
+    match unpacker.unpack(&mut fields, path.as_deref())? {
+        Some((file_dst, Outcome::Written(file))) => {
+            visitor.after_unpack(&fields.into_entry::<io::Empty>(), &file_dst, file)
+        }
+        Some((_, Outcome::Kept)) | None => Ok(()),
     }
 }
 
@@ -261,6 +1124,12 @@ impl<'a, R: Read> Entries<'a, R> {
             _ignored: marker::PhantomData,
         }
     }
+
+    /// Returns the position of the header following the last entry returned,
+    /// including any extension blocks of that entry.
+    pub(crate) fn next_header_position(&self) -> u64 {
+        self.fields.next
+    }
 }
 impl<'a, R: Read> Iterator for Entries<'a, R> {
     type Item = io::Result<Entry<'a, R>>;
@@ -273,10 +1142,7 @@ impl<'a, R: Read> Iterator for Entries<'a, R> {
 }
 
 impl<'a> EntriesFields<'a> {
-    fn next_entry_raw(
-        &mut self,
-        pax_extensions: Option<&[u8]>,
-    ) -> io::Result<Option<Entry<'a, io::Empty>>> {
+    fn next_entry_raw(&mut self, pax: Option<&PaxMap>) -> io::Result<Option<Entry<'a, io::Empty>>> {
         let mut header = Header::new_old();
         let mut header_pos = self.next;
         loop {
@@ -304,63 +1170,48 @@ impl<'a> EntriesFields<'a> {
             header_pos = self.next;
         }
 
-        // Make sure the checksum is ok
-        let sum = header.as_bytes()[..148]
-            .iter()
-            .chain(&header.as_bytes()[156..])
-            .fold(0, |a, b| a + (*b as u32))
-            + 8 * 32;
-        let cksum = header.cksum()?;
-        if sum != cksum {
-            return Err(other("archive header checksum mismatch"));
-        }
-
-        let mut pax_size: Option<u64> = None;
-        if let Some(pax_extensions_ref) = &pax_extensions {
-            pax_size = pax_extensions_value(pax_extensions_ref, PAX_SIZE);
-
-            if let Some(pax_uid) = pax_extensions_value(pax_extensions_ref, PAX_UID) {
-                header.set_uid(pax_uid);
-            }
-
-            if let Some(pax_gid) = pax_extensions_value(pax_extensions_ref, PAX_GID) {
-                header.set_gid(pax_gid);
-            }
-        }
-
+        let size = check_header(&mut header, pax)?;
         let file_pos = self.next;
-        let mut size = header.entry_size()?;
-        if size == 0 {
-            if let Some(pax_size) = pax_size {
-                size = pax_size;
-            }
-        }
         let ret = EntryFields {
             size: size,
             header_pos: header_pos,
             file_pos: file_pos,
             data: vec![EntryIo::Data((&self.archive.inner).take(size))],
+            regions: if size > 0 {
+                vec![Region {
+                    offset: 0,
+                    len: size,
+                    archive_pos: Some(file_pos),
+                }]
+            } else {
+                Vec::new()
+            },
+            read_pos: 0,
+            stored_size: size,
+            sparse: false,
+            archive: Some(&self.archive.inner),
+            seekable_archive: self.seekable_archive.map(|a| &a.inner),
             header: header,
             long_pathname: None,
             long_linkname: None,
             pax_extensions: None,
+            pax: None,
             mask: self.archive.inner.mask,
             unpack_xattrs: self.archive.inner.unpack_xattrs,
+            xattr_filter: self.archive.inner.xattr_filter.clone(),
+            xattr_best_effort: self.archive.inner.xattr_best_effort,
+            xattr_warnings: Vec::new(),
+            unpack_acls: self.archive.inner.unpack_acls,
             preserve_permissions: self.archive.inner.preserve_permissions,
             preserve_mtime: self.archive.inner.preserve_mtime,
+            preserve_atime: self.archive.inner.preserve_atime,
             overwrite: self.archive.inner.overwrite,
+            atomic: self.archive.inner.atomic,
+            durability: self.archive.inner.durability,
             preserve_ownerships: self.archive.inner.preserve_ownerships,
         };
 
-        // Store where the next entry is, rounding up by 512 bytes (the size of
-        // a header);
-        let size = size
-            .checked_add(511)
-            .ok_or_else(|| other("size overflow"))?;
-        self.next = self
-            .next
-            .checked_add(size & !(512 - 1))
-            .ok_or_else(|| other("size overflow"))?;
+        self.next = next_header_pos(self.next, size)?;
 
         Ok(Some(ret.into_entry()))
     }
@@ -370,167 +1221,89 @@ impl<'a> EntriesFields<'a> {
             return self.next_entry_raw(None);
         }
 
-        let mut gnu_longname = None;
-        let mut gnu_longlink = None;
-        let mut pax_extensions = None;
-        let mut processed = 0;
+        let mut members = ExtensionMembers::new(self.global_pax.clone());
         loop {
-            processed += 1;
-            let entry = match self.next_entry_raw(pax_extensions.as_deref())? {
+            let entry = match self.next_entry_raw(members.pax.as_ref())? {
                 Some(entry) => entry,
-                None if processed > 1 => {
-                    return Err(other(
-                        "members found describing a future member \
-                         but no future member found",
-                    ));
-                }
-                None => return Ok(None),
+                None => return members.at_eof().map(|()| None),
             };
 
-            let is_recognized_header =
-                entry.header().as_gnu().is_some() || entry.header().as_ustar().is_some();
-
-            if is_recognized_header && entry.header().entry_type().is_gnu_longname() {
-                if gnu_longname.is_some() {
-                    return Err(other(
-                        "two long name entries describing \
-                         the same member",
-                    ));
-                }
-                gnu_longname = Some(EntryFields::from(entry).read_all()?);
-                continue;
-            }
-
-            if is_recognized_header && entry.header().entry_type().is_gnu_longlink() {
-                if gnu_longlink.is_some() {
-                    return Err(other(
-                        "two long name entries describing \
-                         the same member",
-                    ));
+            match Extension::of(entry.header()) {
+                Some(Extension::PaxGlobal) => {
+                    // Global headers are still handed out as entries, so
+                    // their data is kept around to be read again.
+                    let mut fields = EntryFields::from(entry);
+                    let data = fields.read_all()?;
+                    self.global_pax
+                        .get_or_insert_with(PaxMap::new)
+                        .update(&data);
+                    fields.data = vec![EntryIo::Buffer(io::Cursor::new(data.clone()))];
+                    fields.read_pos = 0;
+                    fields.pax_extensions = Some(data);
+                    return Ok(Some(fields.into_entry()));
                 }
-                gnu_longlink = Some(EntryFields::from(entry).read_all()?);
-                continue;
-            }
-
-            if is_recognized_header && entry.header().entry_type().is_pax_local_extensions() {
-                if pax_extensions.is_some() {
-                    return Err(other(
-                        "two pax extensions entries describing \
-                         the same member",
-                    ));
+                Some(extension) => {
+                    let data = EntryFields::from(entry).read_all()?;
+                    members.add(extension, data)?;
+                    continue;
                 }
-                pax_extensions = Some(EntryFields::from(entry).read_all()?);
-                continue;
+                None => {}
             }
 
             let mut fields = EntryFields::from(entry);
-            fields.long_pathname = gnu_longname;
-            fields.long_linkname = gnu_longlink;
-            fields.pax_extensions = pax_extensions;
+            fields.long_pathname = members.long_pathname;
+            fields.long_linkname = members.long_linkname;
+            fields.pax_extensions = members.pax_extensions;
+            fields.pax = members.pax;
             self.parse_sparse_header(&mut fields)?;
+            self.parse_pax_sparse(&mut fields)?;
             return Ok(Some(fields.into_entry()));
         }
     }
 
     fn parse_sparse_header(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
-        if !entry.header.entry_type().is_gnu_sparse() {
-            return Ok(());
-        }
-        let gnu = match entry.header.as_gnu() {
-            Some(gnu) => gnu,
-            None => return Err(other("sparse entry type listed but not GNU header")),
+        let mut sparse = match GnuSparse::new(&entry.header, entry.size)? {
+            Some(sparse) => sparse,
+            None => return Ok(()),
         };
-
-        // Sparse files are represented internally as a list of blocks that are
-        // read. Blocks are either a bunch of 0's or they're data from the
-        // underlying archive.
-        //
-        // Blocks of a sparse file are described by the `GnuSparseHeader`
-        // structure, some of which are contained in `GnuHeader` but some of
-        // which may also be contained after the first header in further
-        // headers.
-        //
-        // We read off all the blocks here and use the `add_block` function to
-        // incrementally add them to the list of I/O block (in `entry.data`).
-        // The `add_block` function also validates that each chunk comes after
-        // the previous, we don't overrun the end of the file, and each block is
-        // aligned to a 512-byte boundary in the archive itself.
-        //
-        // At the end we verify that the sparse file size (`Header::size`) is
-        // the same as the current offset (described by the list of blocks) as
-        // well as the amount of data read equals the size of the entry
-        // (`Header::entry_size`).
-        entry.data.truncate(0);
-
-        let mut cur = 0;
-        let mut remaining = entry.size;
-        {
-            let data = &mut entry.data;
-            let reader = &self.archive.inner;
-            let size = entry.size;
-            let mut add_block = |block: &GnuSparseHeader| -> io::Result<_> {
-                if block.is_empty() {
-                    return Ok(());
-                }
-                let off = block.offset()?;
-                let len = block.length()?;
-                if len != 0 && (size - remaining) % 512 != 0 {
-                    return Err(other(
-                        "previous block in sparse file was not \
-                         aligned to 512-byte boundary",
-                    ));
-                } else if off < cur {
-                    return Err(other(
-                        "out of order or overlapping sparse \
-                         blocks",
-                    ));
-                } else if cur < off {
-                    let block = io::repeat(0).take(off - cur);
-                    data.push(EntryIo::Pad(block));
-                }
-                cur = off
-                    .checked_add(len)
-                    .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
-                remaining = remaining.checked_sub(len).ok_or_else(|| {
-                    other(
-                        "sparse file consumed more data than the header \
-                         listed",
-                    )
-                })?;
-                data.push(EntryIo::Data(reader.take(len)));
-                Ok(())
-            };
-            for block in gnu.sparse.iter() {
-                add_block(block)?
-            }
-            if gnu.is_extended() {
-                let mut ext = GnuExtSparseHeader::new();
-                ext.isextended[0] = 1;
-                while ext.is_extended() {
-                    if !try_read_all(&mut &self.archive.inner, ext.as_mut_bytes())? {
-                        return Err(other("failed to read extension"));
-                    }
-
-                    self.next += 512;
-                    for block in ext.sparse.iter() {
-                        add_block(block)?;
-                    }
-                }
+        let mut ext = GnuExtSparseHeader::new();
+        while sparse.is_extended() {
+            if !try_read_all(&mut &self.archive.inner, ext.as_mut_bytes())? {
+                return Err(other("failed to read extension"));
             }
+            self.next += 512;
+            sparse.add_extension(&ext)?;
         }
-        if cur != gnu.real_size()? {
-            return Err(other(
-                "mismatch in sparse file chunks and \
-                 size in header",
-            ));
-        }
-        entry.size = cur;
-        if remaining > 0 {
-            return Err(other(
-                "mismatch in sparse file chunks and \
-                 entry size in header",
-            ));
+        let (regions, real_size) = sparse.finish(self.archive.inner.pos.get())?;
+        entry.data = region_data(&self.archive.inner, &regions);
+        entry.regions = regions;
+        entry.size = real_size;
+        entry.sparse = true;
+        Ok(())
+    }
+
+    fn parse_pax_sparse(&mut self, entry: &mut EntryFields<'a>) -> io::Result<()> {
+        let sparse = PaxSparse::new(
+            &entry.header,
+            entry.pax.as_ref(),
+            entry.pax_extensions.as_deref(),
+        )?;
+        let mut sparse = match sparse {
+            Some(sparse) => sparse,
+            None => return Ok(()),
+        };
+        if sparse.map.is_none() {
+            sparse.map = Some(read_sparse_map(entry)?);
         }
+
+        // Whatever data of the entry is left is the data of the blocks.
+        let stored = entry.size - entry.read_pos;
+        let (regions, real_size) = sparse.finish(self.archive.inner.pos.get(), stored)?;
+        entry.data = region_data(&self.archive.inner, &regions);
+        entry.regions = regions;
+        entry.read_pos = 0;
+        entry.size = real_size;
+        entry.sparse = true;
         Ok(())
     }
 
@@ -585,14 +1358,528 @@ impl<'a, R: ?Sized + Read> Read for &'a ArchiveInner<R> {
     }
 }
 
+impl<R: ?Sized + Seek> ArchiveInner<R> {
+    /// Moves the reader to `pos` from the start of the archive.
+    pub fn seek_to(&self, pos: u64) -> io::Result<()> {
+        let delta = i64::try_from(pos)
+            .ok()
+            .and_then(|pos| pos.checked_sub(self.pos.get() as i64))
+            .ok_or_else(|| other("seek position out of bounds"))?;
+        let mut me = self;
+        me.seek(SeekFrom::Current(delta)).map(|_| ())
+    }
+}
+
 impl<'a, R: ?Sized + Seek> Seek for &'a ArchiveInner<R> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        let pos = self.obj.borrow_mut().seek(pos)?;
+        // Positions are relative to the start of the archive, which needn't
+        // be the start of the underlying reader.
+        let mut obj = self.obj.borrow_mut();
+        let moved = match pos {
+            SeekFrom::Current(delta) => {
+                obj.seek(pos)?;
+                delta
+            }
+            _ => {
+                let old = obj.stream_position()?;
+                obj.seek(pos)? as i64 - old as i64
+            }
+        };
+        let pos = (self.pos.get() as i64 + moved) as u64;
         self.pos.set(pos);
         Ok(pos)
     }
 }
 
+/// Reads the sparse map at the start of the data of an entry in the pax
+/// format 1.0 of GNU tar, returning the offset and length of each block.
+fn read_sparse_map(entry: &mut EntryFields<'_>) -> io::Result<Vec<(u64, u64)>> {
+    let mut parser = SparseMapParser::new();
+    let mut block = [0; 512];
+    loop {
+        entry
+            .read_exact(&mut block)
+            .map_err(|_| SparseMapParser::invalid())?;
+        if let Some(map) = parser.parse(&block)? {
+            return Ok(map);
+        }
+    }
+}
+
+/// Creates the readers of the contents of an entry laid out as `regions`,
+/// which are read in order from the current position of `archive`.
+fn region_data<'a>(
+    archive: &'a ArchiveInner<dyn Read + 'a>,
+    regions: &[Region],
+) -> Vec<EntryIo<'a>> {
+    regions
+        .iter()
+        .map(|region| match region.archive_pos {
+            Some(_) => EntryIo::Data(archive.take(region.len)),
+            None => EntryIo::Pad(io::repeat(0).take(region.len)),
+        })
+        .collect()
+}
+
+// The remainder of this module is the parsing of headers which doesn't
+// depend on how the archive is read, shared with the async reader.
+
+/// Verifies the checksum of `header` and applies the pax records `pax`
+/// describing it, returning the size of the data following it.
+pub(crate) fn check_header(header: &mut Header, pax: Option<&PaxMap>) -> io::Result<u64> {
+    // Make sure the checksum is ok
+    if header.calculate_cksum() != header.cksum()? {
+        return Err(other("archive header checksum mismatch"));
+    }
+
+    let mut pax_size: Option<u64> = None;
+    if let Some(pax) = pax {
+        pax_size = pax.size();
+
+        if let Some(pax_uid) = pax.uid() {
+            header.set_uid(pax_uid);
+        }
+
+        if let Some(pax_gid) = pax.gid() {
+            header.set_gid(pax_gid);
+        }
+    }
+
+    let mut size = header.entry_size()?;
+    if size == 0 {
+        if let Some(pax_size) = pax_size {
+            size = pax_size;
+        }
+    }
+    Ok(size)
+}
+
+/// Returns where the header following an entry whose data of `size` bytes
+/// starts at `file_pos` is, rounding up by 512 bytes (the size of a header).
+pub(crate) fn next_header_pos(file_pos: u64, size: u64) -> io::Result<u64> {
+    let size = size
+        .checked_add(511)
+        .ok_or_else(|| other("size overflow"))?;
+    file_pos
+        .checked_add(size & !(512 - 1))
+        .ok_or_else(|| other("size overflow"))
+}
+
+/// The kinds of members of an archive which extend the headers of other
+/// members rather than being entries of their own.
+pub(crate) enum Extension {
+    LongName,
+    LongLink,
+    PaxLocal,
+    PaxGlobal,
+}
+
+impl Extension {
+    /// Returns the kind of extension member `header` is the header of, if
+    /// any.
+    pub(crate) fn of(header: &Header) -> Option<Extension> {
+        if header.as_gnu().is_none() && header.as_ustar().is_none() {
+            return None;
+        }
+        let kind = header.entry_type();
+        if kind.is_gnu_longname() {
+            Some(Extension::LongName)
+        } else if kind.is_gnu_longlink() {
+            Some(Extension::LongLink)
+        } else if kind.is_pax_local_extensions() {
+            Some(Extension::PaxLocal)
+        } else if kind.is_pax_global_extensions() {
+            Some(Extension::PaxGlobal)
+        } else {
+            None
+        }
+    }
+}
+
+/// The data of the extension members preceding an entry.
+pub(crate) struct ExtensionMembers {
+    pub long_pathname: Option<Vec<u8>>,
+    pub long_linkname: Option<Vec<u8>>,
+    pub pax_extensions: Option<Vec<u8>>,
+    pub pax: Option<PaxMap>,
+    count: usize,
+}
+
+impl ExtensionMembers {
+    /// Starts collecting the members describing an entry, to which the
+    /// global pax records `global_pax` apply as well.
+    pub(crate) fn new(global_pax: Option<PaxMap>) -> ExtensionMembers {
+        ExtensionMembers {
+            long_pathname: None,
+            long_linkname: None,
+            pax_extensions: None,
+            pax: global_pax,
+            count: 0,
+        }
+    }
+
+    /// Adds the data of a local extension member.
+    pub(crate) fn add(&mut self, extension: Extension, data: Vec<u8>) -> io::Result<()> {
+        self.count += 1;
+        match extension {
+            Extension::LongName | Extension::LongLink => {
+                let slot = match extension {
+                    Extension::LongName => &mut self.long_pathname,
+                    _ => &mut self.long_linkname,
+                };
+                if slot.is_some() {
+                    return Err(other(
+                        "two long name entries describing \
+                         the same member",
+                    ));
+                }
+                *slot = Some(data);
+            }
+            Extension::PaxLocal => {
+                if self.pax_extensions.is_some() {
+                    return Err(other(
+                        "two pax extensions entries describing \
+                         the same member",
+                    ));
+                }
+                self.pax.get_or_insert_with(PaxMap::new).update(&data);
+                self.pax_extensions = Some(data);
+            }
+            Extension::PaxGlobal => unreachable!("global pax headers are entries of their own"),
+        }
+        Ok(())
+    }
+
+    /// Checks that the end of the archive isn't found while members
+    /// describing a future member were seen.
+    pub(crate) fn at_eof(&self) -> io::Result<()> {
+        if self.count > 0 {
+            return Err(other(
+                "members found describing a future member \
+                 but no future member found",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Collects the blocks of a sparse file listed in GNU sparse headers into
+/// the regions of its contents.
+///
+/// Sparse files are represented internally as a list of blocks that are
+/// read. Blocks are either a bunch of 0's or they're data from the underlying
+/// archive.
+///
+/// Blocks of a sparse file are described by the `GnuSparseHeader` structure,
+/// some of which are contained in `GnuHeader` but some of which may also be
+/// contained after the first header in further headers.
+///
+/// Each block is validated to come after the previous, not to overrun the end
+/// of the file, and to be aligned to a 512-byte boundary in the archive
+/// itself. At the end the sparse file size (`Header::size`) is verified to be
+/// the same as the current offset (described by the list of blocks) as well
+/// as the amount of data read to equal the size of the entry
+/// (`Header::entry_size`).
+pub(crate) struct GnuSparse {
+    size: u64,
+    real_size: u64,
+    cur: u64,
+    remaining: u64,
+    extended: bool,
+    regions: Vec<Region>,
+}
+
+impl GnuSparse {
+    /// Starts collecting the blocks of the entry with `header` and `size`
+    /// bytes of data, if it's a GNU sparse file.
+    pub(crate) fn new(header: &Header, size: u64) -> io::Result<Option<GnuSparse>> {
+        if !header.entry_type().is_gnu_sparse() {
+            return Ok(None);
+        }
+        let gnu = match header.as_gnu() {
+            Some(gnu) => gnu,
+            None => return Err(other("sparse entry type listed but not GNU header")),
+        };
+        let mut sparse = GnuSparse {
+            size,
+            real_size: gnu.real_size()?,
+            cur: 0,
+            remaining: size,
+            extended: gnu.is_extended(),
+            regions: Vec::new(),
+        };
+        for block in gnu.sparse.iter() {
+            sparse.add_block(block)?;
+        }
+        Ok(Some(sparse))
+    }
+
+    /// Returns whether an extension header with more blocks follows.
+    pub(crate) fn is_extended(&self) -> bool {
+        self.extended
+    }
+
+    /// Adds the blocks of an extension header.
+    pub(crate) fn add_extension(&mut self, ext: &GnuExtSparseHeader) -> io::Result<()> {
+        for block in ext.sparse.iter() {
+            self.add_block(block)?;
+        }
+        self.extended = ext.is_extended();
+        Ok(())
+    }
+
+    fn add_block(&mut self, block: &GnuSparseHeader) -> io::Result<()> {
+        if block.is_empty() {
+            return Ok(());
+        }
+        let off = block.offset()?;
+        let len = block.length()?;
+        let stored = self.size - self.remaining;
+        if len != 0 && stored % 512 != 0 {
+            return Err(other(
+                "previous block in sparse file was not \
+                 aligned to 512-byte boundary",
+            ));
+        } else if off < self.cur {
+            return Err(other(
+                "out of order or overlapping sparse \
+                 blocks",
+            ));
+        } else if self.cur < off {
+            self.regions.push(Region {
+                offset: self.cur,
+                len: off - self.cur,
+                archive_pos: None,
+            });
+        }
+        if len > 0 {
+            // Relative to the start of the data for now, as extension
+            // headers may still follow.
+            self.regions.push(Region {
+                offset: off,
+                len,
+                archive_pos: Some(stored),
+            });
+        }
+        self.cur = off
+            .checked_add(len)
+            .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
+        self.remaining = self.remaining.checked_sub(len).ok_or_else(|| {
+            other(
+                "sparse file consumed more data than the header \
+                 listed",
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Returns the regions of the file, whose data starts at `data_pos` in
+    /// the archive, along with its size.
+    pub(crate) fn finish(mut self, data_pos: u64) -> io::Result<(Vec<Region>, u64)> {
+        if self.cur != self.real_size {
+            return Err(other(
+                "mismatch in sparse file chunks and \
+                 size in header",
+            ));
+        }
+        if self.remaining > 0 {
+            return Err(other(
+                "mismatch in sparse file chunks and \
+                 entry size in header",
+            ));
+        }
+        for pos in self
+            .regions
+            .iter_mut()
+            .filter_map(|r| r.archive_pos.as_mut())
+        {
+            *pos += data_pos;
+        }
+        Ok((self.regions, self.cur))
+    }
+}
+
+/// The sparse map of a file stored in one of the pax formats of GNU tar.
+///
+/// Formats 0.0 and 0.1 store the map in pax records, as repeated
+/// `GNU.sparse.offset` and `GNU.sparse.numbytes` records or a single
+/// `GNU.sparse.map` record. Format 1.0 stores it at the start of the data of
+/// the entry instead, as decimal numbers on separate lines padded to a whole
+/// block: the number of blocks, followed by the offset and length of each
+/// block. In all formats the data of the blocks is stored contiguously.
+pub(crate) struct PaxSparse {
+    /// The offset and length of each block, or `None` if the map is stored
+    /// at the start of the data and has yet to be parsed with
+    /// `SparseMapParser`.
+    pub map: Option<Vec<(u64, u64)>>,
+    real_size: Option<u64>,
+    numblocks: Option<u64>,
+}
+
+impl PaxSparse {
+    /// Returns the sparse map of the entry with `header`, described by the
+    /// pax records `pax` which were parsed from `pax_extensions`, if it's a
+    /// sparse file.
+    pub(crate) fn new(
+        header: &Header,
+        pax: Option<&PaxMap>,
+        pax_extensions: Option<&[u8]>,
+    ) -> io::Result<Option<PaxSparse>> {
+        let pax = match pax {
+            Some(pax) if !header.entry_type().is_gnu_sparse() => pax,
+            _ => return Ok(None),
+        };
+        let invalid = || other("invalid sparse map in pax records");
+        let (map, real_size) = if let Some(map) = pax.get(PAX_GNUSPARSEMAP) {
+            let numbers = str::from_utf8(map)
+                .map_err(|_| invalid())?
+                .split(',')
+                .map(|n| n.parse::<u64>().map_err(|_| invalid()))
+                .collect::<io::Result<Vec<_>>>()?;
+            if numbers.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            let map = numbers.chunks(2).map(|c| (c[0], c[1])).collect();
+            (Some(map), pax.get_u64(PAX_GNUSPARSESIZE))
+        } else if pax.get_u64(PAX_GNUSPARSEMAJOR) == Some(1) {
+            (None, pax.get_u64(PAX_GNUSPARSEREALSIZE))
+        } else if pax.get(PAX_GNUSPARSEOFFSET).is_some() {
+            // The records of format 0.0 repeat, so they're only found in the
+            // raw local records, in order.
+            let mut offset = None;
+            let mut map = Vec::new();
+            for ext in PaxExtensions::new(pax_extensions.unwrap_or(&[])) {
+                let ext = ext?;
+                let value = || {
+                    ext.value()
+                        .ok()
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .ok_or_else(invalid)
+                };
+                match ext.key() {
+                    Ok(PAX_GNUSPARSEOFFSET) if offset.is_none() => offset = Some(value()?),
+                    Ok(PAX_GNUSPARSENUMBYTES) => match offset.take() {
+                        Some(offset) => map.push((offset, value()?)),
+                        None => return Err(invalid()),
+                    },
+                    Ok(PAX_GNUSPARSEOFFSET) => return Err(invalid()),
+                    _ => {}
+                }
+            }
+            (Some(map), pax.get_u64(PAX_GNUSPARSESIZE))
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(PaxSparse {
+            map,
+            real_size,
+            numblocks: pax.get_u64(PAX_GNUSPARSENUMBLOCKS),
+        }))
+    }
+
+    /// Returns the regions of the file, whose remaining `stored` bytes of
+    /// data start at `data_pos` in the archive, along with its size.
+    pub(crate) fn finish(self, data_pos: u64, stored: u64) -> io::Result<(Vec<Region>, u64)> {
+        let map = self.map.unwrap_or_default();
+        let real_size = self
+            .real_size
+            .ok_or_else(|| other("missing size of sparse file"))?;
+        if matches!(self.numblocks, Some(n) if n != map.len() as u64) {
+            return Err(other("mismatch in number of sparse blocks"));
+        }
+
+        let mut regions = Vec::new();
+        let mut pos = data_pos;
+        let mut cur = 0;
+        for (off, len) in map {
+            if off < cur {
+                return Err(other("out of order or overlapping sparse blocks"));
+            } else if cur < off {
+                regions.push(Region {
+                    offset: cur,
+                    len: off - cur,
+                    archive_pos: None,
+                });
+            }
+            if len > 0 {
+                regions.push(Region {
+                    offset: off,
+                    len,
+                    archive_pos: Some(pos),
+                });
+            }
+            cur = off
+                .checked_add(len)
+                .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
+            pos = pos
+                .checked_add(len)
+                .ok_or_else(|| other("more bytes listed in sparse file than u64 can hold"))?;
+        }
+        if pos - data_pos != stored {
+            return Err(other(
+                "mismatch in sparse file chunks and entry size in header",
+            ));
+        }
+        if cur > real_size {
+            return Err(other("mismatch in sparse file chunks and size in header"));
+        } else if cur < real_size {
+            regions.push(Region {
+                offset: cur,
+                len: real_size - cur,
+                archive_pos: None,
+            });
+        }
+        Ok((regions, real_size))
+    }
+}
+
+/// Parses the sparse map at the start of the data of an entry in the pax
+/// format 1.0 of GNU tar, one block at a time.
+pub(crate) struct SparseMapParser {
+    line: Vec<u8>,
+    count: Option<u64>,
+    numbers: Vec<u64>,
+}
+
+impl SparseMapParser {
+    pub(crate) fn new() -> SparseMapParser {
+        SparseMapParser {
+            line: Vec::new(),
+            count: None,
+            numbers: Vec::new(),
+        }
+    }
+
+    pub(crate) fn invalid() -> io::Error {
+        other("invalid sparse map at the start of the entry")
+    }
+
+    /// Parses the next block of the data, returning the offset and length of
+    /// each block of the file once the whole map has been parsed.
+    pub(crate) fn parse(&mut self, block: &[u8; 512]) -> io::Result<Option<Vec<(u64, u64)>>> {
+        for &b in block.iter() {
+            if b != b'\n' {
+                self.line.push(b);
+                continue;
+            }
+            let n = str::from_utf8(&self.line)
+                .ok()
+                .and_then(|n| n.parse::<u64>().ok())
+                .ok_or_else(SparseMapParser::invalid)?;
+            self.line.clear();
+            match self.count {
+                None => self.count = Some(n),
+                Some(_) => self.numbers.push(n),
+            }
+            if self.count.and_then(|c| c.checked_mul(2)) == Some(self.numbers.len() as u64) {
+                let map = self.numbers.chunks(2).map(|c| (c[0], c[1])).collect();
+                return Ok(Some(map));
+            }
+        }
+        Ok(None)
+    }
+}
+
 /// Try to fill the buffer from the reader.
 ///
 /// If the reader reaches its end before filling the buffer at all, returns `false`.
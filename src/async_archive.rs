@@ -0,0 +1,990 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::future::Future;
+use std::io::{self, Error, ErrorKind, SeekFrom};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, ReadBuf};
+
+use crate::archive::{
+    check_header, next_header_pos, Extension, ExtensionMembers, GnuSparse, PaxSparse,
+    SparseMapParser, UnpackOptions,
+};
+use crate::entry::{self, EntryFields, EntryMeta, OverwritePolicy, Region, Replace, UnpackKind};
+use crate::error::TarError;
+use crate::header::bytes2path;
+use crate::other;
+use crate::target::{FsTarget, UnpackTarget};
+use crate::{
+    EntryType, GnuExtSparseHeader, Header, PaxExtensions, PaxMap, SparseMap, TargetMetadata,
+};
+
+/// A top-level representation of an archive read from an asynchronous
+/// reader.
+///
+/// This is the counterpart of [`Archive`](crate::Archive) for readers
+/// implementing `tokio::io::AsyncRead`, available with the `tokio` feature.
+/// Entries are parsed in the same way, taking GNU long names, pax records and
+/// sparse files into account.
+///
+/// Unpacking creates files, directories and links with `tokio::fs`, making the
+/// same decisions about paths and existing files as `Archive` does. As
+/// `tokio::fs` can't set times or ownerships, the permissions, ownerships and
+/// times of entries are then restored with `spawn_blocking`, according to the
+/// settings of the archive. There are no settings for extended attributes,
+/// ACLs, atomic unpacking or durability: none of them are restored or done.
+pub struct AsyncArchive<R> {
+    options: UnpackOptions,
+    ignore_zeros: bool,
+    reader: Mutex<Counted<R>>,
+}
+
+/// A reader which keeps track of how many bytes were read from it.
+struct Counted<R> {
+    pos: u64,
+    obj: R,
+}
+
+/// A stream of the entries of an [`AsyncArchive`].
+///
+/// Entries are returned by [`AsyncEntries::next`], and must be read in
+/// order: reading an entry after the next one has been requested fails.
+pub struct AsyncEntries<'a, R> {
+    archive: &'a AsyncArchive<R>,
+    next: u64,
+    done: bool,
+    raw: bool,
+    global_pax: Option<PaxMap>,
+}
+
+/// An entry of an [`AsyncArchive`], whose contents are read through
+/// `tokio::io::AsyncRead`.
+pub struct AsyncEntry<'a, R> {
+    archive: &'a AsyncArchive<R>,
+    meta: EntryMeta,
+    pax_extensions: Option<Vec<u8>>,
+    buffer: Option<io::Cursor<Vec<u8>>>,
+    pos: u64,
+}
+
+/// The header of a member of an archive and where its data is.
+struct RawHeader {
+    header: Header,
+    header_pos: u64,
+    file_pos: u64,
+    size: u64,
+}
+
+impl<R: AsyncRead + Unpin> AsyncArchive<R> {
+    /// Create a new archive with the underlying object as the reader.
+    pub fn new(obj: R) -> AsyncArchive<R> {
+        AsyncArchive {
+            options: UnpackOptions::default(),
+            ignore_zeros: false,
+            reader: Mutex::new(Counted { pos: 0, obj }),
+        }
+    }
+
+    /// Unwrap this archive, returning the underlying object.
+    pub fn into_inner(self) -> R {
+        match self.reader.into_inner() {
+            Ok(reader) => reader.obj,
+            Err(poisoned) => poisoned.into_inner().obj,
+        }
+    }
+
+    /// Construct a stream over the entries in this archive.
+    ///
+    /// As with [`Archive::entries`](crate::Archive::entries), entries must be
+    /// processed in sequence.
+    pub fn entries(&mut self) -> io::Result<AsyncEntries<'_, R>> {
+        if self.lock()?.pos != 0 {
+            return Err(other(
+                "cannot call entries unless archive is at \
+                 position 0",
+            ));
+        }
+        Ok(AsyncEntries {
+            archive: self,
+            next: 0,
+            done: false,
+            raw: false,
+            global_pax: None,
+        })
+    }
+
+    /// Unpacks the contents of this archive to a destination on the local
+    /// filesystem, like [`Archive::unpack`](crate::Archive::unpack).
+    ///
+    /// This operation will create a directory at `dst` if it doesn't exist
+    /// already, and entries are never unpacked outside of it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn unpack() -> std::io::Result<()> {
+    /// use tar::AsyncArchive;
+    /// use tokio::fs::File;
+    ///
+    /// let mut ar = AsyncArchive::new(File::open("foo.tar").await?);
+    /// ar.unpack("foo").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        let dst = dst.as_ref();
+        if fs::symlink_metadata(dst).await.is_err() {
+            fs::create_dir_all(dst)
+                .await
+                .map_err(|e| TarError::new(format!("failed to create `{}`", dst.display()), e))?;
+        }
+        let dst = fs::canonicalize(dst)
+            .await
+            .unwrap_or_else(|_| dst.to_path_buf());
+
+        // Directories are unpacked last, just like `Archive::unpack` does.
+        let mut directories = Vec::new();
+        let mut entries = self.entries()?;
+        while let Some(entry) = entries.next().await {
+            let mut file = entry.map_err(|e| TarError::new("failed to iterate over archive", e))?;
+            if file.header().entry_type() == EntryType::Directory {
+                directories.push(file);
+            } else {
+                file.unpack_in(&dst).await?;
+            }
+        }
+        for mut dir in directories {
+            dir.unpack_in(&dst).await?;
+        }
+        Ok(())
+    }
+
+    /// Set the mask of the permission bits when unpacking the entries of this
+    /// archive.
+    ///
+    /// The mask will be inverted when applying against a mode, similar to how
+    /// `umask` works on Unix. In logical notation it looks like:
+    ///
+    /// ```text
+    /// new_mode = old_mode & (~mask)
+    /// ```
+    ///
+    /// The mask is 0 by default and is currently only implemented on Unix.
+    pub fn set_mask(&mut self, mask: u32) {
+        self.options.mask = mask;
+    }
+
+    /// Indicate whether access time information is preserved when unpacking
+    /// the entries of this archive.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_preserve_atime(&mut self, preserve: bool) {
+        self.options.preserve_atime = preserve;
+    }
+
+    /// Set whether permissions are preserved when unpacking the entries of
+    /// this archive, including the suid, sgid and sticky bits.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix.
+    pub fn set_preserve_permissions(&mut self, preserve: bool) {
+        self.options.preserve_permissions = preserve;
+    }
+
+    /// Set whether numeric ownership ids (like uid and gid on Unix) are
+    /// preserved when unpacking the entries of this archive.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Unix.
+    pub fn set_preserve_ownerships(&mut self, preserve: bool) {
+        self.options.preserve_ownerships = preserve;
+    }
+
+    /// Indicate whether files and symlinks should be overwritten on extraction.
+    ///
+    /// This is a shorthand for `set_overwrite_policy` with
+    /// `OverwritePolicy::Unlink` if `overwrite` is true and
+    /// `OverwritePolicy::KeepOld` otherwise.
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.options.overwrite = if overwrite {
+            OverwritePolicy::Unlink
+        } else {
+            OverwritePolicy::KeepOld
+        };
+    }
+
+    /// Set what happens when an entry is unpacked to a path which already
+    /// exists.
+    ///
+    /// The default policy is [`OverwritePolicy::Unlink`].
+    pub fn set_overwrite_policy(&mut self, policy: OverwritePolicy) {
+        self.options.overwrite = policy;
+    }
+
+    /// Indicate whether modification time information is preserved when
+    /// unpacking the entries of this archive.
+    ///
+    /// This flag is enabled by default.
+    pub fn set_preserve_mtime(&mut self, preserve: bool) {
+        self.options.preserve_mtime = preserve;
+    }
+
+    /// Ignore zeroed headers, which would otherwise indicate to the archive
+    /// that it has no more entries.
+    ///
+    /// This can be used in case multiple tar archives have been concatenated
+    /// together.
+    pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
+        self.ignore_zeros = ignore_zeros;
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, Counted<R>>> {
+        self.reader
+            .lock()
+            .map_err(|_| other("archive reader was poisoned"))
+    }
+
+    /// Returns how many bytes of the archive have been read.
+    fn pos(&self) -> io::Result<u64> {
+        Ok(self.lock()?.pos)
+    }
+
+    /// Reads the data stored at `pos` in the archive, which must be where
+    /// the reader is.
+    fn poll_read_at(
+        &self,
+        cx: &mut Context<'_>,
+        into: &mut [u8],
+        pos: u64,
+    ) -> Poll<io::Result<usize>> {
+        let mut reader = self.lock()?;
+        if reader.pos != pos {
+            return Poll::Ready(Err(other("entries of an archive must be read in sequence")));
+        }
+        let mut buf = ReadBuf::new(into);
+        match Pin::new(&mut reader.obj).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => {}
+            poll => return poll.map_ok(|()| 0),
+        }
+        let n = buf.filled().len();
+        if n == 0 {
+            return Poll::Ready(Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "unexpected EOF reading the contents of an entry",
+            )));
+        }
+        reader.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for &AsyncArchive<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut reader = self.lock()?;
+        let filled = buf.filled().len();
+        let ret = Pin::new(&mut reader.obj).poll_read(cx, buf);
+        reader.pos += (buf.filled().len() - filled) as u64;
+        ret
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncEntries<'a, R> {
+    /// Indicates whether this stream will return raw entries or not.
+    ///
+    /// If the raw list of entries are returned, then no preprocessing happens
+    /// on account of this library, for example taking into account GNU long name
+    /// or long link archive members. Raw iteration is disabled by default.
+    pub fn raw(self, raw: bool) -> AsyncEntries<'a, R> {
+        AsyncEntries { raw, ..self }
+    }
+
+    /// Returns the next entry of the archive, or `None` once the end of the
+    /// archive is reached.
+    ///
+    /// Any unread contents of the previous entry are skipped.
+    pub async fn next(&mut self) -> Option<io::Result<AsyncEntry<'a, R>>> {
+        if self.done {
+            return None;
+        }
+        match self.next_entry().await {
+            Ok(Some(e)) => Some(Ok(e)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    async fn next_header(&mut self, pax: Option<&PaxMap>) -> io::Result<Option<RawHeader>> {
+        let mut header = Header::new_old();
+        let mut header_pos = self.next;
+        loop {
+            // Skip to the start of the next header in the archive
+            let delta = self.next - self.archive.pos()?;
+            self.skip(delta).await?;
+
+            // EOF is an indicator that we are at the end of the archive.
+            if !self.try_read_all(header.as_mut_bytes()).await? {
+                return Ok(None);
+            }
+
+            if !header.as_bytes().iter().all(|i| *i == 0) {
+                self.next += 512;
+                break;
+            }
+
+            if !self.archive.ignore_zeros {
+                return Ok(None);
+            }
+            self.next += 512;
+            header_pos = self.next;
+        }
+
+        let size = check_header(&mut header, pax)?;
+        let file_pos = self.next;
+        self.next = next_header_pos(self.next, size)?;
+        Ok(Some(RawHeader {
+            header,
+            header_pos,
+            file_pos,
+            size,
+        }))
+    }
+
+    async fn next_entry(&mut self) -> io::Result<Option<AsyncEntry<'a, R>>> {
+        if self.raw {
+            let raw = self.next_header(None).await?;
+            return Ok(raw.map(|raw| self.entry(raw, ExtensionMembers::new(None))));
+        }
+
+        let mut members = ExtensionMembers::new(self.global_pax.clone());
+        loop {
+            let raw = match self.next_header(members.pax.as_ref()).await? {
+                Some(raw) => raw,
+                None => return members.at_eof().map(|()| None),
+            };
+
+            match Extension::of(&raw.header) {
+                Some(Extension::PaxGlobal) => {
+                    // Global headers are still handed out as entries, so
+                    // their data is kept around to be read again.
+                    let data = self.read_data(raw.size).await?;
+                    self.global_pax
+                        .get_or_insert_with(PaxMap::new)
+                        .update(&data);
+                    let mut entry = self.entry(raw, ExtensionMembers::new(None));
+                    entry.buffer = Some(io::Cursor::new(data.clone()));
+                    entry.pax_extensions = Some(data);
+                    return Ok(Some(entry));
+                }
+                Some(extension) => {
+                    let data = self.read_data(raw.size).await?;
+                    members.add(extension, data)?;
+                    continue;
+                }
+                None => {}
+            }
+
+            let mut entry = self.entry(raw, members);
+            self.parse_sparse_header(&mut entry).await?;
+            self.parse_pax_sparse(&mut entry).await?;
+            return Ok(Some(entry));
+        }
+    }
+
+    fn entry(&self, raw: RawHeader, members: ExtensionMembers) -> AsyncEntry<'a, R> {
+        let path = entry::resolve_path(
+            &raw.header,
+            members.long_pathname.as_deref(),
+            members.pax.as_ref(),
+        )
+        .into_owned();
+        let link_name = entry::resolve_link_name(
+            &raw.header,
+            members.long_linkname.as_deref(),
+            members.pax.as_ref(),
+        )
+        .map(Cow::into_owned);
+        let regions = if raw.size > 0 {
+            vec![Region {
+                offset: 0,
+                len: raw.size,
+                archive_pos: Some(raw.file_pos),
+            }]
+        } else {
+            Vec::new()
+        };
+        AsyncEntry {
+            archive: self.archive,
+            meta: EntryMeta {
+                header: raw.header,
+                path,
+                link_name,
+                pax: members.pax,
+                size: raw.size,
+                header_pos: raw.header_pos,
+                file_pos: raw.file_pos,
+                regions,
+                sparse: false,
+            },
+            pax_extensions: members.pax_extensions,
+            buffer: None,
+            pos: 0,
+        }
+    }
+
+    async fn parse_sparse_header(&mut self, entry: &mut AsyncEntry<'a, R>) -> io::Result<()> {
+        let mut sparse = match GnuSparse::new(&entry.meta.header, entry.meta.size)? {
+            Some(sparse) => sparse,
+            None => return Ok(()),
+        };
+        let mut ext = GnuExtSparseHeader::new();
+        while sparse.is_extended() {
+            if !self.try_read_all(ext.as_mut_bytes()).await? {
+                return Err(other("failed to read extension"));
+            }
+            self.next += 512;
+            sparse.add_extension(&ext)?;
+        }
+        let (regions, real_size) = sparse.finish(self.archive.pos()?)?;
+        entry.meta.regions = regions;
+        entry.meta.size = real_size;
+        entry.meta.sparse = true;
+        Ok(())
+    }
+
+    async fn parse_pax_sparse(&mut self, entry: &mut AsyncEntry<'a, R>) -> io::Result<()> {
+        let sparse = PaxSparse::new(
+            &entry.meta.header,
+            entry.meta.pax.as_ref(),
+            entry.pax_extensions.as_deref(),
+        )?;
+        let mut sparse = match sparse {
+            Some(sparse) => sparse,
+            None => return Ok(()),
+        };
+        if sparse.map.is_none() {
+            let mut parser = SparseMapParser::new();
+            let mut block = [0; 512];
+            while sparse.map.is_none() {
+                entry
+                    .read_exact(&mut block)
+                    .await
+                    .map_err(|_| SparseMapParser::invalid())?;
+                sparse.map = parser.parse(&block)?;
+            }
+        }
+
+        // Whatever data of the entry is left is the data of the blocks.
+        let stored = entry.meta.size - entry.pos;
+        let (regions, real_size) = sparse.finish(self.archive.pos()?, stored)?;
+        entry.meta.regions = regions;
+        entry.meta.size = real_size;
+        entry.meta.sparse = true;
+        entry.pos = 0;
+        Ok(())
+    }
+
+    async fn read_data(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        // Preallocate some data but don't let ourselves get too crazy now.
+        let mut data = Vec::with_capacity(cmp::min(size, 128 * 1024) as usize);
+        self.archive.take(size).read_to_end(&mut data).await?;
+        if data.len() as u64 != size {
+            return Err(other("unexpected EOF reading an extension member"));
+        }
+        Ok(data)
+    }
+
+    async fn skip(&mut self, amt: u64) -> io::Result<()> {
+        let mut data = self.archive.take(amt);
+        if tokio::io::copy(&mut data, &mut tokio::io::sink()).await? != amt {
+            return Err(other("unexpected EOF during skip"));
+        }
+        Ok(())
+    }
+
+    /// Try to fill the buffer from the archive, returning `false` if the
+    /// archive reaches its end before filling the buffer at all.
+    async fn try_read_all(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.archive.read(&mut buf[read..]).await? {
+                0 => {
+                    if read == 0 {
+                        return Ok(false);
+                    }
+
+                    return Err(other("failed to read entire block"));
+                }
+                n => read += n,
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncEntry<'a, R> {
+    /// Returns the path name for this entry, as [`Entry::path`] does.
+    ///
+    /// [`Entry::path`]: crate::Entry::path
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
+        bytes2path(Cow::Borrowed(&self.meta.path))
+    }
+
+    /// Returns the raw bytes listed for this entry.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.meta.path
+    }
+
+    /// Returns the link name for this entry, if any is found, as
+    /// [`Entry::link_name`] does.
+    ///
+    /// [`Entry::link_name`]: crate::Entry::link_name
+    pub fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
+        match self.meta.link_name {
+            Some(ref bytes) => bytes2path(Cow::Borrowed(bytes)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the link name for this entry, in bytes, if listed.
+    pub fn link_name_bytes(&self) -> Option<&[u8]> {
+        self.meta.link_name.as_deref()
+    }
+
+    /// Returns the pax extensions preceding this entry, or the records of
+    /// this entry itself if it's a pax extensions entry, if any.
+    pub fn pax_extensions(&self) -> Option<PaxExtensions<'_>> {
+        self.pax_extensions.as_deref().map(PaxExtensions::new)
+    }
+
+    /// Returns all of the pax records which apply to this entry, if any.
+    pub fn pax(&self) -> Option<&PaxMap> {
+        self.meta.pax.as_ref()
+    }
+
+    /// Returns access to the header of this entry in the archive.
+    pub fn header(&self) -> &Header {
+        &self.meta.header
+    }
+
+    /// Returns the size of the file this entry represents.
+    pub fn size(&self) -> u64 {
+        self.meta.size
+    }
+
+    /// Returns the starting position, in bytes, of the header of this entry in
+    /// the archive.
+    pub fn raw_header_position(&self) -> u64 {
+        self.meta.header_pos
+    }
+
+    /// Returns the starting position, in bytes, of the file of this entry in
+    /// the archive.
+    pub fn raw_file_position(&self) -> u64 {
+        self.meta.file_pos
+    }
+
+    /// Returns the layout of the data and holes of this entry, if it's a
+    /// sparse file.
+    pub fn sparse_map(&self) -> Option<SparseMap> {
+        self.meta.sparse_map()
+    }
+
+    /// Extracts this file under the specified path, avoiding security issues,
+    /// like [`Entry::unpack_in`] does.
+    ///
+    /// Returns `false` if the entry was skipped because its path would have
+    /// escaped `dst`.
+    ///
+    /// [`Entry::unpack_in`]: crate::Entry::unpack_in
+    pub async fn unpack_in<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<bool> {
+        let dst = dst.as_ref();
+        let file_dst = self.fields().unpack_in_dst(dst, None)?;
+        let file_dst = match file_dst {
+            Some(file_dst) => file_dst,
+            None => return Ok(false),
+        };
+
+        // Skip cases where only slashes or '.' parts were seen, because
+        // this is effectively an empty filename.
+        if *dst == *file_dst {
+            return Ok(true);
+        }
+
+        let parent = match file_dst.parent() {
+            Some(p) => p,
+            None => return Err(other("cannot unpack an entry without a parent")),
+        };
+        ensure_dir_created(dst, parent)
+            .await
+            .map_err(|e| TarError::new(format!("failed to create `{}`", parent.display()), e))?;
+        let canon_target = validate_inside_dst(dst, parent).await?;
+
+        self.unpack(&canon_target, &file_dst)
+            .await
+            .map_err(|e| TarError::new(format!("failed to unpack `{}`", file_dst.display()), e))?;
+        Ok(true)
+    }
+
+    /// Creates the fields of this entry, without its contents, to share the
+    /// logic of unpacking with `Entry`.
+    fn fields(&self) -> EntryFields<'static> {
+        self.archive.options.detached_fields(&self.meta, Vec::new())
+    }
+
+    async fn unpack(&mut self, target_base: &Path, dst: &Path) -> io::Result<()> {
+        // The fields aren't `Send`, so they can't be kept across an `await`.
+        let (kind, src) = {
+            let fields = self.fields();
+            let kind = fields.unpack_kind();
+            let src = match kind {
+                UnpackKind::HardLink | UnpackKind::Symlink => Some(fields.link_src()?.into_owned()),
+                _ => None,
+            };
+            (kind, src)
+        };
+        match kind {
+            UnpackKind::Dir => {
+                if self.unpack_dir(dst).await? {
+                    self.set_metadata(dst, None, false).await?;
+                }
+                return Ok(());
+            }
+            UnpackKind::HardLink => {
+                // Make sure the destination of the hard link is inside of
+                // `dst` too, as `Entry::unpack` does.
+                let link_src = target_base.join(src.unwrap());
+                validate_inside_dst(target_base, &link_src).await?;
+                self.create_or_replace(dst, || fs::hard_link(&link_src, dst))
+                    .await
+                    .map_err(|err| {
+                        Error::new(
+                            err.kind(),
+                            format!(
+                                "{} when hard linking {} to {}",
+                                err,
+                                link_src.display(),
+                                dst.display()
+                            ),
+                        )
+                    })?;
+                return Ok(());
+            }
+            UnpackKind::Symlink => {
+                let src = src.unwrap();
+                let created = self
+                    .create_or_replace(dst, || symlink(&src, dst))
+                    .await
+                    .map_err(|err| {
+                        Error::new(
+                            err.kind(),
+                            format!(
+                                "{} when symlinking {} to {}",
+                                err,
+                                src.display(),
+                                dst.display()
+                            ),
+                        )
+                    })?;
+                if created.is_some() {
+                    self.set_times(dst).await?;
+                }
+                return Ok(());
+            }
+            UnpackKind::Skip => return Ok(()),
+            UnpackKind::File => {}
+        }
+
+        // Unrecognized kinds are written out as regular files, and a new file
+        // is always created rather than overwriting one in place.
+        let create = || async move {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(dst)
+                .await
+        };
+        let mut f = match self.create_or_replace(dst, create).await? {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        self.write_contents(&mut f).await.map_err(|e| {
+            TarError::new(
+                format!(
+                    "failed to unpack `{}` into `{}`",
+                    String::from_utf8_lossy(&self.meta.header.path_bytes()),
+                    dst.display()
+                ),
+                e,
+            )
+        })?;
+        self.set_metadata(dst, Some(f.into_std().await), true).await
+    }
+
+    /// Writes the remaining contents of this entry to `f`, leaving holes of
+    /// sparse files unwritten.
+    async fn write_contents(&mut self, f: &mut fs::File) -> io::Result<()> {
+        let pos = self.pos;
+        let regions = self
+            .meta
+            .regions
+            .iter()
+            .filter(|r| r.offset + r.len > pos)
+            .copied()
+            .collect::<Vec<_>>();
+        for region in regions {
+            let len = region.offset + region.len - self.pos;
+            match region.archive_pos {
+                Some(_) => {
+                    if tokio::io::copy(&mut (&mut *self).take(len), f).await? != len {
+                        return Err(other("failed to write entire file"));
+                    }
+                }
+                None => {
+                    let size = f.seek(SeekFrom::Current(len as i64)).await?;
+                    f.set_len(size).await?;
+                    self.pos += len;
+                }
+            }
+        }
+        if let Some(ref mut buffer) = self.buffer {
+            tokio::io::copy(buffer, f).await?;
+        }
+        f.flush().await
+    }
+
+    /// Unpacks this entry as the directory `dst`, returning `false` if
+    /// something other than a directory is in the way and the overwrite
+    /// policy says to leave it alone.
+    async fn unpack_dir(&self, dst: &Path) -> io::Result<bool> {
+        // If the directory already exists just let it slide
+        let create = || async move {
+            match fs::create_dir(dst).await {
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    match fs::metadata(dst).await {
+                        Ok(prev) if prev.is_dir() => Ok(()),
+                        _ => Err(err),
+                    }
+                }
+                result => result,
+            }
+        };
+        self.create_or_replace(dst, create)
+            .await
+            .map(|created| created.is_some())
+            .map_err(|err| {
+                Error::new(
+                    err.kind(),
+                    format!("{} when creating dir {}", err, dst.display()),
+                )
+            })
+    }
+
+    /// Runs `create` to create `dst`, and if it fails because something
+    /// already exists there consults the overwrite policy to decide whether
+    /// to remove the existing path and try again.
+    ///
+    /// Returns `None` if the entry should not be unpacked.
+    async fn create_or_replace<U, F>(
+        &self,
+        dst: &Path,
+        create: impl Fn() -> F,
+    ) -> io::Result<Option<U>>
+    where
+        F: Future<Output = io::Result<U>>,
+    {
+        let err = match create().await {
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => err,
+            result => return result.map(Some),
+        };
+        let prev = match fs::symlink_metadata(dst).await {
+            Ok(prev) => TargetMetadata::from(prev),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return create().await.map(Some),
+            Err(e) => return Err(e),
+        };
+        let replace = self.fields().replace_existing(prev.mtime());
+        let removed = match replace {
+            Replace::Fail => return Err(err),
+            Replace::Keep => return Ok(None),
+            _ if !prev.is_dir() => fs::remove_file(dst).await,
+            Replace::UnlinkRecursive => fs::remove_dir_all(dst).await,
+            _ => fs::remove_dir(dst).await,
+        };
+        match removed {
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            result => result?,
+        }
+        create().await.map(Some)
+    }
+
+    /// Restores the times of the symlink `dst`.
+    async fn set_times(&self, dst: &Path) -> io::Result<()> {
+        let (atime, mtime) = self.fields().unpack_times();
+        if atime.is_none() && mtime.is_none() {
+            return Ok(());
+        }
+        let path = dst.to_path_buf();
+        blocking(move || FsTarget::new().set_times(&path, None, atime, mtime))
+            .await
+            .map_err(|e| {
+                TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
+            })?;
+        Ok(())
+    }
+
+    /// Restores the ownership and permissions of `dst`, which is open as `f`
+    /// if it's a file, and optionally its times.
+    ///
+    /// `tokio::fs` can't set times or ownerships, so this is done with
+    /// `spawn_blocking`, reusing the logic of unpacking synchronously.
+    async fn set_metadata(
+        &self,
+        dst: &Path,
+        f: Option<std::fs::File>,
+        times: bool,
+    ) -> io::Result<()> {
+        let (atime, mtime, ownership, mode) = {
+            let fields = self.fields();
+            let (atime, mtime) = if times {
+                fields.unpack_times()
+            } else {
+                (None, None)
+            };
+            (
+                atime,
+                mtime,
+                fields.unpack_ownership()?,
+                fields.unpack_mode(),
+            )
+        };
+
+        let path = dst.to_path_buf();
+        blocking(move || {
+            let target = &mut FsTarget::new();
+            let mut f = f;
+            if atime.is_some() || mtime.is_some() {
+                target
+                    .set_times(&path, f.as_mut(), atime, mtime)
+                    .map_err(|e| {
+                        TarError::new(format!("failed to set mtime for `{}`", path.display()), e)
+                    })?;
+            }
+            entry::set_perms_ownerships(target, &path, f.as_mut(), ownership, mode)
+        })
+        .await
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncRead for AsyncEntry<'a, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        if let Some(ref mut buffer) = me.buffer {
+            return Pin::new(buffer).poll_read(cx, buf);
+        }
+        let pos = me.pos;
+        let region = match me.meta.regions.iter().find(|r| r.offset + r.len > pos) {
+            Some(region) if buf.remaining() > 0 => *region,
+            _ => return Poll::Ready(Ok(())),
+        };
+        let skip = pos - region.offset;
+        let n = cmp::min(buf.remaining() as u64, region.len - skip) as usize;
+        let into = buf.initialize_unfilled_to(n);
+        let n = match region.archive_pos {
+            Some(archive_pos) => match me.archive.poll_read_at(cx, into, archive_pos + skip) {
+                Poll::Ready(Ok(n)) => n,
+                poll => return poll.map_ok(|_| ()),
+            },
+            None => {
+                into.fill(0);
+                n
+            }
+        };
+        buf.advance(n);
+        me.pos += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn ensure_dir_created(dst: &Path, dir: &Path) -> io::Result<()> {
+    let mut ancestor = dir;
+    let mut dirs_to_create = Vec::new();
+    while fs::symlink_metadata(ancestor).await.is_err() {
+        dirs_to_create.push(ancestor);
+        if let Some(parent) = ancestor.parent() {
+            ancestor = parent;
+        } else {
+            break;
+        }
+    }
+    for ancestor in dirs_to_create.into_iter().rev() {
+        if let Some(parent) = ancestor.parent() {
+            validate_inside_dst(dst, parent).await?;
+        }
+        fs::create_dir_all(ancestor).await?;
+    }
+    Ok(())
+}
+
+async fn validate_inside_dst(dst: &Path, file_dst: &Path) -> io::Result<PathBuf> {
+    // Abort if target (canonical) parent is outside of `dst`
+    let canon_parent = fs::canonicalize(file_dst).await.map_err(|err| {
+        Error::new(
+            err.kind(),
+            format!("{} while canonicalizing {}", err, file_dst.display()),
+        )
+    })?;
+    let canon_target = fs::canonicalize(dst).await.map_err(|err| {
+        Error::new(
+            err.kind(),
+            format!("{} while canonicalizing {}", err, dst.display()),
+        )
+    })?;
+    entry::check_inside_dst(&canon_target, &canon_parent)?;
+    Ok(canon_target)
+}
+
+#[cfg(unix)]
+async fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::symlink(src, dst).await
+}
+
+#[cfg(windows)]
+async fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    fs::symlink_file(src, dst).await
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(other("Not implemented"))
+}
+
+/// Runs `f` on a thread where blocking is acceptable.
+pub(crate) async fn blocking<T, F>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(other("blocking task was cancelled")),
+    }
+}
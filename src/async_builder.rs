@@ -0,0 +1,432 @@
+use std::io;
+use std::path::Path;
+
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::async_archive::blocking;
+use crate::builder::{
+    append_pax, append_pax_global, metadata_error, pax_records, prepare_fs_header,
+    prepare_header_link, prepare_header_path, prepare_owner_names, walk_dir_all, WalkedPath,
+};
+use crate::header::HeaderMode;
+use crate::{Header, PaxBuilder};
+
+/// A structure for building archives into an asynchronous writer.
+///
+/// This is the counterpart of [`Builder`](crate::Builder) for writers
+/// implementing `tokio::io::AsyncWrite`, available with the `tokio` feature.
+/// Entries are laid out in the same way, with GNU long name members for paths
+/// and link names which don't fit in their header.
+///
+/// As a destructor can't wait for the writer, an archive isn't terminated
+/// when an `AsyncBuilder` is dropped: [`finish`](AsyncBuilder::finish) or
+/// [`into_inner`](AsyncBuilder::into_inner) needs to be called once all of
+/// the entries have been appended. ACLs of files on the local filesystem are
+/// not stored.
+pub struct AsyncBuilder<W> {
+    mode: HeaderMode,
+    follow: bool,
+    pax: PaxBuilder,
+    finished: bool,
+    obj: W,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncBuilder<W> {
+    /// Create a new archive builder with the underlying object as the
+    /// destination of all data written. The builder will use
+    /// `HeaderMode::Complete` by default.
+    pub fn new(obj: W) -> AsyncBuilder<W> {
+        AsyncBuilder {
+            mode: HeaderMode::Complete,
+            follow: true,
+            pax: PaxBuilder::new(),
+            finished: false,
+            obj,
+        }
+    }
+
+    /// Changes the HeaderMode that will be used when reading fs Metadata for
+    /// methods that implicitly read metadata for an input Path. Notably, this
+    /// does _not_ apply to `append(Header)`.
+    pub fn mode(&mut self, mode: HeaderMode) {
+        self.mode = mode;
+    }
+
+    /// Follow symlinks, archiving the contents of the file they point to rather
+    /// than adding a symlink to the archive. Defaults to true.
+    pub fn follow_symlinks(&mut self, follow: bool) {
+        self.follow = follow;
+    }
+
+    /// Gets shared reference to the underlying object.
+    pub fn get_ref(&self) -> &W {
+        &self.obj
+    }
+
+    /// Gets mutable reference to the underlying object.
+    ///
+    /// Note that care must be taken while writing to the underlying object.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.obj
+    }
+
+    /// Unwrap this archive, returning the underlying object.
+    ///
+    /// This function will finish writing the archive if the `finish` function
+    /// hasn't yet been called, returning any I/O error which happens during
+    /// that operation.
+    pub async fn into_inner(mut self) -> io::Result<W> {
+        self.finish().await?;
+        Ok(self.obj)
+    }
+
+    /// Adds a new entry to this archive, like
+    /// [`Builder::append`](crate::Builder::append).
+    ///
+    /// The header specified is appended followed by the contents of `data`.
+    /// To produce a valid archive the `size` field of `header` must be the
+    /// same as the length of the stream, and its checksum should have been
+    /// set via the `set_cksum` method.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn append() -> std::io::Result<()> {
+    /// use tar::{AsyncBuilder, Header};
+    ///
+    /// let mut header = Header::new_gnu();
+    /// header.set_path("foo")?;
+    /// header.set_size(4);
+    /// header.set_cksum();
+    ///
+    /// let mut ar = AsyncBuilder::new(Vec::new());
+    /// ar.append(&header, &[1, 2, 3, 4][..]).await?;
+    /// let data = ar.into_inner().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn append<R: AsyncRead + Unpin>(
+        &mut self,
+        header: &Header,
+        data: R,
+    ) -> io::Result<()> {
+        let mut members = Vec::new();
+        append_pax(&mut members, &mut self.pax)?;
+        self.write_entry(members, header, data).await
+    }
+
+    /// Adds pax extended header records to the next entry appended to this
+    /// archive, like
+    /// [`Builder::append_pax_extensions`](crate::Builder::append_pax_extensions).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if a key is empty or contains
+    /// `=` or a newline, in which case none of the records are added.
+    pub fn append_pax_extensions<'k, 'v, I>(&mut self, records: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (&'k str, &'v [u8])>,
+    {
+        let mut pax = self.pax.clone();
+        pax_records(&mut pax, &mut records.into_iter())?;
+        self.pax = pax;
+        Ok(())
+    }
+
+    /// Appends a global pax extended header holding `records`, which applies
+    /// to all of the entries appended after it, like
+    /// [`Builder::append_pax_global_extensions`](crate::Builder::append_pax_global_extensions).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if a key is empty or contains
+    /// `=` or a newline, and any I/O error which occurs while writing.
+    pub async fn append_pax_global_extensions<'k, 'v, I>(&mut self, records: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (&'k str, &'v [u8])>,
+    {
+        let mut buf = Vec::new();
+        append_pax_global(&mut buf, &mut records.into_iter())?;
+        self.obj.write_all(&buf).await
+    }
+
+    /// Sets the user and group names of `header`, like
+    /// [`Builder::set_owner_names`](crate::Builder::set_owner_names).
+    ///
+    /// Names which don't fit in the header, or aren't ASCII, are also stored
+    /// in pax records of the next entry appended to this archive.
+    pub fn set_owner_names(
+        &mut self,
+        header: &mut Header,
+        username: &str,
+        groupname: &str,
+    ) -> io::Result<()> {
+        prepare_owner_names(&mut self.pax, header, username, groupname)
+    }
+
+    /// Adds a new entry to this archive with the specified path, like
+    /// [`Builder::append_data`](crate::Builder::append_data).
+    ///
+    /// The path is set in the given header, appending a GNU long-name
+    /// extension entry to the archive first if it doesn't fit, and the
+    /// checksum of the header is updated. No other metadata in the header
+    /// will be modified.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn append() -> std::io::Result<()> {
+    /// use tar::{AsyncBuilder, Header};
+    /// use tokio::fs::File;
+    ///
+    /// let file = File::open("foo.txt").await?;
+    /// let mut header = Header::new_gnu();
+    /// header.set_size(file.metadata().await?.len());
+    ///
+    /// let mut ar = AsyncBuilder::new(File::create("foo.tar").await?);
+    /// ar.append_data(&mut header, "really/long/path/to/foo.txt", file)
+    ///     .await?;
+    /// ar.finish().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn append_data<P: AsRef<Path>, R: AsyncRead + Unpin>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        data: R,
+    ) -> io::Result<()> {
+        let mut members = Vec::new();
+        append_pax(&mut members, &mut self.pax)?;
+        prepare_header_path(&mut members, header, path.as_ref())?;
+        header.set_cksum();
+        self.write_entry(members, header, data).await
+    }
+
+    /// Adds a new link (symbolic or hard) entry to this archive with the
+    /// specified path and target, like
+    /// [`Builder::append_link`](crate::Builder::append_link).
+    ///
+    /// The entry type of `header` must be set to either
+    /// [`EntryType::Link`](crate::EntryType::Link) or
+    /// [`EntryType::Symlink`](crate::EntryType::Symlink).
+    pub async fn append_link<P: AsRef<Path>, T: AsRef<Path>>(
+        &mut self,
+        header: &mut Header,
+        path: P,
+        target: T,
+    ) -> io::Result<()> {
+        let mut members = Vec::new();
+        append_pax(&mut members, &mut self.pax)?;
+        prepare_header_path(&mut members, header, path.as_ref())?;
+        prepare_header_link(&mut members, header, target.as_ref())?;
+        header.set_cksum();
+        self.write_entry(members, header, tokio::io::empty()).await
+    }
+
+    /// Adds a file on the local filesystem to this archive, like
+    /// [`Builder::append_path`](crate::Builder::append_path).
+    ///
+    /// The path name for the file inside of this archive will be the same as
+    /// `path`, and it is required that the path is a relative path.
+    pub async fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.append_path_with_name_opt(path.as_ref(), None).await
+    }
+
+    /// Adds a file on the local filesystem to this archive under another
+    /// name, like
+    /// [`Builder::append_path_with_name`](crate::Builder::append_path_with_name).
+    pub async fn append_path_with_name<P: AsRef<Path>, N: AsRef<Path>>(
+        &mut self,
+        path: P,
+        name: N,
+    ) -> io::Result<()> {
+        self.append_path_with_name_opt(path.as_ref(), Some(name.as_ref()))
+            .await
+    }
+
+    /// Adds a file to this archive with the given path as the name of the
+    /// file in the archive, like
+    /// [`Builder::append_file`](crate::Builder::append_file).
+    pub async fn append_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        file: &mut fs::File,
+    ) -> io::Result<()> {
+        let stat = file.metadata().await?;
+        self.append_fs(path.as_ref(), &stat, file, None).await
+    }
+
+    /// Adds a directory to this archive with the given path as the name of
+    /// the directory in the archive, like
+    /// [`Builder::append_dir`](crate::Builder::append_dir).
+    ///
+    /// This will not add the contents of the directory to the archive, see
+    /// `append_dir_all` for recursively adding them.
+    pub async fn append_dir<P, Q>(&mut self, path: P, src_path: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let stat = fs::metadata(src_path.as_ref()).await?;
+        self.append_fs(path.as_ref(), &stat, tokio::io::empty(), None)
+            .await
+    }
+
+    /// Adds a directory and all of its contents (recursively) to this archive
+    /// with the given path as the name of the directory in the archive, like
+    /// [`Builder::append_dir_all`](crate::Builder::append_dir_all).
+    ///
+    /// The directory is walked in the same way as by `Builder`, with
+    /// `spawn_blocking`, before the paths found are appended.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async fn append() -> std::io::Result<()> {
+    /// use tar::AsyncBuilder;
+    /// use tokio::fs::File;
+    ///
+    /// let mut ar = AsyncBuilder::new(File::create("foo.tar").await?);
+    /// ar.append_dir_all("bardir", ".").await?;
+    /// ar.finish().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn append_dir_all<P, Q>(&mut self, path: P, src_path: Q) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let (path, src_path) = (path.as_ref().to_owned(), src_path.as_ref().to_owned());
+        let follow = self.follow;
+        let found = blocking(move || {
+            let mut found = Vec::new();
+            walk_dir_all(&path, &src_path, follow, &mut |dest, src, kind| {
+                found.push((dest.to_owned(), src.to_owned(), kind));
+                Ok(())
+            })?;
+            Ok(found)
+        })
+        .await?;
+        for (dest, src, kind) in found {
+            match kind {
+                WalkedPath::Dir => self.append_dir(&dest, &src).await?,
+                WalkedPath::Symlink => {
+                    let stat = fs::symlink_metadata(&src).await?;
+                    let link_name = fs::read_link(&src).await?;
+                    self.append_fs(&dest, &stat, tokio::io::empty(), Some(&link_name))
+                        .await?
+                }
+                WalkedPath::File => {
+                    self.append_file(&dest, &mut fs::File::open(src).await?)
+                        .await?
+                }
+                #[cfg(unix)]
+                WalkedPath::Special(stat) => self.append_special(&dest, &stat).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish writing this archive, emitting the termination sections and
+    /// flushing the underlying object.
+    ///
+    /// This function should only be called when the archive has been written
+    /// entirely and if an I/O error happens the underlying object still needs
+    /// to be acquired.
+    ///
+    /// In most situations the `into_inner` method should be preferred.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.obj.write_all(&[0; 1024]).await?;
+        self.obj.flush().await
+    }
+
+    async fn append_path_with_name_opt(
+        &mut self,
+        path: &Path,
+        name: Option<&Path>,
+    ) -> io::Result<()> {
+        let stat = if self.follow {
+            fs::metadata(path).await
+        } else {
+            fs::symlink_metadata(path).await
+        };
+        let stat = stat.map_err(|err| metadata_error(err, path))?;
+        let ar_name = name.unwrap_or(path);
+        if stat.is_file() {
+            let file = fs::File::open(path).await?;
+            self.append_fs(ar_name, &stat, file, None).await
+        } else if stat.is_dir() {
+            self.append_fs(ar_name, &stat, tokio::io::empty(), None)
+                .await
+        } else if stat.file_type().is_symlink() {
+            let link_name = fs::read_link(path).await?;
+            self.append_fs(ar_name, &stat, tokio::io::empty(), Some(&link_name))
+                .await
+        } else {
+            #[cfg(unix)]
+            {
+                self.append_special(path, &stat).await
+            }
+            #[cfg(not(unix))]
+            {
+                Err(crate::other(&format!(
+                    "{} has unknown file type",
+                    path.display()
+                )))
+            }
+        }
+    }
+
+    async fn append_fs<R: AsyncRead + Unpin>(
+        &mut self,
+        path: &Path,
+        stat: &std::fs::Metadata,
+        data: R,
+        link_name: Option<&Path>,
+    ) -> io::Result<()> {
+        let mut members = Vec::new();
+        let header = prepare_fs_header(
+            &mut members,
+            path,
+            stat,
+            self.mode,
+            link_name,
+            &mut self.pax,
+        )?;
+        self.write_entry(members, &header, data).await
+    }
+
+    #[cfg(unix)]
+    async fn append_special(&mut self, path: &Path, stat: &std::fs::Metadata) -> io::Result<()> {
+        let mut buf = Vec::new();
+        crate::builder::append_special(&mut buf, path, stat, self.mode, &mut self.pax)?;
+        self.obj.write_all(&buf).await
+    }
+
+    /// Writes the extension `members` preceding an entry, its header and its
+    /// data padded to a whole number of blocks.
+    async fn write_entry<R: AsyncRead + Unpin>(
+        &mut self,
+        mut members: Vec<u8>,
+        header: &Header,
+        mut data: R,
+    ) -> io::Result<()> {
+        members.extend_from_slice(header.as_bytes());
+        self.obj.write_all(&members).await?;
+        let len = tokio::io::copy(&mut data, &mut self.obj).await?;
+
+        // Pad with zeros if necessary.
+        let remaining = 512 - (len % 512);
+        if remaining < 512 {
+            self.obj.write_all(&[0; 512][..remaining as usize]).await?;
+        }
+        Ok(())
+    }
+}
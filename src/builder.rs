@@ -1,11 +1,19 @@
 use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::mem;
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+use std::ops::{Deref, DerefMut};
 use std::path::Path;
 use std::str;
 
+use crate::archive::SeekRead;
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+use crate::compression::Compressor;
 use crate::header::{path2bytes, HeaderMode};
-use crate::{other, EntryType, Header};
+use crate::pax;
+use crate::{other, Archive, EntryType, Header, PaxBuilder};
 
 /// A structure for building archives
 ///
@@ -14,6 +22,8 @@ use crate::{other, EntryType, Header};
 pub struct Builder<W: Write> {
     mode: HeaderMode,
     follow: bool,
+    acls: bool,
+    pax: PaxBuilder,
     finished: bool,
     obj: Option<W>,
 }
@@ -26,6 +36,8 @@ impl<W: Write> Builder<W> {
         Builder {
             mode: HeaderMode::Complete,
             follow: true,
+            acls: false,
+            pax: PaxBuilder::new(),
             finished: false,
             obj: Some(obj),
         }
@@ -44,6 +56,16 @@ impl<W: Write> Builder<W> {
         self.follow = follow;
     }
 
+    /// Store the POSIX ACLs of files and directories read from the local
+    /// filesystem, as `SCHILY.acl.access` and `SCHILY.acl.default` pax
+    /// records in the same format as star and GNU tar. Defaults to false.
+    ///
+    /// This is currently only implemented on Linux, when the `acl` feature
+    /// of this crate is enabled.
+    pub fn preserve_acls(&mut self, preserve: bool) {
+        self.acls = preserve;
+    }
+
     /// Gets shared reference to the underlying object.
     pub fn get_ref(&self) -> &W {
         self.obj.as_ref().unwrap()
@@ -108,7 +130,109 @@ impl<W: Write> Builder<W> {
     /// let data = ar.into_inner().unwrap();
     /// ```
     pub fn append<R: Read>(&mut self, header: &Header, mut data: R) -> io::Result<()> {
-        append(self.get_mut(), header, &mut data)
+        let dst = self.obj.as_mut().unwrap();
+        append_pax(dst, &mut self.pax)?;
+        append(dst, header, &mut data)
+    }
+
+    /// Sets the user and group names of the owner in `header`, which is
+    /// expected to be appended to this archive next.
+    ///
+    /// The header only has room for names of up to 32 bytes. Names which are
+    /// longer, or which aren't ASCII, are additionally stored in `uname` and
+    /// `gname` pax records attached to the next entry appended to this
+    /// archive, like with `append_pax_extensions`, while the header holds as
+    /// much of them as fits.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a name contains a nul byte or if `header` can't
+    /// hold user and group names, in which case nothing is changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// let mut header = Header::new_ustar();
+    /// header.set_size(0);
+    /// ar.set_owner_names(&mut header, "a-rather-long-user-name-for-a-header", "wheel")
+    ///     .unwrap();
+    /// ar.append_data(&mut header, "foo", &[][..]).unwrap();
+    /// ```
+    pub fn set_owner_names(
+        &mut self,
+        header: &mut Header,
+        username: &str,
+        groupname: &str,
+    ) -> io::Result<()> {
+        prepare_owner_names(&mut self.pax, header, username, groupname)
+    }
+
+    /// Adds pax extended header records to the next entry appended to this
+    /// archive.
+    ///
+    /// The records are written in a local pax extended header (`x`) right
+    /// before the next entry, together with any records this builder
+    /// generates itself, such as ACLs. Calling this several times before an
+    /// entry is appended accumulates the records. Records which are still
+    /// pending when the archive is finished are discarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if a key is empty or contains
+    /// `=` or a newline, in which case none of the records are added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Builder, Header};
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// ar.append_pax_extensions(vec![
+    ///     ("comment", &b"made by hand"[..]),
+    ///     ("SCHILY.xattr.user.mime_type", b"text/plain"),
+    /// ])
+    /// .unwrap();
+    ///
+    /// let mut header = Header::new_ustar();
+    /// header.set_size(3);
+    /// ar.append_data(&mut header, "foo.txt", &b"foo"[..]).unwrap();
+    /// let data = ar.into_inner().unwrap();
+    /// ```
+    pub fn append_pax_extensions<'k, 'v, I>(&mut self, records: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (&'k str, &'v [u8])>,
+    {
+        let mut pax = self.pax.clone();
+        pax_records(&mut pax, &mut records.into_iter())?;
+        self.pax = pax;
+        Ok(())
+    }
+
+    /// Appends a global pax extended header (`g`) holding `records`, which
+    /// applies to all of the entries appended after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if a key is empty or contains
+    /// `=` or a newline, and any I/O error which occurs while writing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::Builder;
+    ///
+    /// let mut ar = Builder::new(Vec::new());
+    /// ar.append_pax_global_extensions(vec![("comment", &b"nightly build"[..])])
+    ///     .unwrap();
+    /// ```
+    pub fn append_pax_global_extensions<'k, 'v, I>(&mut self, records: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (&'k str, &'v [u8])>,
+    {
+        append_pax_global(self.get_mut(), &mut records.into_iter())
     }
 
     /// Adds a new entry to this archive with the specified path.
@@ -157,7 +281,9 @@ impl<W: Write> Builder<W> {
         path: P,
         data: R,
     ) -> io::Result<()> {
-        prepare_header_path(self.get_mut(), header, path.as_ref())?;
+        let dst = self.obj.as_mut().unwrap();
+        append_pax(dst, &mut self.pax)?;
+        prepare_header_path(dst, header, path.as_ref())?;
         header.set_cksum();
         self.append(&header, data)
     }
@@ -204,6 +330,7 @@ impl<W: Write> Builder<W> {
     }
 
     fn _append_link(&mut self, header: &mut Header, path: &Path, target: &Path) -> io::Result<()> {
+        append_pax(self.obj.as_mut().unwrap(), &mut self.pax)?;
         prepare_header_path(self.get_mut(), header, path)?;
         prepare_header_link(self.get_mut(), header, target)?;
         header.set_cksum();
@@ -237,7 +364,16 @@ impl<W: Write> Builder<W> {
     pub fn append_path<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
         let mode = self.mode.clone();
         let follow = self.follow;
-        append_path_with_name(self.get_mut(), path.as_ref(), None, mode, follow)
+        let acls = self.acls;
+        append_path_with_name(
+            self.obj.as_mut().unwrap(),
+            path.as_ref(),
+            None,
+            mode,
+            follow,
+            acls,
+            &mut self.pax,
+        )
     }
 
     /// Adds a file on the local filesystem to this archive under another name.
@@ -275,12 +411,15 @@ impl<W: Write> Builder<W> {
     ) -> io::Result<()> {
         let mode = self.mode.clone();
         let follow = self.follow;
+        let acls = self.acls;
         append_path_with_name(
-            self.get_mut(),
+            self.obj.as_mut().unwrap(),
             path.as_ref(),
             Some(name.as_ref()),
             mode,
             follow,
+            acls,
+            &mut self.pax,
         )
     }
 
@@ -312,7 +451,15 @@ impl<W: Write> Builder<W> {
     /// ```
     pub fn append_file<P: AsRef<Path>>(&mut self, path: P, file: &mut fs::File) -> io::Result<()> {
         let mode = self.mode.clone();
-        append_file(self.get_mut(), path.as_ref(), file, mode)
+        let acls = self.acls;
+        append_file(
+            self.obj.as_mut().unwrap(),
+            path.as_ref(),
+            file,
+            mode,
+            acls,
+            &mut self.pax,
+        )
     }
 
     /// Adds a directory to this archive with the given path as the name of the
@@ -349,7 +496,15 @@ impl<W: Write> Builder<W> {
         Q: AsRef<Path>,
     {
         let mode = self.mode.clone();
-        append_dir(self.get_mut(), path.as_ref(), src_path.as_ref(), mode)
+        let acls = self.acls;
+        append_dir(
+            self.obj.as_mut().unwrap(),
+            path.as_ref(),
+            src_path.as_ref(),
+            mode,
+            acls,
+            &mut self.pax,
+        )
     }
 
     /// Adds a directory and all of its contents (recursively) to this archive
@@ -381,12 +536,15 @@ impl<W: Write> Builder<W> {
     {
         let mode = self.mode.clone();
         let follow = self.follow;
+        let acls = self.acls;
         append_dir_all(
-            self.get_mut(),
+            self.obj.as_mut().unwrap(),
             path.as_ref(),
             src_path.as_ref(),
             mode,
             follow,
+            acls,
+            &mut self.pax,
         )
     }
 
@@ -406,6 +564,144 @@ impl<W: Write> Builder<W> {
     }
 }
 
+impl<W: Read + Write + Seek> Builder<W> {
+    /// Create an archive builder which appends entries to the archive that
+    /// `obj` holds, like `tar -r`.
+    ///
+    /// The archive is expected to start at the current position of `obj`, and
+    /// to be uncompressed. Its entries are read through to find where the
+    /// blocks of zeros which end it start, which is where new entries are
+    /// written, over them. An archive missing these blocks is appended to at
+    /// the end of `obj`, and an empty `obj` becomes a new archive.
+    ///
+    /// Note that `obj` must not be a file opened in append mode, as writes to
+    /// it would then always happen at its end, after the old blocks of zeros.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive can't be read, or if it ends in the
+    /// middle of an entry.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::OpenOptions;
+    /// use tar::Builder;
+    ///
+    /// let file = OpenOptions::new()
+    ///     .read(true)
+    ///     .write(true)
+    ///     .create(true)
+    ///     .open("logs.tar")
+    ///     .unwrap();
+    /// let mut ar = Builder::append_to(file).unwrap();
+    /// ar.append_path("app.log").unwrap();
+    /// ar.finish().unwrap();
+    /// ```
+    pub fn append_to(mut obj: W) -> io::Result<Builder<W>> {
+        let end = append_position(&mut obj)?;
+        obj.seek(SeekFrom::Start(end))?;
+        Ok(Builder::new(obj))
+    }
+}
+
+/// A builder which compresses the archive it builds.
+///
+/// All the methods of [`Builder`] can be used through it, but unlike
+/// [`Builder::finish`] its own [`finish`](CompressedBuilder::finish) also
+/// completes the compressed stream, which is only complete once it has been
+/// called. Dropping the builder without calling it doesn't report errors and
+/// may leave the stream incomplete.
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+pub struct CompressedBuilder<W: Write> {
+    builder: Builder<Compressor<W>>,
+}
+
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+impl<W: Write> CompressedBuilder<W> {
+    /// Create a new archive builder which compresses the archive with gzip
+    /// at `level`, from 0 (no compression) to 9 (best compression), into
+    /// `obj`.
+    ///
+    /// This requires the `flate2` feature of this crate.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use tar::CompressedBuilder;
+    ///
+    /// let file = File::create("foo.tar.gz").unwrap();
+    /// let mut ar = CompressedBuilder::new_gzip(file, 6).unwrap();
+    /// ar.append_path("foo.txt").unwrap();
+    /// let file = ar.finish().unwrap();
+    /// ```
+    #[cfg(feature = "flate2")]
+    pub fn new_gzip(obj: W, level: u32) -> io::Result<CompressedBuilder<W>> {
+        Ok(CompressedBuilder::new(Compressor::gzip(obj, level)?))
+    }
+
+    /// Create a new archive builder which compresses the archive with xz at
+    /// preset `level`, from 0 to 9, into `obj`.
+    ///
+    /// This requires the `xz2` feature of this crate.
+    #[cfg(feature = "xz2")]
+    pub fn new_xz(obj: W, level: u32) -> io::Result<CompressedBuilder<W>> {
+        Ok(CompressedBuilder::new(Compressor::xz(obj, level)?))
+    }
+
+    /// Create a new archive builder which compresses the archive with zstd
+    /// at `level` into `obj`, where 0 selects zstd's default level.
+    ///
+    /// This requires the `zstd` feature of this crate.
+    #[cfg(feature = "zstd")]
+    pub fn new_zstd(obj: W, level: i32) -> io::Result<CompressedBuilder<W>> {
+        Ok(CompressedBuilder::new(Compressor::zstd(obj, level)?))
+    }
+
+    fn new(obj: Compressor<W>) -> CompressedBuilder<W> {
+        CompressedBuilder {
+            builder: Builder::new(obj),
+        }
+    }
+
+    /// Finish writing this archive, then complete the compressed stream and
+    /// flush it, returning the underlying object.
+    ///
+    /// Errors which happen while writing the termination sections or
+    /// finishing the compression are all returned.
+    pub fn finish(self) -> io::Result<W> {
+        self.builder.into_inner()?.finish()
+    }
+}
+
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+impl<W: Write> Deref for CompressedBuilder<W> {
+    type Target = Builder<Compressor<W>>;
+
+    fn deref(&self) -> &Builder<Compressor<W>> {
+        &self.builder
+    }
+}
+
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+impl<W: Write> DerefMut for CompressedBuilder<W> {
+    fn deref_mut(&mut self) -> &mut Builder<Compressor<W>> {
+        &mut self.builder
+    }
+}
+
+/// Returns the position in `obj` where the entries appended to the archive
+/// which starts at its current position go.
+fn append_position(obj: &mut dyn SeekRead) -> io::Result<u64> {
+    let start = obj.stream_position()?;
+    let end = start + Archive::new(&mut *obj).end_position()?;
+    if end > obj.seek(SeekFrom::End(0))? {
+        return Err(other("archive ends in the middle of an entry"));
+    }
+    Ok(end)
+}
+
 fn append(mut dst: &mut dyn Write, header: &Header, mut data: &mut dyn Read) -> io::Result<()> {
     dst.write_all(header.as_bytes())?;
     let len = io::copy(&mut data, &mut dst)?;
@@ -426,27 +722,26 @@ fn append_path_with_name(
     name: Option<&Path>,
     mode: HeaderMode,
     follow: bool,
+    acls: bool,
+    pax: &mut PaxBuilder,
 ) -> io::Result<()> {
     let stat = if follow {
-        fs::metadata(path).map_err(|err| {
-            io::Error::new(
-                err.kind(),
-                format!("{} when getting metadata for {}", err, path.display()),
-            )
-        })?
+        fs::metadata(path).map_err(|err| metadata_error(err, path))?
     } else {
-        fs::symlink_metadata(path).map_err(|err| {
-            io::Error::new(
-                err.kind(),
-                format!("{} when getting metadata for {}", err, path.display()),
-            )
-        })?
+        fs::symlink_metadata(path).map_err(|err| metadata_error(err, path))?
     };
     let ar_name = name.unwrap_or(path);
     if stat.is_file() {
-        append_fs(dst, ar_name, &stat, &mut fs::File::open(path)?, mode, None)
+        if acls {
+            append_acls(pax, AclSource::Path(path), false)?;
+        }
+        let mut file = fs::File::open(path)?;
+        append_fs(dst, ar_name, &stat, &mut file, mode, None, pax)
     } else if stat.is_dir() {
-        append_fs(dst, ar_name, &stat, &mut io::empty(), mode, None)
+        if acls {
+            append_acls(pax, AclSource::Path(path), true)?;
+        }
+        append_fs(dst, ar_name, &stat, &mut io::empty(), mode, None, pax)
     } else if stat.file_type().is_symlink() {
         let link_name = fs::read_link(path)?;
         append_fs(
@@ -456,11 +751,12 @@ fn append_path_with_name(
             &mut io::empty(),
             mode,
             Some(&link_name),
+            pax,
         )
     } else {
         #[cfg(unix)]
         {
-            append_special(dst, path, &stat, mode)
+            append_special(dst, path, &stat, mode, pax)
         }
         #[cfg(not(unix))]
         {
@@ -469,12 +765,21 @@ fn append_path_with_name(
     }
 }
 
+/// Adds the path whose metadata couldn't be read to `err`.
+pub(crate) fn metadata_error(err: io::Error, path: &Path) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        format!("{} when getting metadata for {}", err, path.display()),
+    )
+}
+
 #[cfg(unix)]
-fn append_special(
+pub(crate) fn append_special(
     dst: &mut dyn Write,
     path: &Path,
     stat: &fs::Metadata,
     mode: HeaderMode,
+    pax: &mut PaxBuilder,
 ) -> io::Result<()> {
     use ::std::os::unix::fs::{FileTypeExt, MetadataExt};
 
@@ -498,6 +803,7 @@ fn append_special(
 
     let mut header = Header::new_gnu();
     header.set_metadata_in_mode(stat, mode);
+    append_pax(dst, pax)?;
     prepare_header_path(dst, &mut header, path)?;
 
     header.set_entry_type(entry_type);
@@ -518,9 +824,14 @@ fn append_file(
     path: &Path,
     file: &mut fs::File,
     mode: HeaderMode,
+    acls: bool,
+    pax: &mut PaxBuilder,
 ) -> io::Result<()> {
     let stat = file.metadata()?;
-    append_fs(dst, path, &stat, file, mode, None)
+    if acls {
+        append_acls(pax, AclSource::File(file), stat.is_dir())?;
+    }
+    append_fs(dst, path, &stat, file, mode, None, pax)
 }
 
 fn append_dir(
@@ -528,9 +839,142 @@ fn append_dir(
     path: &Path,
     src_path: &Path,
     mode: HeaderMode,
+    acls: bool,
+    pax: &mut PaxBuilder,
 ) -> io::Result<()> {
     let stat = fs::metadata(src_path)?;
-    append_fs(dst, path, &stat, &mut io::empty(), mode, None)
+    if acls {
+        append_acls(pax, AclSource::Path(src_path), true)?;
+    }
+    append_fs(dst, path, &stat, &mut io::empty(), mode, None, pax)
+}
+
+/// Where the ACLs of an entry are read from.
+#[cfg_attr(not(all(feature = "acl", target_os = "linux")), allow(dead_code))]
+enum AclSource<'a> {
+    Path(&'a Path),
+    File(&'a fs::File),
+}
+
+/// Adds the POSIX ACLs of `src` to the pax records of the next entry, if it
+/// has any besides its permission bits.
+#[cfg(all(feature = "acl", target_os = "linux"))]
+fn append_acls(pax: &mut PaxBuilder, src: AclSource<'_>, is_dir: bool) -> io::Result<()> {
+    use crate::acl;
+    use xattr::FileExt;
+
+    let get = |name: &str| match src {
+        AclSource::Path(path) => xattr::get(path, name),
+        AclSource::File(file) => file.get_xattr(name),
+    };
+    let mut names = vec![(acl::PAX_ACL_ACCESS, acl::XATTR_ACL_ACCESS)];
+    if is_dir {
+        names.push((acl::PAX_ACL_DEFAULT, acl::XATTR_ACL_DEFAULT));
+    }
+    for (key, name) in names {
+        let value = match get(name) {
+            Ok(Some(value)) => value,
+            // Filesystems without ACL support, and files with only their
+            // permission bits, have no ACL attributes.
+            Ok(None) => continue,
+            Err(ref e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => continue,
+            Err(e) => return Err(e),
+        };
+        pax.add(key, &acl::to_text(&acl::from_xattr(&value)?));
+    }
+    Ok(())
+}
+
+#[cfg(not(all(feature = "acl", target_os = "linux")))]
+fn append_acls(_: &mut PaxBuilder, _: AclSource<'_>, _: bool) -> io::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn prepare_owner_names(
+    pax: &mut PaxBuilder,
+    header: &mut Header,
+    username: &str,
+    groupname: &str,
+) -> io::Result<()> {
+    if username.contains('\0') || groupname.contains('\0') {
+        return Err(other("owner name contains a nul byte"));
+    }
+    if header.as_ustar().is_none() && header.as_gnu().is_none() {
+        return Err(other("not a ustar or gnu archive, cannot set owner names"));
+    }
+    // Both the ustar and GNU formats have 32 byte name fields
+    let fit = |name: &str| {
+        let mut len = name.len().min(32);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        len
+    };
+    if username.len() > 32 || !username.is_ascii() {
+        pax.add(pax::PAX_UNAME, username);
+    }
+    if groupname.len() > 32 || !groupname.is_ascii() {
+        pax.add(pax::PAX_GNAME, groupname);
+    }
+    header.set_username(&username[..fit(username)])?;
+    header.set_groupname(&groupname[..fit(groupname)])
+}
+
+/// Adds `records` to `pax`, validating their keys.
+pub(crate) fn pax_records(
+    pax: &mut PaxBuilder,
+    records: &mut dyn Iterator<Item = (&str, &[u8])>,
+) -> io::Result<()> {
+    for (key, value) in records {
+        if !pax::valid_key(key) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid pax extension key {:?}", key),
+            ));
+        }
+        pax.add_bytes(key, value);
+    }
+    Ok(())
+}
+
+/// Appends a global pax extended header holding `records`.
+pub(crate) fn append_pax_global(
+    dst: &mut dyn Write,
+    records: &mut dyn Iterator<Item = (&str, &[u8])>,
+) -> io::Result<()> {
+    let mut pax = PaxBuilder::new();
+    pax_records(&mut pax, records)?;
+    append_pax_header(
+        dst,
+        "pax_global_header",
+        EntryType::XGlobalHeader,
+        pax.as_bytes(),
+    )
+}
+
+/// Appends a pax extended header holding the pending records in `pax`, which
+/// applies to the entry appended after it, if there are any.
+pub(crate) fn append_pax(dst: &mut dyn Write, pax: &mut PaxBuilder) -> io::Result<()> {
+    if pax.is_empty() {
+        return Ok(());
+    }
+    let pax = mem::take(pax);
+    append_pax_header(dst, "././@PaxHeader", EntryType::XHeader, pax.as_bytes())
+}
+
+fn append_pax_header(
+    dst: &mut dyn Write,
+    name: &str,
+    entry_type: EntryType,
+    data: &[u8],
+) -> io::Result<()> {
+    let mut header = Header::new_ustar();
+    header.set_path(name)?;
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    header.set_entry_type(entry_type);
+    header.set_cksum();
+    append(dst, &header, &mut &data[..])
 }
 
 fn prepare_header(size: u64, entry_type: u8) -> Header {
@@ -548,7 +992,11 @@ fn prepare_header(size: u64, entry_type: u8) -> Header {
     header
 }
 
-fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) -> io::Result<()> {
+pub(crate) fn prepare_header_path(
+    dst: &mut dyn Write,
+    header: &mut Header,
+    path: &Path,
+) -> io::Result<()> {
     // Try to encode the path directly in the header, but if it ends up not
     // working (probably because it's too long) then try to use the GNU-specific
     // long name extension by emitting an entry which indicates that it's the
@@ -580,7 +1028,7 @@ fn prepare_header_path(dst: &mut dyn Write, header: &mut Header, path: &Path) ->
     Ok(())
 }
 
-fn prepare_header_link(
+pub(crate) fn prepare_header_link(
     dst: &mut dyn Write,
     header: &mut Header,
     link_name: &Path,
@@ -605,16 +1053,32 @@ fn append_fs(
     read: &mut dyn Read,
     mode: HeaderMode,
     link_name: Option<&Path>,
+    pax: &mut PaxBuilder,
 ) -> io::Result<()> {
+    let header = prepare_fs_header(dst, path, meta, mode, link_name, pax)?;
+    append(dst, &header, read)
+}
+
+/// Builds the header of an entry for a file on the local filesystem, writing
+/// the pax and GNU long name members which precede it to `dst`.
+pub(crate) fn prepare_fs_header(
+    dst: &mut dyn Write,
+    path: &Path,
+    meta: &fs::Metadata,
+    mode: HeaderMode,
+    link_name: Option<&Path>,
+    pax: &mut PaxBuilder,
+) -> io::Result<Header> {
     let mut header = Header::new_gnu();
 
+    append_pax(dst, pax)?;
     prepare_header_path(dst, &mut header, path)?;
     header.set_metadata_in_mode(meta, mode);
     if let Some(link_name) = link_name {
         prepare_header_link(dst, &mut header, link_name)?;
     }
     header.set_cksum();
-    append(dst, &header, read)
+    Ok(header)
 }
 
 fn append_dir_all(
@@ -623,6 +1087,51 @@ fn append_dir_all(
     src_path: &Path,
     mode: HeaderMode,
     follow: bool,
+    acls: bool,
+    pax: &mut PaxBuilder,
+) -> io::Result<()> {
+    walk_dir_all(path, src_path, follow, &mut |dest, src, kind| match kind {
+        WalkedPath::Dir => append_dir(dst, dest, src, mode, acls, pax),
+        WalkedPath::Symlink => {
+            let stat = fs::symlink_metadata(src)?;
+            let link_name = fs::read_link(src)?;
+            append_fs(
+                dst,
+                dest,
+                &stat,
+                &mut io::empty(),
+                mode,
+                Some(&link_name),
+                pax,
+            )
+        }
+        WalkedPath::File => append_file(dst, dest, &mut fs::File::open(src)?, mode, acls, pax),
+        #[cfg(unix)]
+        WalkedPath::Special(stat) => append_special(dst, dest, &stat, mode, pax),
+    })
+}
+
+/// How a path found by `walk_dir_all` is added to the archive.
+pub(crate) enum WalkedPath {
+    /// A directory, without its contents.
+    Dir,
+    /// A symlink which isn't followed.
+    Symlink,
+    /// A regular file, possibly through a symlink which is followed.
+    File,
+    /// A fifo or a device, along with its metadata.
+    #[cfg(unix)]
+    Special(fs::Metadata),
+}
+
+/// Walks the directory `src_path` recursively for `append_dir_all`, calling
+/// `f` with the path in the archive, the path on the local filesystem and the
+/// kind of every path found.
+pub(crate) fn walk_dir_all(
+    path: &Path,
+    src_path: &Path,
+    follow: bool,
+    f: &mut dyn FnMut(&Path, &Path, WalkedPath) -> io::Result<()>,
 ) -> io::Result<()> {
     let mut stack = vec![(src_path.to_path_buf(), true, false)];
     while let Some((src, is_dir, is_symlink)) = stack.pop() {
@@ -635,22 +1144,20 @@ fn append_dir_all(
                 stack.push((entry.path(), file_type.is_dir(), file_type.is_symlink()));
             }
             if dest != Path::new("") {
-                append_dir(dst, &dest, &src, mode)?;
+                f(&dest, &src, WalkedPath::Dir)?;
             }
         } else if !follow && is_symlink {
-            let stat = fs::symlink_metadata(&src)?;
-            let link_name = fs::read_link(&src)?;
-            append_fs(dst, &dest, &stat, &mut io::empty(), mode, Some(&link_name))?;
+            f(&dest, &src, WalkedPath::Symlink)?;
         } else {
             #[cfg(unix)]
             {
                 let stat = fs::metadata(&src)?;
                 if !stat.is_file() {
-                    append_special(dst, &dest, &stat, mode)?;
+                    f(&dest, &src, WalkedPath::Special(stat))?;
                     continue;
                 }
             }
-            append_file(dst, &dest, &mut fs::File::open(src)?, mode)?;
+            f(&dest, &src, WalkedPath::File)?;
         }
     }
     Ok(())
@@ -0,0 +1,275 @@
+use std::io;
+use std::io::prelude::*;
+
+use crate::other;
+use crate::Header;
+
+/// The compression format of an archive.
+///
+/// Archives can be decompressed when the matching feature of this crate is
+/// enabled, and compressed with the `flate2`, `xz2` and `zstd` features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// The archive isn't compressed.
+    None,
+    /// gzip, with the `flate2` feature.
+    Gzip,
+    /// bzip2, with the `bzip2` feature.
+    Bzip2,
+    /// xz, with the `xz2` feature.
+    Xz,
+    /// Zstandard, with the `zstd` feature, or the pure-Rust decoder of the
+    /// `ruzstd` feature if only that one is enabled.
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression format of an archive from its first bytes.
+    ///
+    /// A whole first block which holds a valid tar header is never taken for
+    /// a compressed stream, whatever the path of its entry begins with.
+    pub fn detect(bytes: &[u8]) -> Compression {
+        if bytes.len() >= 512 {
+            let header = Header::from_byte_slice(&bytes[..512]);
+            if header.cksum().ok() == Some(header.calculate_cksum()) {
+                return Compression::None;
+            }
+        }
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Compression::Xz
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Bzip2 => "bzip2",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// A reader which decompresses an archive in the format detected from its
+/// first bytes.
+///
+/// This is the reader of the archives created by
+/// [`Archive::open_auto`](crate::Archive::open_auto). Concatenated gzip,
+/// bzip2, xz and zstd streams are all decompressed, except with the `ruzstd`
+/// decoder, which only reads the first zstd frame.
+pub struct Decompressor<R: Read> {
+    compression: Compression,
+    decoder: Decoder<R>,
+}
+
+/// The first bytes read from a reader, followed by the rest of it.
+type Prefixed<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+enum Decoder<R: Read> {
+    None(Prefixed<R>),
+    #[cfg(feature = "flate2")]
+    Gzip(flate2::read::MultiGzDecoder<Prefixed<R>>),
+    #[cfg(feature = "bzip2")]
+    Bzip2(bzip2::read::MultiBzDecoder<Prefixed<R>>),
+    #[cfg(feature = "xz2")]
+    Xz(xz2::read::XzDecoder<Prefixed<R>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::read::Decoder<'static, io::BufReader<Prefixed<R>>>),
+    #[cfg(all(feature = "ruzstd", not(feature = "zstd")))]
+    Zstd(Box<ruzstd::decoding::StreamingDecoder<Prefixed<R>, ruzstd::decoding::FrameDecoder>>),
+}
+
+impl<R: Read> Decompressor<R> {
+    /// Reads the first bytes of `obj` to detect its compression format, and
+    /// wraps it in the matching decoder.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading fails, or if the archive is compressed in
+    /// a format whose decoder isn't enabled.
+    pub fn new(mut obj: R) -> io::Result<Decompressor<R>> {
+        let mut prefix = Vec::with_capacity(512);
+        (&mut obj).take(512).read_to_end(&mut prefix)?;
+        let compression = Compression::detect(&prefix);
+        let src = io::Cursor::new(prefix).chain(obj);
+        let decoder = match compression {
+            Compression::None => Decoder::None(src),
+            #[cfg(feature = "flate2")]
+            Compression::Gzip => Decoder::Gzip(flate2::read::MultiGzDecoder::new(src)),
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => Decoder::Bzip2(bzip2::read::MultiBzDecoder::new(src)),
+            #[cfg(feature = "xz2")]
+            Compression::Xz => Decoder::Xz(xz2::read::XzDecoder::new_multi_decoder(src)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Decoder::Zstd(zstd::stream::read::Decoder::new(src)?),
+            #[cfg(all(feature = "ruzstd", not(feature = "zstd")))]
+            Compression::Zstd => Decoder::Zstd(Box::new(
+                ruzstd::decoding::StreamingDecoder::new(src)
+                    .map_err(|e| other(&format!("invalid zstd frame: {}", e)))?,
+            )),
+            #[allow(unreachable_patterns)]
+            compression => {
+                return Err(other(&format!(
+                    "archive is compressed with {}, which this build of tar \
+                     can't decompress",
+                    compression.name()
+                )))
+            }
+        };
+        Ok(Decompressor {
+            compression,
+            decoder,
+        })
+    }
+
+    /// Returns the compression format which was detected.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+}
+
+impl<R: Read> Read for Decompressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.decoder {
+            Decoder::None(r) => r.read(buf),
+            #[cfg(feature = "flate2")]
+            Decoder::Gzip(r) => r.read(buf),
+            #[cfg(feature = "bzip2")]
+            Decoder::Bzip2(r) => r.read(buf),
+            #[cfg(feature = "xz2")]
+            Decoder::Xz(r) => r.read(buf),
+            #[cfg(any(feature = "zstd", feature = "ruzstd"))]
+            Decoder::Zstd(r) => r.read(buf),
+        }
+    }
+}
+
+/// A writer which compresses an archive, created by the constructors of
+/// [`CompressedBuilder`](crate::CompressedBuilder) such as
+/// [`CompressedBuilder::new_gzip`](crate::CompressedBuilder::new_gzip).
+///
+/// The compressed stream is only complete once
+/// [`finish`](Compressor::finish) has been called.
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+pub struct Compressor<W: Write> {
+    encoder: Encoder<W>,
+}
+
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+enum Encoder<W: Write> {
+    #[cfg(feature = "flate2")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "xz2")]
+    Xz(xz2::write::XzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+impl<W: Write> Compressor<W> {
+    /// Compresses with gzip at `level`, from 0 to 9.
+    #[cfg(feature = "flate2")]
+    pub(crate) fn gzip(obj: W, level: u32) -> io::Result<Compressor<W>> {
+        if level > 9 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid gzip compression level {}", level),
+            ));
+        }
+        let level = flate2::Compression::new(level);
+        Ok(Compressor {
+            encoder: Encoder::Gzip(flate2::write::GzEncoder::new(obj, level)),
+        })
+    }
+
+    /// Compresses with xz at preset `level`, from 0 to 9.
+    #[cfg(feature = "xz2")]
+    pub(crate) fn xz(obj: W, level: u32) -> io::Result<Compressor<W>> {
+        let stream = xz2::stream::Stream::new_easy_encoder(level, xz2::stream::Check::Crc64)?;
+        Ok(Compressor {
+            encoder: Encoder::Xz(xz2::write::XzEncoder::new_stream(obj, stream)),
+        })
+    }
+
+    /// Compresses with zstd at `level`, where 0 is zstd's default level.
+    #[cfg(feature = "zstd")]
+    pub(crate) fn zstd(obj: W, level: i32) -> io::Result<Compressor<W>> {
+        Ok(Compressor {
+            encoder: Encoder::Zstd(zstd::stream::write::Encoder::new(obj, level)?),
+        })
+    }
+
+    /// Returns the compression format of this writer.
+    pub fn compression(&self) -> Compression {
+        match self.encoder {
+            #[cfg(feature = "flate2")]
+            Encoder::Gzip(_) => Compression::Gzip,
+            #[cfg(feature = "xz2")]
+            Encoder::Xz(_) => Compression::Xz,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(_) => Compression::Zstd,
+        }
+    }
+
+    /// Gets shared reference to the underlying object.
+    pub fn get_ref(&self) -> &W {
+        match &self.encoder {
+            #[cfg(feature = "flate2")]
+            Encoder::Gzip(w) => w.get_ref(),
+            #[cfg(feature = "xz2")]
+            Encoder::Xz(w) => w.get_ref(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(w) => w.get_ref(),
+        }
+    }
+
+    /// Completes the compressed stream, and flushes and returns the
+    /// underlying object.
+    pub fn finish(self) -> io::Result<W> {
+        let mut obj = match self.encoder {
+            #[cfg(feature = "flate2")]
+            Encoder::Gzip(w) => w.finish()?,
+            #[cfg(feature = "xz2")]
+            Encoder::Xz(w) => w.finish()?,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(w) => w.finish()?,
+        };
+        obj.flush()?;
+        Ok(obj)
+    }
+}
+
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            #[cfg(feature = "flate2")]
+            Encoder::Gzip(w) => w.write(buf),
+            #[cfg(feature = "xz2")]
+            Encoder::Xz(w) => w.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            #[cfg(feature = "flate2")]
+            Encoder::Gzip(w) => w.flush(),
+            #[cfg(feature = "xz2")]
+            Encoder::Xz(w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(w) => w.flush(),
+        }
+    }
+}
@@ -1,19 +1,19 @@
 use std::borrow::Cow;
 use std::cmp;
-use std::fs;
-use std::fs::OpenOptions;
+use std::fmt;
 use std::io::prelude::*;
 use std::io::{self, Error, ErrorKind, SeekFrom};
 use std::marker;
 use std::path::{Component, Path, PathBuf};
+use std::str;
 
-use filetime::{self, FileTime};
-
-use crate::archive::ArchiveInner;
+use crate::archive::{ArchiveInner, SeekRead};
 use crate::error::TarError;
 use crate::header::bytes2path;
 use crate::other;
-use crate::{Archive, Header, PaxExtensions};
+use crate::pax::{self, PAX_ATIME, PAX_LIBARCHIVEXATTR, PAX_SCHILYXATTR};
+use crate::target::{FsTarget, UnpackTarget};
+use crate::{Archive, Header, PaxExtensions, PaxMap};
 
 /// A read-only view into an entry of an archive.
 ///
@@ -31,22 +31,290 @@ pub struct EntryFields<'a> {
     pub long_pathname: Option<Vec<u8>>,
     pub long_linkname: Option<Vec<u8>>,
     pub pax_extensions: Option<Vec<u8>>,
+    pub pax: Option<PaxMap>,
     pub mask: u32,
     pub header: Header,
     pub size: u64,
     pub header_pos: u64,
     pub file_pos: u64,
     pub data: Vec<EntryIo<'a>>,
+    pub regions: Vec<Region>,
+    pub read_pos: u64,
+    pub stored_size: u64,
+    pub sparse: bool,
+    pub archive: Option<&'a ArchiveInner<dyn Read + 'a>>,
+    pub seekable_archive: Option<&'a ArchiveInner<dyn SeekRead + 'a>>,
     pub unpack_xattrs: bool,
+    pub xattr_filter: XattrFilter,
+    pub xattr_best_effort: bool,
+    pub xattr_warnings: Vec<XattrWarning>,
+    pub unpack_acls: bool,
     pub preserve_permissions: bool,
     pub preserve_ownerships: bool,
     pub preserve_mtime: bool,
-    pub overwrite: bool,
+    pub preserve_atime: bool,
+    pub overwrite: OverwritePolicy,
+    pub atomic: bool,
+    pub durability: Durability,
 }
 
 pub enum EntryIo<'a> {
     Pad(io::Take<io::Repeat>),
     Data(io::Take<&'a ArchiveInner<dyn Read + 'a>>),
+    Buffer(io::Cursor<Vec<u8>>),
+    Reader(io::Take<Box<dyn Read + 'a>>),
+}
+
+// A contiguous region of the contents of an entry, which is either stored in
+// the archive at `archive_pos` or is a hole of zeros in a sparse file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub offset: u64,
+    pub len: u64,
+    pub archive_pos: Option<u64>,
+}
+
+// The metadata of an entry detached from the archive it was read from, for
+// entries whose contents are read without going through an `Archive`. Paths
+// are resolved from long names and pax records already.
+#[derive(Clone)]
+pub struct EntryMeta {
+    pub header: Header,
+    pub path: Vec<u8>,
+    pub link_name: Option<Vec<u8>>,
+    pub pax: Option<PaxMap>,
+    pub size: u64,
+    pub header_pos: u64,
+    pub file_pos: u64,
+    pub regions: Vec<Region>,
+    pub sparse: bool,
+}
+
+impl EntryMeta {
+    pub fn sparse_map(&self) -> Option<SparseMap> {
+        if self.sparse {
+            Some(SparseMap::new(&self.regions, self.size))
+        } else {
+            None
+        }
+    }
+}
+
+/// Configuration of what happens when unpacking an entry to a path which
+/// already exists.
+///
+/// This applies to regular files, symlinks, hard links, and to directories
+/// which are in the way of a file or vice versa. Existing directories are
+/// always reused when unpacking a directory entry on top of them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum OverwritePolicy {
+    /// Fail with an error of kind `AlreadyExists`, like `--keep-old-files`
+    /// in GNU tar.
+    KeepOld,
+
+    /// Silently leave the existing path alone and skip the entry, like
+    /// `--skip-old-files` in GNU tar.
+    SkipOld,
+
+    /// Skip the entry if the existing path was modified more recently than
+    /// the entry's mtime, otherwise replace it, like `--keep-newer-files` in
+    /// GNU tar.
+    KeepNewer,
+
+    /// Unlink the existing path and replace it. Directories in the way are
+    /// only removed if they are empty.
+    ///
+    /// This is the default.
+    Unlink,
+
+    /// Like `Unlink`, but directories in the way are removed along with all
+    /// of their contents, like `--recursive-unlink` in GNU tar.
+    RecursiveUnlink,
+}
+
+// What the overwrite policy says to do about a path which already exists.
+pub enum Replace {
+    Fail,
+    Keep,
+    Unlink,
+    UnlinkRecursive,
+}
+
+// How an entry is unpacked, according to its type.
+pub enum UnpackKind {
+    Dir,
+    HardLink,
+    Symlink,
+    File,
+    // Extension members, which aren't unpacked at all.
+    Skip,
+}
+
+// What became of an entry unpacked onto a target, along with the file which
+// was written if it's a file.
+pub enum Outcome<F> {
+    Written(Option<F>),
+    Kept,
+}
+
+/// Configuration of how much effort is spent to ensure that unpacked data has
+/// reached stable storage.
+///
+/// Without syncing, a power loss shortly after unpacking has succeeded may
+/// still lose the data or leave empty files behind.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Durability {
+    /// Leave it up to the operating system when data is written back.
+    ///
+    /// This is the default.
+    Unsynced,
+
+    /// Sync the contents and metadata of each regular file before it is
+    /// closed.
+    SyncFiles,
+
+    /// Sync each regular file like `SyncFiles`, and additionally sync every
+    /// directory which had entries created in it, so that the names of the
+    /// unpacked files are durable as well.
+    ///
+    /// When unpacking an entire archive the directories are synced once at
+    /// the end rather than after each entry. This is currently only
+    /// implemented on Unix, where directories can be synced.
+    SyncAll,
+}
+
+/// A selection of which extended attributes are restored when unpacking.
+///
+/// Attributes are selected by namespace, such as `user` or `trusted`, or by a
+/// longer prefix of their name, such as `security.selinux`. A prefix matches
+/// an attribute if it is the attribute's name or is followed by a `.` in it.
+///
+/// If any namespaces are included then only attributes matching one of them
+/// are restored, otherwise all of them are. Attributes matching an excluded
+/// namespace are never restored.
+///
+/// # Examples
+///
+/// ```
+/// use tar::XattrFilter;
+///
+/// let mut filter = XattrFilter::new();
+/// filter.include("user").include("security").exclude("security.selinux");
+/// assert!(filter.matches(b"user.comment"));
+/// assert!(filter.matches(b"security.capability"));
+/// assert!(!filter.matches(b"security.selinux"));
+/// assert!(!filter.matches(b"trusted.overlay.opaque"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XattrFilter {
+    include: Vec<Vec<u8>>,
+    exclude: Vec<Vec<u8>>,
+}
+
+impl XattrFilter {
+    /// Creates a filter which matches all attributes.
+    pub fn new() -> XattrFilter {
+        XattrFilter::default()
+    }
+
+    /// Restricts the restored attributes to those in `namespace` and any
+    /// other included namespaces.
+    pub fn include(&mut self, namespace: &str) -> &mut XattrFilter {
+        self.include.push(namespace.as_bytes().to_vec());
+        self
+    }
+
+    /// Excludes the attributes in `namespace` from being restored.
+    pub fn exclude(&mut self, namespace: &str) -> &mut XattrFilter {
+        self.exclude.push(namespace.as_bytes().to_vec());
+        self
+    }
+
+    /// Returns whether the attribute called `name` is restored by this
+    /// filter.
+    pub fn matches(&self, name: &[u8]) -> bool {
+        fn in_namespace(name: &[u8], namespace: &[u8]) -> bool {
+            name.starts_with(namespace)
+                && (name.len() == namespace.len() || name[namespace.len()] == b'.')
+        }
+
+        (self.include.is_empty() || self.include.iter().any(|ns| in_namespace(name, ns)))
+            && !self.exclude.iter().any(|ns| in_namespace(name, ns))
+    }
+}
+
+/// The layout of the contents of a sparse file, as returned by
+/// [`Entry::sparse_map`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SparseMap {
+    data: Vec<(u64, u64)>,
+    real_size: u64,
+}
+
+impl SparseMap {
+    pub(crate) fn new(regions: &[Region], real_size: u64) -> SparseMap {
+        SparseMap {
+            data: regions
+                .iter()
+                .filter(|r| r.archive_pos.is_some())
+                .map(|r| (r.offset, r.len))
+                .collect(),
+            real_size,
+        }
+    }
+
+    /// Returns the regions of the file which hold data, as `(offset, length)`
+    /// pairs in increasing order of offset.
+    ///
+    /// Everything outside of these regions is a hole which reads as zeros.
+    pub fn data_regions(&self) -> &[(u64, u64)] {
+        &self.data
+    }
+
+    /// Returns the size of the file, including its holes.
+    pub fn real_size(&self) -> u64 {
+        self.real_size
+    }
+}
+
+/// An extended attribute which could not be restored while unpacking in best
+/// effort mode.
+#[derive(Debug)]
+pub struct XattrWarning {
+    path: PathBuf,
+    name: Vec<u8>,
+    error: io::Error,
+}
+
+impl XattrWarning {
+    /// Returns the path of the unpacked file which is missing the attribute.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the name of the attribute.
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// Returns the reason why the attribute could not be restored.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for XattrWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed to set extended attribute {:?} on `{}`: {}",
+            String::from_utf8_lossy(&self.name),
+            self.path.display(),
+            self.error
+        )
+    }
 }
 
 /// When unpacking items the unpacked thing is returned to allow custom
@@ -56,6 +324,9 @@ pub enum EntryIo<'a> {
 pub enum Unpacked {
     /// A file was unpacked.
     File(std::fs::File),
+    /// The entry wasn't unpacked because the overwrite policy of the archive
+    /// says to keep what already exists at its location.
+    Kept,
     /// A directory, hardlink, symlink, or other node was unpacked.
     #[doc(hidden)]
     __Nonexhaustive,
@@ -114,6 +385,40 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.link_name_bytes()
     }
 
+    /// Returns the user name of the owner of this entry.
+    ///
+    /// A return value of `Ok(Some(..))` indicates that the user name was
+    /// present and was valid utf-8, `Ok(None)` indicates that the user name is
+    /// not present in this archive format, and `Err` indicates that the user
+    /// name was present but was not valid utf-8.
+    ///
+    /// Like `path`, this will not always return the same value as
+    /// `self.header().username()`, as a pax `uname` record takes precedence
+    /// over the header, which only holds names of up to 32 bytes.
+    pub fn username(&self) -> Result<Option<&str>, str::Utf8Error> {
+        self.username_bytes().map(str::from_utf8).transpose()
+    }
+
+    /// Returns the user name of the owner of this entry, in bytes, if
+    /// present.
+    pub fn username_bytes(&self) -> Option<&[u8]> {
+        self.fields.username_bytes()
+    }
+
+    /// Returns the group name of the owner of this entry.
+    ///
+    /// This behaves like `username`, taking a pax `gname` record into
+    /// account.
+    pub fn groupname(&self) -> Result<Option<&str>, str::Utf8Error> {
+        self.groupname_bytes().map(str::from_utf8).transpose()
+    }
+
+    /// Returns the group name of the owner of this entry, in bytes, if
+    /// present.
+    pub fn groupname_bytes(&self) -> Option<&[u8]> {
+        self.fields.groupname_bytes()
+    }
+
     /// Returns an iterator over the pax extensions contained in this entry.
     ///
     /// Pax extensions are a form of archive where extra metadata is stored in
@@ -136,6 +441,18 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.pax_extensions()
     }
 
+    /// Returns the pax extended header records which apply to this entry,
+    /// parsed into a map.
+    ///
+    /// Unlike `pax_extensions` this takes the records of global pax headers
+    /// earlier in the archive into account, with the entry's own records
+    /// taking precedence. `None` is returned if no records apply, and always
+    /// for entries which are pax headers themselves or which were read with
+    /// raw iteration.
+    pub fn pax(&self) -> Option<&PaxMap> {
+        self.fields.pax.as_ref()
+    }
+
     /// Returns access to the header of this entry in the archive.
     ///
     /// This provides access to the metadata for this entry in the archive.
@@ -171,6 +488,41 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.file_pos
     }
 
+    /// Returns the layout of the data and holes of this entry, if it's a
+    /// sparse file.
+    ///
+    /// Both the old GNU sparse format and the pax formats of GNU tar are
+    /// understood. For sparse files [`Entry::size`] is the real size of the
+    /// file, and reading the entry yields zeros for its holes; the map allows
+    /// recreating the holes or copying only the data instead.
+    pub fn sparse_map(&self) -> Option<SparseMap> {
+        if !self.fields.sparse {
+            return None;
+        }
+        Some(SparseMap::new(&self.fields.regions, self.fields.size))
+    }
+
+    /// Returns the metadata of this entry, detached from the archive.
+    pub(crate) fn meta(&self) -> EntryMeta {
+        EntryMeta {
+            header: self.fields.header.clone(),
+            path: self.path_bytes().into_owned(),
+            link_name: self.link_name_bytes().map(|l| l.into_owned()),
+            pax: self.fields.pax.clone(),
+            size: self.fields.size,
+            header_pos: self.fields.header_pos,
+            file_pos: self.fields.file_pos,
+            regions: self.fields.regions.clone(),
+            sparse: self.fields.sparse,
+        }
+    }
+
+    /// Returns the number of bytes the contents of this entry take up in the
+    /// archive, which differs from its size for sparse files.
+    pub(crate) fn stored_size(&self) -> u64 {
+        self.fields.stored_size
+    }
+
     /// Writes this file to the specified location.
     ///
     /// This function will write the entire contents of this file into the
@@ -178,8 +530,11 @@ impl<'a, R: Read> Entry<'a, R> {
     /// path `dst`.
     ///
     /// This function will create a file at the path `dst`, and it is required
-    /// that the intermediate directories are created. Any existing file at the
-    /// location `dst` will be overwritten.
+    /// that the intermediate directories are created. What happens to an
+    /// existing file at the location `dst` is decided by the overwrite policy
+    /// of the archive, see [`Archive::set_overwrite_policy`]. By default it
+    /// is overwritten, and if the policy says to keep it then
+    /// `Unpacked::Kept` is returned.
     ///
     /// > **Note**: This function does not have as many sanity checks as
     /// > `Archive::unpack` or `Entry::unpack_in`. As a result if you're
@@ -201,7 +556,7 @@ impl<'a, R: Read> Entry<'a, R> {
     /// }
     /// ```
     pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<Unpacked> {
-        self.fields.unpack(None, dst.as_ref())
+        self.fields.unpack_alone(dst.as_ref())
     }
 
     /// Extracts this file under the specified path, avoiding security issues.
@@ -209,8 +564,9 @@ impl<'a, R: Read> Entry<'a, R> {
     /// This function will write the entire contents of this file into the
     /// location obtained by appending the path of this file in the archive to
     /// `dst`, creating any intermediate directories if needed. Metadata will
-    /// also be propagated to the path `dst`. Any existing file at the location
-    /// `dst` will be overwritten.
+    /// also be propagated to the path `dst`. What happens to an existing file
+    /// at that location is decided by the overwrite policy of the archive,
+    /// see [`Archive::set_overwrite_policy`]. By default it is overwritten.
     ///
     /// This function carefully avoids writing outside of `dst`. If the file has
     /// a '..' in its path, this function will skip it and return false.
@@ -229,7 +585,36 @@ impl<'a, R: Read> Entry<'a, R> {
     /// }
     /// ```
     pub fn unpack_in<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<bool> {
-        self.fields.unpack_in(dst.as_ref())
+        self.fields.unpack_in(&mut FsTarget::new(), dst.as_ref())
+    }
+
+    /// Extracts this file under the specified path in `target`, avoiding
+    /// security issues.
+    ///
+    /// This behaves like [`Entry::unpack_in`], but the file and any
+    /// intermediate directories are created through `target` instead of in
+    /// the local filesystem.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use tar::{Archive, MemoryTarget};
+    ///
+    /// # let data = tar::Builder::new(Vec::new()).into_inner().unwrap();
+    /// let mut ar = Archive::new(&data[..]);
+    /// let mut target = MemoryTarget::new();
+    ///
+    /// for file in ar.entries().unwrap() {
+    ///     let mut file = file.unwrap();
+    ///     file.unpack_in_target(&mut target, "/").unwrap();
+    /// }
+    /// ```
+    pub fn unpack_in_target<T: UnpackTarget, P: AsRef<Path>>(
+        &mut self,
+        target: &mut T,
+        dst: P,
+    ) -> io::Result<bool> {
+        self.fields.unpack_in(target, dst.as_ref())
     }
 
     /// Set the mask of the permission bits when unpacking this entry.
@@ -257,6 +642,46 @@ impl<'a, R: Read> Entry<'a, R> {
         self.fields.unpack_xattrs = unpack_xattrs;
     }
 
+    /// Configure which extended attributes are restored when unpacking this
+    /// entry, if `set_unpack_xattrs` is enabled.
+    ///
+    /// All attributes are restored by default.
+    pub fn set_xattr_filter(&mut self, filter: XattrFilter) {
+        self.fields.xattr_filter = filter;
+    }
+
+    /// Indicate whether extended attributes are restored on a best effort
+    /// basis when unpacking this entry.
+    ///
+    /// Unpacking normally fails if any attribute can't be set, for example
+    /// because the filesystem doesn't support it or because setting it
+    /// requires privileges. When enabled such attributes are skipped instead,
+    /// and reported by `xattr_warnings` once this entry has been unpacked.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_xattr_best_effort(&mut self, best_effort: bool) {
+        self.fields.xattr_best_effort = best_effort;
+    }
+
+    /// Returns the extended attributes which were skipped while unpacking
+    /// this entry in best effort mode.
+    pub fn xattr_warnings(&self) -> &[XattrWarning] {
+        &self.fields.xattr_warnings
+    }
+
+    /// Indicate whether POSIX ACLs are restored when unpacking this entry.
+    ///
+    /// ACLs are read from the `SCHILY.acl.access` and `SCHILY.acl.default`
+    /// pax records written by star and GNU tar, and applied through the
+    /// `system.posix_acl_access` and `system.posix_acl_default` extended
+    /// attributes.
+    ///
+    /// This flag is disabled by default and is currently only implemented on
+    /// Linux, when the `acl` feature of this crate is enabled.
+    pub fn set_unpack_acls(&mut self, unpack_acls: bool) {
+        self.fields.unpack_acls = unpack_acls;
+    }
+
     /// Indicate whether extended permissions (like suid on Unix) are preserved
     /// when unpacking this entry.
     ///
@@ -273,6 +698,60 @@ impl<'a, R: Read> Entry<'a, R> {
     pub fn set_preserve_mtime(&mut self, preserve: bool) {
         self.fields.preserve_mtime = preserve;
     }
+
+    /// Indicate whether access time information is restored when unpacking
+    /// this entry.
+    ///
+    /// When enabled the access time recorded in the archive, in pax
+    /// extensions or a GNU header, is restored. If the archive doesn't record
+    /// an access time for this entry then its modification time is used
+    /// instead. Otherwise the access time is set to the modification time if
+    /// that is preserved, and left alone if not.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_preserve_atime(&mut self, preserve: bool) {
+        self.fields.preserve_atime = preserve;
+    }
+
+    /// Configure what happens when this entry is unpacked to a path which
+    /// already exists.
+    ///
+    /// This is `OverwritePolicy::Unlink` by default.
+    pub fn set_overwrite_policy(&mut self, policy: OverwritePolicy) {
+        self.fields.overwrite = policy;
+    }
+
+    /// Indicate whether regular files are unpacked atomically.
+    ///
+    /// When enabled the contents of this entry are written to a temporary
+    /// file in the destination directory, which is renamed over the final
+    /// path only once its contents and metadata are complete. Any existing
+    /// file at that path remains visible until then, and a failure while
+    /// unpacking leaves it untouched.
+    ///
+    /// This flag is disabled by default.
+    pub fn set_atomic(&mut self, atomic: bool) {
+        self.fields.atomic = atomic;
+    }
+
+    /// Configure whether unpacking this entry syncs it to stable storage.
+    ///
+    /// This is `Durability::Unsynced` by default.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.fields.durability = durability;
+    }
+}
+
+impl<'a, R: Read + Seek> Entry<'a, R> {
+    /// Reads the contents of this entry at `offset` into `buf`, returning the
+    /// number of bytes read.
+    ///
+    /// This works like `FileExt::read_at`: the current position of the entry
+    /// is left unchanged. See the `Seek` implementation of `Entry` for when
+    /// this is possible.
+    pub fn read_at(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.fields.read_at(buf, offset)
+    }
 }
 
 impl<'a, R: Read> Read for Entry<'a, R> {
@@ -281,6 +760,21 @@ impl<'a, R: Read> Read for Entry<'a, R> {
     }
 }
 
+/// Seeks within the contents of an entry.
+///
+/// This is only possible for entries of an iterator created with
+/// [`Archive::entries_with_seek`] or [`Archive::entries_from`], which may
+/// seek the underlying reader; seeking other entries returns an error. The
+/// holes of sparse files read back as zeros without reading the archive.
+///
+/// The contents of an entry must only be accessed while it is the current
+/// entry of its iterator, like when reading them.
+impl<'a, R: Read + Seek> Seek for Entry<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.fields.seek(pos)
+    }
+}
+
 impl<'a> EntryFields<'a> {
     pub fn from<R: Read>(entry: Entry<R>) -> EntryFields {
         entry.fields
@@ -300,32 +794,16 @@ impl<'a> EntryFields<'a> {
         self.read_to_end(&mut v).map(|_| v)
     }
 
-    fn path(&self) -> io::Result<Cow<Path>> {
+    pub fn path(&self) -> io::Result<Cow<Path>> {
         bytes2path(self.path_bytes())
     }
 
-    fn path_bytes(&self) -> Cow<[u8]> {
-        match self.long_pathname {
-            Some(ref bytes) => {
-                if let Some(&0) = bytes.last() {
-                    Cow::Borrowed(&bytes[..bytes.len() - 1])
-                } else {
-                    Cow::Borrowed(bytes)
-                }
-            }
-            None => {
-                if let Some(ref pax) = self.pax_extensions {
-                    let pax = PaxExtensions::new(pax)
-                        .filter_map(|f| f.ok())
-                        .find(|f| f.key_bytes() == b"path")
-                        .map(|f| f.value_bytes());
-                    if let Some(field) = pax {
-                        return Cow::Borrowed(field);
-                    }
-                }
-                self.header.path_bytes()
-            }
-        }
+    pub fn path_bytes(&self) -> Cow<[u8]> {
+        resolve_path(
+            &self.header,
+            self.long_pathname.as_deref(),
+            self.pax.as_ref(),
+        )
     }
 
     /// Gets the path in a "lossy" way, used for error reporting ONLY.
@@ -333,7 +811,7 @@ impl<'a> EntryFields<'a> {
         String::from_utf8_lossy(&self.path_bytes()).to_string()
     }
 
-    fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
+    pub fn link_name(&self) -> io::Result<Option<Cow<Path>>> {
         match self.link_name_bytes() {
             Some(bytes) => bytes2path(bytes).map(Some),
             None => Ok(None),
@@ -341,26 +819,24 @@ impl<'a> EntryFields<'a> {
     }
 
     fn link_name_bytes(&self) -> Option<Cow<[u8]>> {
-        match self.long_linkname {
-            Some(ref bytes) => {
-                if let Some(&0) = bytes.last() {
-                    Some(Cow::Borrowed(&bytes[..bytes.len() - 1]))
-                } else {
-                    Some(Cow::Borrowed(bytes))
-                }
-            }
-            None => {
-                if let Some(ref pax) = self.pax_extensions {
-                    let pax = PaxExtensions::new(pax)
-                        .filter_map(|f| f.ok())
-                        .find(|f| f.key_bytes() == b"linkpath")
-                        .map(|f| f.value_bytes());
-                    if let Some(field) = pax {
-                        return Some(Cow::Borrowed(field));
-                    }
-                }
-                self.header.link_name_bytes()
-            }
+        resolve_link_name(
+            &self.header,
+            self.long_linkname.as_deref(),
+            self.pax.as_ref(),
+        )
+    }
+
+    fn username_bytes(&self) -> Option<&[u8]> {
+        match self.pax.as_ref().and_then(|pax| pax.uname()) {
+            Some(name) => Some(name),
+            None => self.header.username_bytes(),
+        }
+    }
+
+    fn groupname_bytes(&self) -> Option<&[u8]> {
+        match self.pax.as_ref().and_then(|pax| pax.gname()) {
+            Some(name) => Some(name),
+            None => self.header.groupname_bytes(),
         }
     }
 
@@ -378,7 +854,55 @@ impl<'a> EntryFields<'a> {
         )))
     }
 
-    fn unpack_in(&mut self, dst: &Path) -> io::Result<bool> {
+    fn unpack_in<T: UnpackTarget + ?Sized>(
+        &mut self,
+        target: &mut T,
+        dst: &Path,
+    ) -> io::Result<bool> {
+        let file_dst = match self.unpack_in_dst(dst, None)? {
+            Some(file_dst) => file_dst,
+            None => return Ok(false),
+        };
+
+        // Skip cases where only slashes or '.' parts were seen, because
+        // this is effectively an empty filename.
+        if *dst == *file_dst {
+            return Ok(true);
+        }
+
+        self.unpack_in_at(target, dst, &file_dst)?;
+        if self.durability == Durability::SyncAll {
+            sync_dirs(target, dst, &file_dst)?;
+        }
+        Ok(true)
+    }
+
+    fn unpack_alone(&mut self, dst: &Path) -> io::Result<Unpacked> {
+        let target = &mut FsTarget::new();
+        let unpacked = self.unpack(target, None, dst)?;
+        if self.durability == Durability::SyncAll {
+            if let Some(parent) = dst.parent() {
+                let parent = if parent == Path::new("") {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+                sync_dir(target, parent)?;
+            }
+        }
+        Ok(match unpacked {
+            Outcome::Written(Some(f)) => Unpacked::File(f),
+            Outcome::Written(None) => Unpacked::__Nonexhaustive,
+            Outcome::Kept => Unpacked::Kept,
+        })
+    }
+
+    /// Computes the location that this entry would be unpacked to underneath
+    /// `dst`, optionally using `path` in place of the entry's own path.
+    ///
+    /// Returns `None` if the entry should be skipped, and `dst` itself if the
+    /// path is effectively empty.
+    pub fn unpack_in_dst(&self, dst: &Path, path: Option<&Path>) -> io::Result<Option<PathBuf>> {
         // Notes regarding bsdtar 2.8.3 / libarchive 2.8.3:
         // * Leading '/'s are trimmed. For example, `///test` is treated as
         //   `test`.
@@ -394,12 +918,15 @@ impl<'a> EntryFields<'a> {
 
         let mut file_dst = dst.to_path_buf();
         {
-            let path = self.path().map_err(|e| {
-                TarError::new(
-                    format!("invalid path in entry header: {}", self.path_lossy()),
-                    e,
-                )
-            })?;
+            let path = match path {
+                Some(path) => Cow::Borrowed(path),
+                None => self.path().map_err(|e| {
+                    TarError::new(
+                        format!("invalid path in entry header: {}", self.path_lossy()),
+                        e,
+                    )
+                })?,
+            };
             for part in path.components() {
                 match part {
                     // Leading '/' characters, root paths, and '.'
@@ -411,120 +938,180 @@ impl<'a> EntryFields<'a> {
                     // unpacking the file to prevent directory traversal
                     // security issues.  See, e.g.: CVE-2001-1267,
                     // CVE-2002-0399, CVE-2005-1918, CVE-2007-4131
-                    Component::ParentDir => return Ok(false),
+                    Component::ParentDir => return Ok(None),
 
                     Component::Normal(part) => file_dst.push(part),
                 }
             }
         }
 
-        // Skip cases where only slashes or '.' parts were seen, because
-        // this is effectively an empty filename.
-        if *dst == *file_dst {
-            return Ok(true);
+        // Skip entries without a parent (i.e. outside of FS root)
+        if *dst != *file_dst && file_dst.parent().is_none() {
+            return Ok(None);
         }
 
-        // Skip entries without a parent (i.e. outside of FS root)
+        Ok(Some(file_dst))
+    }
+
+    /// Unpacks this entry to `file_dst`, a location previously returned by
+    /// `unpack_in_dst`, creating any missing parent directories inside of
+    /// `dst`.
+    ///
+    /// Returns the handle of the file that was written, if any.
+    pub fn unpack_in_at<T: UnpackTarget + ?Sized>(
+        &mut self,
+        target: &mut T,
+        dst: &Path,
+        file_dst: &Path,
+    ) -> io::Result<Outcome<T::File>> {
         let parent = match file_dst.parent() {
             Some(p) => p,
-            None => return Ok(false),
+            None => return Err(other("cannot unpack an entry without a parent")),
         };
 
-        self.ensure_dir_created(&dst, parent)
+        self.ensure_dir_created(target, &dst, parent)
             .map_err(|e| TarError::new(format!("failed to create `{}`", parent.display()), e))?;
 
-        let canon_target = self.validate_inside_dst(&dst, parent)?;
+        let canon_target = self.validate_inside_dst(target, &dst, parent)?;
 
-        self.unpack(Some(&canon_target), &file_dst)
-            .map_err(|e| TarError::new(format!("failed to unpack `{}`", file_dst.display()), e))?;
-
-        Ok(true)
+        self.unpack(target, Some(&canon_target), file_dst)
+            .map_err(|e| {
+                TarError::new(format!("failed to unpack `{}`", file_dst.display()), e).into()
+            })
     }
 
     /// Unpack as destination directory `dst`.
-    fn unpack_dir(&mut self, dst: &Path) -> io::Result<()> {
+    ///
+    /// Returns `false` if something other than a directory is in the way and
+    /// the overwrite policy says to leave it alone.
+    fn unpack_dir<T: UnpackTarget + ?Sized>(
+        &mut self,
+        target: &mut T,
+        dst: &Path,
+    ) -> io::Result<bool> {
         // If the directory already exists just let it slide
-        fs::create_dir(dst).or_else(|err| {
-            if err.kind() == ErrorKind::AlreadyExists {
-                let prev = fs::metadata(dst);
-                if prev.map(|m| m.is_dir()).unwrap_or(false) {
-                    return Ok(());
+        let create = |target: &mut T| {
+            target.create_dir(dst).or_else(|err| {
+                if err.kind() == ErrorKind::AlreadyExists {
+                    let prev = target.metadata(dst);
+                    if prev.map(|m| m.is_dir()).unwrap_or(false) {
+                        return Ok(());
+                    }
                 }
-            }
-            Err(Error::new(
-                err.kind(),
-                format!("{} when creating dir {}", err, dst.display()),
-            ))
-        })
+                Err(err)
+            })
+        };
+        self.create_or_replace(target, dst, create)
+            .map(|created| created.is_some())
+            .map_err(|err| {
+                Error::new(
+                    err.kind(),
+                    format!("{} when creating dir {}", err, dst.display()),
+                )
+            })
     }
 
-    /// Returns access to the header of this entry in the archive.
-    fn unpack(&mut self, target_base: Option<&Path>, dst: &Path) -> io::Result<Unpacked> {
-        fn set_perms_ownerships(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            header: &Header,
-            mask: u32,
-            perms: bool,
-            ownerships: bool,
-        ) -> io::Result<()> {
-            // ownerships need to be set first to avoid stripping SUID bits in the permissions ...
-            if ownerships {
-                set_ownerships(dst, &f, header.uid()?, header.gid()?)?;
-            }
-            // ... then set permissions, SUID bits set here is kept
-            if let Ok(mode) = header.mode() {
-                set_perms(dst, f, mode, mask, perms)?;
-            }
-
-            Ok(())
+    /// Runs `create` to create `dst`, and if it fails because something
+    /// already exists there consults the overwrite policy to decide whether
+    /// to remove the existing path and try again.
+    ///
+    /// Returns `None` if the entry should not be unpacked.
+    fn create_or_replace<T: UnpackTarget + ?Sized, U>(
+        &self,
+        target: &mut T,
+        dst: &Path,
+        mut create: impl FnMut(&mut T) -> io::Result<U>,
+    ) -> io::Result<Option<U>> {
+        let err = match create(target) {
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => err,
+            result => return result.map(Some),
+        };
+        if !self.make_way(target, dst, err, true)? {
+            return Ok(None);
         }
+        create(target).map(Some)
+    }
 
-        fn get_mtime(header: &Header) -> Option<FileTime> {
-            header.mtime().ok().map(|mtime| {
-                // For some more information on this see the comments in
-                // `Header::fill_platform_from`, but the general idea is that
-                // we're trying to avoid 0-mtime files coming out of archives
-                // since some tools don't ingest them well. Perhaps one day
-                // when Cargo stops working with 0-mtime archives we can remove
-                // this.
-                let mtime = if mtime == 0 { 1 } else { mtime };
-                FileTime::from_unix_time(mtime as i64, 0)
-            })
+    /// Decides what to do about an existing path last modified at
+    /// `prev_mtime` according to the overwrite policy.
+    pub fn replace_existing(&self, prev_mtime: i64) -> Replace {
+        match self.overwrite {
+            OverwritePolicy::KeepOld => Replace::Fail,
+            OverwritePolicy::SkipOld => Replace::Keep,
+            OverwritePolicy::KeepNewer => match self.header.mtime() {
+                Ok(mtime) if prev_mtime > mtime as i64 => Replace::Keep,
+                _ => Replace::Unlink,
+            },
+            OverwritePolicy::Unlink => Replace::Unlink,
+            OverwritePolicy::RecursiveUnlink => Replace::UnlinkRecursive,
         }
+    }
 
-        let kind = self.header.entry_type();
+    /// Consults the overwrite policy about an existing `dst`, removing it if
+    /// it's to be replaced. The error `err` is returned if the policy is to
+    /// fail instead.
+    ///
+    /// If `unlink_files` is false then only directories are removed, as the
+    /// caller will atomically rename over anything else.
+    ///
+    /// Returns `false` if the entry should not be unpacked.
+    fn make_way<T: UnpackTarget + ?Sized>(
+        &self,
+        target: &mut T,
+        dst: &Path,
+        err: io::Error,
+        unlink_files: bool,
+    ) -> io::Result<bool> {
+        let prev = match target.symlink_metadata(dst) {
+            Ok(prev) => prev,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+        let recursive = match self.replace_existing(prev.mtime()) {
+            Replace::Fail => return Err(err),
+            Replace::Keep => return Ok(false),
+            Replace::Unlink => false,
+            Replace::UnlinkRecursive => true,
+        };
+        let removed = if !prev.is_dir() {
+            if !unlink_files {
+                return Ok(true);
+            }
+            target.remove_file(dst)
+        } else if recursive {
+            target.remove_dir_all(dst)
+        } else {
+            target.remove_dir(dst)
+        };
+        match removed {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
 
-        if kind.is_dir() {
-            self.unpack_dir(dst)?;
-            set_perms_ownerships(
-                dst,
-                None,
-                &self.header,
-                self.mask,
-                self.preserve_permissions,
-                self.preserve_ownerships,
-            )?;
-            return Ok(Unpacked::__Nonexhaustive);
-        } else if kind.is_hard_link() || kind.is_symlink() {
-            let src = match self.link_name()? {
-                Some(name) => name,
-                None => {
-                    return Err(other(&format!(
-                        "hard link listed for {} but no link name found",
-                        String::from_utf8_lossy(self.header.as_bytes())
-                    )));
+    /// Unpacks this entry to `dst` in `target`, returning the handle of the
+    /// file that was written, if any.
+    fn unpack<T: UnpackTarget + ?Sized>(
+        &mut self,
+        target: &mut T,
+        target_base: Option<&Path>,
+        dst: &Path,
+    ) -> io::Result<Outcome<T::File>> {
+        match self.unpack_kind() {
+            UnpackKind::Dir => {
+                if !self.unpack_dir(target, dst)? {
+                    return Ok(Outcome::Kept);
                 }
-            };
-
-            if src.iter().count() == 0 {
-                return Err(other(&format!(
-                    "symlink destination for {} is empty",
-                    String::from_utf8_lossy(self.header.as_bytes())
-                )));
+                let (ownership, mode) = (self.unpack_ownership()?, self.unpack_mode());
+                set_perms_ownerships(target, dst, None, ownership, mode)?;
+                if self.unpack_acls {
+                    set_acls(self, target, dst, true)?;
+                }
+                return Ok(Outcome::Written(None));
             }
-
-            if kind.is_hard_link() {
+            UnpackKind::HardLink => {
+                let src = self.link_src()?;
                 let link_src = match target_base {
                     // If we're unpacking within a directory then ensure that
                     // the destination of this hard link is both present and
@@ -539,32 +1126,33 @@ impl<'a> EntryFields<'a> {
                     // so we need to validate at this time.
                     Some(ref p) => {
                         let link_src = p.join(src);
-                        self.validate_inside_dst(p, &link_src)?;
+                        self.validate_inside_dst(target, p, &link_src)?;
                         link_src
                     }
                     None => src.into_owned(),
                 };
-                fs::hard_link(&link_src, dst).map_err(|err| {
-                    Error::new(
-                        err.kind(),
-                        format!(
-                            "{} when hard linking {} to {}",
-                            err,
-                            link_src.display(),
-                            dst.display()
-                        ),
-                    )
-                })?;
-            } else {
-                symlink(&src, dst)
-                    .or_else(|err_io| {
-                        if err_io.kind() == io::ErrorKind::AlreadyExists && self.overwrite {
-                            // remove dest and try once more
-                            std::fs::remove_file(dst).and_then(|()| symlink(&src, dst))
-                        } else {
-                            Err(err_io)
-                        }
-                    })
+                let created = self
+                    .create_or_replace(target, dst, |target| target.hard_link(&link_src, dst))
+                    .map_err(|err| {
+                        Error::new(
+                            err.kind(),
+                            format!(
+                                "{} when hard linking {} to {}",
+                                err,
+                                link_src.display(),
+                                dst.display()
+                            ),
+                        )
+                    })?;
+                if created.is_none() {
+                    return Ok(Outcome::Kept);
+                }
+                return Ok(Outcome::Written(None));
+            }
+            UnpackKind::Symlink => {
+                let src = self.link_src()?;
+                let created = self
+                    .create_or_replace(target, dst, |target| target.symlink(&src, dst))
                     .map_err(|err| {
                         Error::new(
                             err.kind(),
@@ -576,83 +1164,44 @@ impl<'a> EntryFields<'a> {
                             ),
                         )
                     })?;
-                if self.preserve_mtime {
-                    if let Some(mtime) = get_mtime(&self.header) {
-                        filetime::set_symlink_file_times(dst, mtime, mtime).map_err(|e| {
-                            TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
-                        })?;
-                    }
+                if created.is_none() {
+                    return Ok(Outcome::Kept);
                 }
+                let (atime, mtime) = self.unpack_times();
+                if atime.is_some() || mtime.is_some() {
+                    target.set_times(dst, None, atime, mtime).map_err(|e| {
+                        TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
+                    })?;
+                }
+                return Ok(Outcome::Written(None));
             }
-            return Ok(Unpacked::__Nonexhaustive);
-
-            #[cfg(target_arch = "wasm32")]
-            #[allow(unused_variables)]
-            fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
-                Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
-            }
-
-            #[cfg(windows)]
-            fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
-                ::std::os::windows::fs::symlink_file(src, dst)
-            }
-
-            #[cfg(unix)]
-            fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
-                ::std::os::unix::fs::symlink(src, dst)
-            }
-        } else if kind.is_pax_global_extensions()
-            || kind.is_pax_local_extensions()
-            || kind.is_gnu_longname()
-            || kind.is_gnu_longlink()
-        {
-            return Ok(Unpacked::__Nonexhaustive);
-        };
-
-        // Old BSD-tar compatibility.
-        // Names that have a trailing slash should be treated as a directory.
-        // Only applies to old headers.
-        if self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/") {
-            self.unpack_dir(dst)?;
-            set_perms_ownerships(
-                dst,
-                None,
-                &self.header,
-                self.mask,
-                self.preserve_permissions,
-                self.preserve_ownerships,
-            )?;
-            return Ok(Unpacked::__Nonexhaustive);
-        }
-
-        // Note the lack of `else` clause above. According to the FreeBSD
-        // documentation:
-        //
-        // > A POSIX-compliant implementation must treat any unrecognized
-        // > typeflag value as a regular file.
-        //
-        // As a result if we don't recognize the kind we just write out the file
-        // as we would normally.
+            UnpackKind::Skip => return Ok(Outcome::Written(None)),
+            UnpackKind::File => {}
+        }
 
         // Ensure we write a new file rather than overwriting in-place which
         // is attackable; if an existing file is found unlink it.
-        fn open(dst: &Path) -> io::Result<std::fs::File> {
-            OpenOptions::new().write(true).create_new(true).open(dst)
-        }
-        let mut f = (|| -> io::Result<std::fs::File> {
-            let mut f = open(dst).or_else(|err| {
-                if err.kind() != ErrorKind::AlreadyExists {
-                    Err(err)
-                } else if self.overwrite {
-                    match fs::remove_file(dst) {
-                        Ok(()) => open(dst),
-                        Err(ref e) if e.kind() == io::ErrorKind::NotFound => open(dst),
-                        Err(e) => Err(e),
-                    }
-                } else {
-                    Err(err)
+        //
+        // When unpacking atomically the file is instead written under a
+        // temporary name in the same directory and only renamed over `dst`
+        // once it's complete, so whatever was previously at `dst` stays
+        // intact until then.
+        let mut tmp = None;
+        let f = (|| -> io::Result<Option<T::File>> {
+            let mut f = if self.atomic {
+                let exists = Error::new(ErrorKind::AlreadyExists, "file already exists");
+                if !self.make_way(target, dst, exists, false)? {
+                    return Ok(None);
                 }
-            })?;
+                let (path, f) = create_temp(target, dst)?;
+                tmp = Some(path);
+                f
+            } else {
+                match self.create_or_replace(target, dst, |target| target.create_file(dst))? {
+                    Some(f) => f,
+                    None => return Ok(None),
+                }
+            };
             for io in self.data.drain(..) {
                 match io {
                     EntryIo::Data(mut d) => {
@@ -661,248 +1210,356 @@ impl<'a> EntryFields<'a> {
                             return Err(other("failed to write entire file"));
                         }
                     }
-                    EntryIo::Pad(d) => {
-                        // TODO: checked cast to i64
-                        let to = SeekFrom::Current(d.limit() as i64);
-                        let size = f.seek(to)?;
-                        f.set_len(size)?;
+                    EntryIo::Reader(mut d) => {
+                        let expected = d.limit();
+                        if io::copy(&mut d, &mut f)? != expected {
+                            return Err(other("failed to write entire file"));
+                        }
+                    }
+                    EntryIo::Pad(d) => target.write_zeros(&mut f, d.limit())?,
+                    EntryIo::Buffer(mut d) => {
+                        io::copy(&mut d, &mut f)?;
                     }
                 }
             }
-            Ok(f)
-        })()
-        .map_err(|e| {
-            let header = self.header.path_bytes();
-            TarError::new(
-                format!(
-                    "failed to unpack `{}` into `{}`",
-                    String::from_utf8_lossy(&header),
-                    dst.display()
-                ),
-                e,
-            )
-        })?;
+            Ok(Some(f))
+        })();
+        let f = match f {
+            Ok(Some(f)) => f,
+            Ok(None) => return Ok(Outcome::Kept),
+            Err(e) => {
+                if let Some(tmp) = tmp {
+                    let _ = target.remove_file(&tmp);
+                }
+                let header = self.header.path_bytes();
+                let err = TarError::new(
+                    format!(
+                        "failed to unpack `{}` into `{}`",
+                        String::from_utf8_lossy(&header),
+                        dst.display()
+                    ),
+                    e,
+                );
+                return Err(err.into());
+            }
+        };
 
-        if self.preserve_mtime {
-            if let Some(mtime) = get_mtime(&self.header) {
-                filetime::set_file_handle_times(&f, Some(mtime), Some(mtime)).map_err(|e| {
-                    TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
+        let path = tmp.as_deref().unwrap_or(dst);
+        let finished = (|| -> io::Result<T::File> {
+            let mut f = f;
+            let (atime, mtime) = self.unpack_times();
+            if atime.is_some() || mtime.is_some() {
+                target
+                    .set_times(path, Some(&mut f), atime, mtime)
+                    .map_err(|e| {
+                        TarError::new(format!("failed to set mtime for `{}`", dst.display()), e)
+                    })?;
+            }
+            let (ownership, mode) = (self.unpack_ownership()?, self.unpack_mode());
+            set_perms_ownerships(target, path, Some(&mut f), ownership, mode)?;
+            if self.unpack_xattrs {
+                set_xattrs(self, target, path)?;
+            }
+            if self.unpack_acls {
+                set_acls(self, target, path, false)?;
+            }
+            if self.durability != Durability::Unsynced {
+                target
+                    .sync_file(&mut f)
+                    .map_err(|e| TarError::new(format!("failed to sync `{}`", dst.display()), e))?;
+            }
+            if let Some(ref tmp) = tmp {
+                target.rename(tmp, dst).map_err(|e| {
+                    TarError::new(
+                        format!(
+                            "failed to rename `{}` to `{}`",
+                            tmp.display(),
+                            dst.display()
+                        ),
+                        e,
+                    )
                 })?;
             }
-        }
-        set_perms_ownerships(
-            dst,
-            Some(&mut f),
-            &self.header,
-            self.mask,
-            self.preserve_permissions,
-            self.preserve_ownerships,
-        )?;
-        if self.unpack_xattrs {
-            set_xattrs(self, dst)?;
-        }
-        return Ok(Unpacked::File(f));
+            Ok(f)
+        })();
+        return match finished {
+            Ok(f) => Ok(Outcome::Written(Some(f))),
+            Err(e) => {
+                if let Some(tmp) = tmp {
+                    let _ = target.remove_file(&tmp);
+                }
+                Err(e)
+            }
+        };
 
-        fn set_ownerships(
+        /// Creates a new file with a unique temporary name in the same
+        /// directory as `dst`.
+        fn create_temp<T: UnpackTarget + ?Sized>(
+            target: &mut T,
             dst: &Path,
-            f: &Option<&mut std::fs::File>,
-            uid: u64,
-            gid: u64,
-        ) -> Result<(), TarError> {
-            _set_ownerships(dst, f, uid, gid).map_err(|e| {
-                TarError::new(
-                    format!(
-                        "failed to set ownerships to uid={:?}, gid={:?} \
-                         for `{}`",
-                        uid,
-                        gid,
-                        dst.display()
-                    ),
-                    e,
-                )
-            })
+        ) -> io::Result<(PathBuf, T::File)> {
+            use std::collections::hash_map::RandomState;
+            use std::hash::{BuildHasher, Hasher};
+
+            let mut attempt = 0u32;
+            loop {
+                // `RandomState` is seeded randomly per thread and varies with
+                // each instance, which is plenty to avoid collisions here.
+                let mut hasher = RandomState::new().build_hasher();
+                hasher.write_u32(attempt);
+                let tmp = dst.with_file_name(format!(".tar-{:016x}.tmp", hasher.finish()));
+                match target.create_file(&tmp) {
+                    Ok(f) => return Ok((tmp, f)),
+                    Err(ref e) if e.kind() == ErrorKind::AlreadyExists && attempt < 100 => {
+                        attempt += 1;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
         }
 
-        #[cfg(unix)]
-        fn _set_ownerships(
+        fn set_xattrs<T: UnpackTarget + ?Sized>(
+            me: &mut EntryFields,
+            target: &mut T,
             dst: &Path,
-            f: &Option<&mut std::fs::File>,
-            uid: u64,
-            gid: u64,
         ) -> io::Result<()> {
-            use std::convert::TryInto;
-            use std::os::unix::prelude::*;
+            let pax = match me.pax {
+                Some(ref pax) => pax,
+                None => return Ok(()),
+            };
 
-            let uid: libc::uid_t = uid.try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, format!("UID {} is too large!", uid))
-            })?;
-            let gid: libc::gid_t = gid.try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::Other, format!("GID {} is too large!", gid))
-            })?;
-            match f {
-                Some(f) => unsafe {
-                    let fd = f.as_raw_fd();
-                    if libc::fchown(fd, uid, gid) != 0 {
-                        Err(io::Error::last_os_error())
-                    } else {
-                        Ok(())
+            // libarchive writes binary-safe `LIBARCHIVE.xattr.` records next
+            // to the `SCHILY.xattr.` ones, so those take precedence.
+            let mut xattrs = Vec::<(Vec<u8>, io::Result<Vec<u8>>)>::new();
+            for (key, value) in pax.iter() {
+                let (name, value) = if key.starts_with(PAX_SCHILYXATTR.as_bytes()) {
+                    let name = &key[PAX_SCHILYXATTR.len()..];
+                    if xattrs.iter().any(|(n, _)| n == name) {
+                        continue;
                     }
-                },
-                None => unsafe {
-                    let path = std::ffi::CString::new(dst.as_os_str().as_bytes()).map_err(|e| {
-                        io::Error::new(
-                            io::ErrorKind::Other,
-                            format!("path contains null character: {:?}", e),
+                    (name.to_vec(), Ok(value.to_vec()))
+                } else if key.starts_with(PAX_LIBARCHIVEXATTR.as_bytes()) {
+                    let name = &key[PAX_LIBARCHIVEXATTR.len()..];
+                    let name = pax::url_decode(name).unwrap_or_else(|| name.to_vec());
+                    xattrs.retain(|(n, _)| *n != name);
+                    let value = pax::base64_decode(value)
+                        .ok_or_else(|| other("invalid base64 in LIBARCHIVE.xattr value"));
+                    (name, value)
+                } else {
+                    continue;
+                };
+                xattrs.push((name, value));
+            }
+
+            for (name, value) in xattrs {
+                if !me.xattr_filter.matches(&name) {
+                    continue;
+                }
+                let result = value.and_then(|value| target.set_xattr(dst, &name, &value));
+                match result {
+                    Ok(()) => {}
+                    Err(error) if me.xattr_best_effort => me.xattr_warnings.push(XattrWarning {
+                        path: dst.to_path_buf(),
+                        name,
+                        error,
+                    }),
+                    Err(e) => {
+                        return Err(TarError::new(
+                            format!(
+                                "failed to set extended attribute {:?} on `{}`",
+                                String::from_utf8_lossy(&name),
+                                dst.display()
+                            ),
+                            e,
                         )
-                    })?;
-                    if libc::lchown(path.as_ptr(), uid, gid) != 0 {
-                        Err(io::Error::last_os_error())
-                    } else {
-                        Ok(())
+                        .into())
                     }
-                },
+                }
             }
-        }
 
-        // Windows does not support posix numeric ownership IDs
-        #[cfg(any(windows, target_arch = "wasm32"))]
-        fn _set_ownerships(
-            _: &Path,
-            _: &Option<&mut std::fs::File>,
-            _: u64,
-            _: u64,
-        ) -> io::Result<()> {
             Ok(())
         }
 
-        fn set_perms(
+        #[cfg(all(feature = "acl", target_os = "linux"))]
+        fn set_acls<T: UnpackTarget + ?Sized>(
+            me: &mut EntryFields,
+            target: &mut T,
             dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            mask: u32,
-            preserve: bool,
-        ) -> Result<(), TarError> {
-            _set_perms(dst, f, mode, mask, preserve).map_err(|e| {
-                TarError::new(
-                    format!(
-                        "failed to set permissions to {:o} \
-                         for `{}`",
-                        mode,
-                        dst.display()
-                    ),
-                    e,
-                )
-            })
-        }
-
-        #[cfg(unix)]
-        fn _set_perms(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            mask: u32,
-            preserve: bool,
+            is_dir: bool,
         ) -> io::Result<()> {
-            use std::os::unix::prelude::*;
-
-            let mode = if preserve { mode } else { mode & 0o777 };
-            let mode = mode & !mask;
-            let perm = fs::Permissions::from_mode(mode as _);
-            match f {
-                Some(f) => f.set_permissions(perm),
-                None => fs::set_permissions(dst, perm),
-            }
-        }
+            use crate::acl;
 
-        #[cfg(windows)]
-        fn _set_perms(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            _mask: u32,
-            _preserve: bool,
-        ) -> io::Result<()> {
-            if mode & 0o200 == 0o200 {
-                return Ok(());
+            let pax = match me.pax {
+                Some(ref pax) => pax,
+                None => return Ok(()),
+            };
+            let mut names = vec![(acl::PAX_ACL_ACCESS, acl::XATTR_ACL_ACCESS)];
+            // Only directories have default ACLs
+            if is_dir {
+                names.push((acl::PAX_ACL_DEFAULT, acl::XATTR_ACL_DEFAULT));
             }
-            match f {
-                Some(f) => {
-                    let mut perm = f.metadata()?.permissions();
-                    perm.set_readonly(true);
-                    f.set_permissions(perm)
-                }
-                None => {
-                    let mut perm = fs::metadata(dst)?.permissions();
-                    perm.set_readonly(true);
-                    fs::set_permissions(dst, perm)
+            for (key, name) in names {
+                let value = match pax.get(key) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let entries = std::str::from_utf8(value)
+                    .map_err(|_| other("ACL is not valid UTF-8"))
+                    .and_then(acl::parse)
+                    .map_err(|e| {
+                        TarError::new(format!("invalid ACL for `{}`", dst.display()), e)
+                    })?;
+                if entries.is_empty() {
+                    continue;
                 }
+                target
+                    .set_xattr(dst, name.as_bytes(), &acl::to_xattr(&entries))
+                    .map_err(|e| {
+                        TarError::new(format!("failed to set ACL for `{}`", dst.display()), e)
+                    })?;
             }
+            Ok(())
         }
 
-        #[cfg(target_arch = "wasm32")]
-        #[allow(unused_variables)]
-        fn _set_perms(
-            dst: &Path,
-            f: Option<&mut std::fs::File>,
-            mode: u32,
-            mask: u32,
-            _preserve: bool,
+        #[cfg(not(all(feature = "acl", target_os = "linux")))]
+        fn set_acls<T: UnpackTarget + ?Sized>(
+            _: &mut EntryFields,
+            _: &mut T,
+            _: &Path,
+            _: bool,
         ) -> io::Result<()> {
-            Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
+            Ok(())
         }
+    }
 
-        #[cfg(all(unix, feature = "xattr"))]
-        fn set_xattrs(me: &mut EntryFields, dst: &Path) -> io::Result<()> {
-            use std::ffi::OsStr;
-            use std::os::unix::prelude::*;
+    /// Decides how this entry is unpacked according to its type.
+    pub fn unpack_kind(&self) -> UnpackKind {
+        let kind = self.header.entry_type();
+        if kind.is_dir() {
+            UnpackKind::Dir
+        } else if kind.is_hard_link() {
+            UnpackKind::HardLink
+        } else if kind.is_symlink() {
+            UnpackKind::Symlink
+        } else if kind.is_pax_global_extensions()
+            || kind.is_pax_local_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+        {
+            UnpackKind::Skip
+        } else if self.header.as_ustar().is_none() && self.path_bytes().ends_with(b"/") {
+            // Old BSD-tar compatibility.
+            // Names that have a trailing slash should be treated as a directory.
+            // Only applies to old headers.
+            UnpackKind::Dir
+        } else {
+            // According to the FreeBSD documentation:
+            //
+            // > A POSIX-compliant implementation must treat any unrecognized
+            // > typeflag value as a regular file.
+            //
+            // As a result if we don't recognize the kind we just write out
+            // the file as we would normally.
+            UnpackKind::File
+        }
+    }
 
-            let exts = match me.pax_extensions() {
-                Ok(Some(e)) => e,
-                _ => return Ok(()),
+    /// Returns the target of this link entry, which must not be empty.
+    pub fn link_src(&self) -> io::Result<Cow<'_, Path>> {
+        let src = match self.link_name()? {
+            Some(name) => name,
+            None => {
+                return Err(other(&format!(
+                    "hard link listed for {} but no link name found",
+                    String::from_utf8_lossy(self.header.as_bytes())
+                )));
+            }
+        };
+
+        if src.iter().count() == 0 {
+            return Err(other(&format!(
+                "symlink destination for {} is empty",
+                String::from_utf8_lossy(self.header.as_bytes())
+            )));
+        }
+        Ok(src)
+    }
+
+    /// Returns the uid and gid to restore on the unpacked entry, if
+    /// ownerships are preserved.
+    pub fn unpack_ownership(&self) -> io::Result<Option<(u64, u64)>> {
+        if self.preserve_ownerships {
+            Ok(Some((self.header.uid()?, self.header.gid()?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns the mode to restore on the unpacked entry, masked according to
+    /// `preserve_permissions` and `mask`.
+    pub fn unpack_mode(&self) -> Option<u32> {
+        self.header.mode().ok().map(|mode| {
+            let mode = if self.preserve_permissions {
+                mode
+            } else {
+                mode & 0o777
             };
-            let exts = exts
-                .filter_map(|e| e.ok())
-                .filter_map(|e| {
-                    let key = e.key_bytes();
-                    let prefix = b"SCHILY.xattr.";
-                    if key.starts_with(prefix) {
-                        Some((&key[prefix.len()..], e))
-                    } else {
-                        None
-                    }
-                })
-                .map(|(key, e)| (OsStr::from_bytes(key), e.value_bytes()));
+            mode & !self.mask
+        })
+    }
 
-            for (key, value) in exts {
-                xattr::set(dst, key, value).map_err(|e| {
-                    TarError::new(
-                        format!(
-                            "failed to set extended \
-                             attributes to {}. \
-                             Xattrs: key={:?}, value={:?}.",
-                            dst.display(),
-                            key,
-                            String::from_utf8_lossy(value)
-                        ),
-                        e,
-                    )
-                })?;
+    /// Returns the access and modification times to restore on the unpacked
+    /// entry, according to `preserve_atime` and `preserve_mtime`.
+    pub fn unpack_times(&self) -> (Option<u64>, Option<u64>) {
+        let mtime = self.header.mtime().ok().map(|mtime| {
+            // For some more information on this see the comments in
+            // `Header::fill_platform_from`, but the general idea is that
+            // we're trying to avoid 0-mtime files coming out of archives
+            // since some tools don't ingest them well. Perhaps one day
+            // when Cargo stops working with 0-mtime archives we can remove
+            // this.
+            if mtime == 0 {
+                1
+            } else {
+                mtime
             }
+        });
+        let atime = if self.preserve_atime {
+            self.atime().or(mtime)
+        } else if self.preserve_mtime {
+            mtime
+        } else {
+            None
+        };
+        let mtime = if self.preserve_mtime { mtime } else { None };
+        (atime, mtime)
+    }
 
-            Ok(())
-        }
-        // Windows does not completely support posix xattrs
-        // https://en.wikipedia.org/wiki/Extended_file_attributes#Windows_NT
-        #[cfg(any(windows, not(feature = "xattr"), target_arch = "wasm32"))]
-        fn set_xattrs(_: &mut EntryFields, _: &Path) -> io::Result<()> {
-            Ok(())
+    /// Returns the access time recorded for this entry, either in its pax
+    /// extensions or in a GNU header.
+    fn atime(&self) -> Option<u64> {
+        let pax = self.pax.as_ref().and_then(|pax| pax.get(PAX_ATIME));
+        // Pax times may have a fractional part, which is dropped.
+        if let Some((false, atime, _)) = pax.and_then(pax::parse_time) {
+            return Some(atime);
         }
+        // GNU tar only fills in the atime in some modes, and leaves it zeroed
+        // otherwise.
+        self.header
+            .as_gnu()
+            .and_then(|gnu| gnu.atime().ok())
+            .filter(|atime| *atime != 0)
     }
 
-    fn ensure_dir_created(&self, dst: &Path, dir: &Path) -> io::Result<()> {
+    pub fn ensure_dir_created<T: UnpackTarget + ?Sized>(
+        &self,
+        target: &mut T,
+        dst: &Path,
+        dir: &Path,
+    ) -> io::Result<()> {
         let mut ancestor = dir;
         let mut dirs_to_create = Vec::new();
-        while ancestor.symlink_metadata().is_err() {
+        while target.symlink_metadata(ancestor).is_err() {
             dirs_to_create.push(ancestor);
             if let Some(parent) = ancestor.parent() {
                 ancestor = parent;
@@ -912,42 +1569,175 @@ impl<'a> EntryFields<'a> {
         }
         for ancestor in dirs_to_create.into_iter().rev() {
             if let Some(parent) = ancestor.parent() {
-                self.validate_inside_dst(dst, parent)?;
+                self.validate_inside_dst(target, dst, parent)?;
             }
-            fs::create_dir_all(ancestor)?;
+            target.create_dir_all(ancestor)?;
         }
         Ok(())
     }
 
-    fn validate_inside_dst(&self, dst: &Path, file_dst: &Path) -> io::Result<PathBuf> {
+    fn validate_inside_dst<T: UnpackTarget + ?Sized>(
+        &self,
+        target: &mut T,
+        dst: &Path,
+        file_dst: &Path,
+    ) -> io::Result<PathBuf> {
         // Abort if target (canonical) parent is outside of `dst`
-        let canon_parent = file_dst.canonicalize().map_err(|err| {
+        let canon_parent = target.canonicalize(file_dst).map_err(|err| {
             Error::new(
                 err.kind(),
                 format!("{} while canonicalizing {}", err, file_dst.display()),
             )
         })?;
-        let canon_target = dst.canonicalize().map_err(|err| {
+        let canon_target = target.canonicalize(dst).map_err(|err| {
             Error::new(
                 err.kind(),
                 format!("{} while canonicalizing {}", err, dst.display()),
             )
         })?;
-        if !canon_parent.starts_with(&canon_target) {
-            let err = TarError::new(
+        check_inside_dst(&canon_target, &canon_parent)?;
+        Ok(canon_target)
+    }
+}
+
+/// Restores the ownership `ownership` and then the permissions `mode` of
+/// `dst`, which is open as `f` if it's a file.
+pub fn set_perms_ownerships<T: UnpackTarget + ?Sized>(
+    target: &mut T,
+    dst: &Path,
+    mut f: Option<&mut T::File>,
+    ownership: Option<(u64, u64)>,
+    mode: Option<u32>,
+) -> io::Result<()> {
+    // ownerships need to be set first to avoid stripping SUID bits in the permissions ...
+    if let Some((uid, gid)) = ownership {
+        target
+            .set_ownership(dst, f.as_deref_mut(), uid, gid)
+            .map_err(|e| {
+                TarError::new(
+                    format!(
+                        "failed to set ownerships to uid={:?}, gid={:?} \
+                         for `{}`",
+                        uid,
+                        gid,
+                        dst.display()
+                    ),
+                    e,
+                )
+            })?;
+    }
+    // ... then set permissions, SUID bits set here is kept
+    if let Some(mode) = mode {
+        target.set_permissions(dst, f, mode).map_err(|e| {
+            TarError::new(
                 format!(
-                    "trying to unpack outside of destination path: {}",
-                    canon_target.display()
+                    "failed to set permissions to {:o} \
+                     for `{}`",
+                    mode,
+                    dst.display()
                 ),
-                // TODO: use ErrorKind::InvalidInput here? (minor breaking change)
-                Error::new(ErrorKind::Other, "Invalid argument"),
-            );
-            return Err(err.into());
+                e,
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Checks that `canon_path` is inside of `canon_dst`, both canonicalized
+/// already, as otherwise unpacking there would escape the destination.
+pub fn check_inside_dst(canon_dst: &Path, canon_path: &Path) -> io::Result<()> {
+    if !canon_path.starts_with(canon_dst) {
+        let err = TarError::new(
+            format!(
+                "trying to unpack outside of destination path: {}",
+                canon_dst.display()
+            ),
+            // TODO: use ErrorKind::InvalidInput here? (minor breaking change)
+            Error::new(ErrorKind::Other, "Invalid argument"),
+        );
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Returns the path of an entry with `header`, preferring the GNU long name
+/// `long_pathname` and then the pax records `pax` over the header itself.
+pub fn resolve_path<'b>(
+    header: &'b Header,
+    long_pathname: Option<&'b [u8]>,
+    pax: Option<&'b PaxMap>,
+) -> Cow<'b, [u8]> {
+    match long_pathname {
+        Some(bytes) => {
+            if let Some(&0) = bytes.last() {
+                Cow::Borrowed(&bytes[..bytes.len() - 1])
+            } else {
+                Cow::Borrowed(bytes)
+            }
+        }
+        None => {
+            // The pax sparse formats of GNU tar store the real path of the
+            // file separately.
+            if let Some(path) = pax
+                .and_then(|pax| pax.get(pax::PAX_GNUSPARSENAME))
+                .or_else(|| pax.and_then(|pax| pax.path()))
+            {
+                return Cow::Borrowed(path);
+            }
+            header.path_bytes()
         }
-        Ok(canon_target)
     }
 }
 
+/// Returns the link name of an entry with `header`, if any, preferring the
+/// GNU long link name `long_linkname` and then the pax records `pax` over the
+/// header itself.
+pub fn resolve_link_name<'b>(
+    header: &'b Header,
+    long_linkname: Option<&'b [u8]>,
+    pax: Option<&'b PaxMap>,
+) -> Option<Cow<'b, [u8]>> {
+    match long_linkname {
+        Some(bytes) => {
+            if let Some(&0) = bytes.last() {
+                Some(Cow::Borrowed(&bytes[..bytes.len() - 1]))
+            } else {
+                Some(Cow::Borrowed(bytes))
+            }
+        }
+        None => {
+            if let Some(linkpath) = pax.and_then(|pax| pax.linkpath()) {
+                return Some(Cow::Borrowed(linkpath));
+            }
+            header.link_name_bytes()
+        }
+    }
+}
+
+/// Syncs every directory from the parent of `file_dst` up to and including
+/// `dst`, which `file_dst` is inside of.
+pub fn sync_dirs<T: UnpackTarget + ?Sized>(
+    target: &mut T,
+    dst: &Path,
+    file_dst: &Path,
+) -> io::Result<()> {
+    for dir in file_dst.ancestors().skip(1) {
+        sync_dir(target, dir)?;
+        if dir == dst {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Syncs the directory `dir`, making the creation of entries in it durable.
+pub fn sync_dir<T: UnpackTarget + ?Sized>(target: &mut T, dir: &Path) -> io::Result<()> {
+    target
+        .sync_dir(dir)
+        .map_err(|e| TarError::new(format!("failed to sync `{}`", dir.display()), e).into())
+}
+
 impl<'a> Read for EntryFields<'a> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
         loop {
@@ -955,6 +1745,10 @@ impl<'a> Read for EntryFields<'a> {
                 Some(Ok(0)) => {
                     self.data.remove(0);
                 }
+                Some(Ok(n)) => {
+                    self.read_pos += n as u64;
+                    return Ok(n);
+                }
                 Some(r) => return r,
                 None => return Ok(0),
             }
@@ -962,11 +1756,66 @@ impl<'a> Read for EntryFields<'a> {
     }
 }
 
+impl<'a> EntryFields<'a> {
+    pub fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (archive, seekable_archive) = match (self.archive, self.seekable_archive) {
+            (Some(archive), Some(seekable_archive)) => (archive, seekable_archive),
+            _ => return Err(other("entry is not part of a seekable archive")),
+        };
+        let (base, delta) = match pos {
+            SeekFrom::Start(n) => (0, n as i64),
+            SeekFrom::Current(n) => (self.read_pos, n),
+            SeekFrom::End(n) => (self.size, n),
+        };
+        let pos = match (base as i64).checked_add(delta) {
+            Some(pos) if pos >= 0 => pos as u64,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                ))
+            }
+        };
+
+        // Rebuild the remaining I/O blocks from the regions after `pos`. The
+        // data of all regions is stored contiguously in the archive, so only
+        // the reader needs to be moved to the first one.
+        self.data.clear();
+        let mut seeked = false;
+        for region in self.regions.iter().filter(|r| r.offset + r.len > pos) {
+            let skip = pos.saturating_sub(region.offset);
+            let len = region.len - skip;
+            match region.archive_pos {
+                Some(archive_pos) => {
+                    if !seeked {
+                        seekable_archive.seek_to(archive_pos + skip)?;
+                        seeked = true;
+                    }
+                    self.data.push(EntryIo::Data(archive.take(len)));
+                }
+                None => self.data.push(EntryIo::Pad(io::repeat(0).take(len))),
+            }
+        }
+        self.read_pos = pos;
+        Ok(pos)
+    }
+
+    pub fn read_at(&mut self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let saved = self.read_pos;
+        self.seek(SeekFrom::Start(offset))?;
+        let ret = self.read(buf);
+        self.seek(SeekFrom::Start(saved))?;
+        ret
+    }
+}
+
 impl<'a> Read for EntryIo<'a> {
     fn read(&mut self, into: &mut [u8]) -> io::Result<usize> {
         match *self {
             EntryIo::Pad(ref mut io) => io.read(into),
             EntryIo::Data(ref mut io) => io.read(into),
+            EntryIo::Buffer(ref mut io) => io.read(into),
+            EntryIo::Reader(ref mut io) => io.read(into),
         }
     }
 }
@@ -701,7 +701,7 @@ impl Header {
         octal_into(&mut self.as_old_mut().cksum, cksum);
     }
 
-    fn calculate_cksum(&self) -> u32 {
+    pub(crate) fn calculate_cksum(&self) -> u32 {
         let old = self.as_old();
         let start = old as *const _ as usize;
         let cksum_start = old.cksum.as_ptr() as *const _ as usize;
@@ -0,0 +1,584 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader, SeekFrom};
+use std::path::Path;
+use std::str;
+
+use crate::header::{bytes2path, path2bytes};
+use crate::pax::url_decode;
+use crate::{other, Archive, Builder, EntryType, Header};
+
+/// The first line of a serialized index, which also identifies its version.
+const MAGIC: &str = "%tar-index 2";
+
+type DigestFn<'a> = dyn FnMut(&mut dyn Read) -> io::Result<String> + 'a;
+
+/// An index of the entries of an archive, for random access to their
+/// contents.
+///
+/// The index is built from a single pass over a seekable archive, skipping
+/// over the contents of entries. Afterwards the contents of any regular file
+/// can be read by seeking directly to it, without iterating over the archive
+/// again.
+///
+/// Paths are resolved like those of [`Entry`](crate::Entry), taking GNU long
+/// names and pax records into account. Leading `./` components and trailing
+/// slashes are ignored when looking up a path. If a path occurs several times
+/// in the archive, then the last entry with that path is the one found, as
+/// that's the one which wins when unpacking the archive.
+///
+/// An index can also be persisted, either in a file next to the archive with
+/// [`write_to`](ArchiveIndex::write_to), or inside the archive itself as its
+/// last member with [`embed`](ArchiveIndex::embed). It's loaded again with
+/// [`Archive::load_index`] and [`Archive::load_embedded_index`], which check
+/// that it still matches the archive.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::Read;
+/// use tar::{Archive, ArchiveIndex};
+///
+/// let mut ar = Archive::new(File::open("foo.tar").unwrap());
+/// let index = ArchiveIndex::build(&mut ar).unwrap();
+/// let mut file = ar.into_inner();
+///
+/// let mut contents = String::new();
+/// index
+///     .open(&mut file, "docs/README")
+///     .unwrap()
+///     .read_to_string(&mut contents)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveIndex {
+    entries: Vec<IndexEntry>,
+    paths: HashMap<Vec<u8>, usize>,
+    end: u64,
+}
+
+/// The location and metadata of a single entry of an [`ArchiveIndex`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+    path: Vec<u8>,
+    link_name: Option<Vec<u8>>,
+    header_pos: u64,
+    file_pos: u64,
+    size: u64,
+    cksum: u32,
+    entry_type: EntryType,
+    mode: Option<u32>,
+    sparse: bool,
+    digest: Option<String>,
+}
+
+impl ArchiveIndex {
+    /// The path of the member which [`embed`](ArchiveIndex::embed) appends to
+    /// an archive.
+    ///
+    /// Other tools see this member as a regular file, so it's also created
+    /// when such an archive is unpacked.
+    pub const EMBEDDED_NAME: &'static str = ".tar-index";
+
+    /// Builds an index of all entries of `archive`.
+    ///
+    /// The archive must not have been read from yet, just like when calling
+    /// [`Archive::entries_with_seek`]. An index embedded in the archive is
+    /// not part of the index built.
+    pub fn build<R: Read + Seek>(archive: &mut Archive<R>) -> io::Result<ArchiveIndex> {
+        ArchiveIndex::_build(archive, None)
+    }
+
+    /// Builds an index of all entries of `archive`, recording a digest of the
+    /// contents of each regular file.
+    ///
+    /// `digest` is called with the contents of every regular file and returns
+    /// its digest, in a form such as `sha256:<hex>`. The digest is stored as
+    /// is, so that it can be checked later by whoever reads the contents; it
+    /// must be non-empty and must not contain whitespace.
+    ///
+    /// Unlike [`build`](ArchiveIndex::build) this reads the whole archive.
+    pub fn build_with_digests<R, F>(
+        archive: &mut Archive<R>,
+        mut digest: F,
+    ) -> io::Result<ArchiveIndex>
+    where
+        R: Read + Seek,
+        F: FnMut(&mut dyn Read) -> io::Result<String>,
+    {
+        ArchiveIndex::_build(archive, Some(&mut digest))
+    }
+
+    fn _build<R: Read + Seek>(
+        archive: &mut Archive<R>,
+        mut digest: Option<&mut DigestFn<'_>>,
+    ) -> io::Result<ArchiveIndex> {
+        let mut index = ArchiveIndex::default();
+        let mut entries = archive.entries_with_seek()?;
+        while let Some(entry) = entries.next() {
+            let mut entry = entry?;
+            if *normalize(&entry.path_bytes()) == *ArchiveIndex::EMBEDDED_NAME.as_bytes() {
+                continue;
+            }
+            // Where the entries end, after any extension blocks of sparse
+            // files, which is where an embedded index goes.
+            index.end = entries.next_header_position();
+            let entry_type = entry.header().entry_type();
+            let digest = match digest {
+                Some(ref mut f) if entry_type.is_file() => {
+                    let digest = f(&mut entry)?;
+                    if !valid_digest(&digest) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("invalid digest `{}`", digest),
+                        ));
+                    }
+                    Some(digest)
+                }
+                _ => None,
+            };
+            index.push(IndexEntry {
+                path: entry.path_bytes().into_owned(),
+                link_name: entry.link_name_bytes().map(|l| l.into_owned()),
+                header_pos: entry.raw_header_position(),
+                file_pos: entry.raw_file_position(),
+                size: entry.stored_size(),
+                cksum: entry.header().cksum()?,
+                entry_type,
+                mode: entry.header().mode().ok(),
+                sparse: entry.sparse_map().is_some(),
+                digest,
+            });
+        }
+        Ok(index)
+    }
+
+    /// Writes this index to `dst`, e.g. a file stored next to the archive.
+    ///
+    /// The index is written in a line-based text format, which is read back
+    /// by [`read_from`](ArchiveIndex::read_from) and
+    /// [`Archive::load_index`].
+    pub fn write_to<W: Write>(&self, mut dst: W) -> io::Result<()> {
+        dst.write_all(self.to_text().as_bytes())
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        text.push_str(MAGIC);
+        text.push('\n');
+        for entry in &self.entries {
+            let _ = write!(
+                text,
+                "{} {} {} {:o} ",
+                entry.header_pos, entry.file_pos, entry.size, entry.cksum
+            );
+            escape(&mut text, &[entry.entry_type.as_byte()]);
+            text.push_str(if entry.sparse { " sparse" } else { " -" });
+            match entry.mode {
+                Some(mode) => {
+                    let _ = write!(text, " {:o} ", mode);
+                }
+                None => text.push_str(" - "),
+            }
+            text.push_str(entry.digest.as_deref().unwrap_or("-"));
+            text.push(' ');
+            escape(&mut text, &entry.path);
+            if let Some(ref link_name) = entry.link_name {
+                text.push(' ');
+                escape(&mut text, link_name);
+            }
+            text.push('\n');
+        }
+        let _ = writeln!(text, "%end {}", self.end);
+        text
+    }
+
+    /// Reads an index written by [`write_to`](ArchiveIndex::write_to).
+    ///
+    /// This doesn't check whether the index matches any archive; use
+    /// [`Archive::load_index`] for that.
+    pub fn read_from<R: Read>(src: R) -> io::Result<ArchiveIndex> {
+        let mut lines = BufReader::new(src).lines();
+        match lines.next() {
+            Some(Ok(ref line)) if line == MAGIC => {}
+            Some(Err(e)) => return Err(e),
+            _ => return Err(other("not an archive index")),
+        }
+        let mut index = ArchiveIndex::default();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if let Some(end) = line.strip_prefix("%end ") {
+                index.end = end.parse().map_err(|_| {
+                    other(&format!("malformed archive index: invalid line {}", i + 2))
+                })?;
+                continue;
+            }
+            let entry = parse_entry(&line).ok_or_else(|| {
+                other(&format!("malformed archive index: invalid line {}", i + 2))
+            })?;
+            index.push(entry);
+        }
+        Ok(index)
+    }
+
+    /// Appends this index to the archive in `dst` as its last member, named
+    /// [`EMBEDDED_NAME`](ArchiveIndex::EMBEDDED_NAME).
+    ///
+    /// `dst` must hold the archive the index was built from, starting at
+    /// offset 0. The member is written right after the last entry of the
+    /// index, followed by a new end-of-archive marker, and the file is
+    /// truncated after it, so an index embedded earlier is replaced. The
+    /// archive stays readable by any tar reader.
+    pub fn embed(&self, dst: &mut File) -> io::Result<()> {
+        // The index ends with a line holding the position of its own header.
+        let pos = self.end;
+        let data = self.to_text();
+
+        let mut header = Header::new_ustar();
+        header.set_path(ArchiveIndex::EMBEDDED_NAME)?;
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+
+        dst.seek(SeekFrom::Start(pos))?;
+        let mut builder = Builder::new(dst);
+        builder.append(&header, data.as_bytes())?;
+        let dst = builder.into_inner()?;
+        let end = dst.stream_position()?;
+        dst.set_len(end)
+    }
+
+    /// Checks that every entry of this index matches the header found at its
+    /// position in the archive read by `reader`, which starts at `base`.
+    pub(crate) fn verify<R: Read + Seek + ?Sized>(
+        &self,
+        reader: &mut R,
+        base: u64,
+    ) -> io::Result<()> {
+        let mut header = Header::new_old();
+        for entry in &self.entries {
+            let mismatch = |what: &str| {
+                other(&format!(
+                    "archive index doesn't match the archive: {} of `{}` differs",
+                    what,
+                    String::from_utf8_lossy(&entry.path)
+                ))
+            };
+            reader.seek(SeekFrom::Start(base + entry.header_pos))?;
+            reader.read_exact(header.as_mut_bytes())?;
+            let cksum = header.cksum().ok();
+            if cksum != Some(header.calculate_cksum()) || cksum != Some(entry.cksum) {
+                return Err(mismatch("header"));
+            }
+            if entry.file_pos != entry.header_pos + 512 {
+                return Err(mismatch("position"));
+            }
+            if header.entry_type() != entry.entry_type {
+                return Err(mismatch("type"));
+            }
+            if header.mode().ok() != entry.mode {
+                return Err(mismatch("mode"));
+            }
+            // Sizes which don't fit in the header are stored in pax records.
+            if header.entry_size().ok() != Some(entry.size) && entry.size <= 0o77777777777 {
+                return Err(mismatch("size"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds and verifies the index embedded in the archive read by `reader`,
+    /// which starts at `base`, if there is one.
+    pub(crate) fn find_embedded<R: Read + Seek + ?Sized>(
+        reader: &mut R,
+        base: u64,
+    ) -> io::Result<Option<ArchiveIndex>> {
+        // The embedded index ends with a line pointing at its own header, so
+        // find the last block of it, just before the end-of-archive marker.
+        let len = reader.seek(SeekFrom::End(0))?;
+        if len < base {
+            return Ok(None);
+        }
+        let mut end = base + (len - base) / 512 * 512;
+        let mut block = [0; 512];
+        loop {
+            if end < base + 1024 {
+                return Ok(None);
+            }
+            reader.seek(SeekFrom::Start(end - 512))?;
+            reader.read_exact(&mut block)?;
+            if block.iter().any(|b| *b != 0) {
+                break;
+            }
+            end -= 512;
+        }
+        let mut tail = [0; 1024];
+        reader.seek(SeekFrom::Start(end - 1024))?;
+        reader.read_exact(&mut tail)?;
+        let tail = match tail.iter().rposition(|b| *b != 0) {
+            Some(i) if tail[i] == b'\n' => &tail[..i],
+            _ => return Ok(None),
+        };
+        let start = tail.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        let pos = match str::from_utf8(&tail[start..])
+            .ok()
+            .and_then(|line| line.strip_prefix("%end "))
+            .and_then(|pos| pos.parse::<u64>().ok())
+        {
+            Some(pos) if pos % 512 == 0 && base + pos + 512 < end => pos,
+            _ => return Ok(None),
+        };
+
+        let mut header = Header::new_old();
+        reader.seek(SeekFrom::Start(base + pos))?;
+        reader.read_exact(header.as_mut_bytes())?;
+        let size = match header.entry_size() {
+            Ok(size) if header.cksum().ok() == Some(header.calculate_cksum()) => size,
+            _ => return Ok(None),
+        };
+        if header.path_bytes() != ArchiveIndex::EMBEDDED_NAME.as_bytes()
+            || base + pos + 512 + size > end
+        {
+            return Ok(None);
+        }
+        let index = ArchiveIndex::read_from(reader.take(size))?;
+        index.verify(reader, base)?;
+        Ok(Some(index))
+    }
+
+    fn push(&mut self, entry: IndexEntry) {
+        self.paths
+            .insert(normalize(&entry.path).into_owned(), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    /// Returns all entries of the archive, in the order in which they appear
+    /// in it.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Returns the number of entries in the archive.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether the archive has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Looks up the entry with the given path.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&IndexEntry> {
+        self._get(path.as_ref())
+    }
+
+    fn _get(&self, path: &Path) -> Option<&IndexEntry> {
+        let path = path2bytes(path).ok()?;
+        self.paths
+            .get(&normalize(&path)[..])
+            .map(|i| &self.entries[*i])
+    }
+
+    /// Opens the contents of the entry with the given path for reading.
+    ///
+    /// `reader` must read the same archive that this index was built from,
+    /// with the archive starting at offset 0. See [`IndexEntry::open`] for
+    /// the details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotFound` if the archive has no entry with
+    /// this path, or any error of opening the entry.
+    pub fn open<'r, R: Read + Seek, P: AsRef<Path>>(
+        &self,
+        reader: &'r mut R,
+        path: P,
+    ) -> io::Result<io::Take<&'r mut R>> {
+        match self._get(path.as_ref()) {
+            Some(entry) => entry.open(reader),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` is not in the archive", path.as_ref().display()),
+            )),
+        }
+    }
+}
+
+impl IndexEntry {
+    /// Returns the path name of this entry.
+    ///
+    /// This method may fail if the pathname is not valid Unicode and this is
+    /// called on a Windows platform.
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
+        bytes2path(Cow::Borrowed(&self.path))
+    }
+
+    /// Returns the path name of this entry in bytes.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.path
+    }
+
+    /// Returns the link name of this entry in bytes, if any.
+    pub fn link_name_bytes(&self) -> Option<&[u8]> {
+        self.link_name.as_deref()
+    }
+
+    /// Returns the position of the header of this entry in the archive, as
+    /// `Entry::raw_header_position` does.
+    pub fn raw_header_position(&self) -> u64 {
+        self.header_pos
+    }
+
+    /// Returns the position of the contents of this entry in the archive, as
+    /// `Entry::raw_file_position` does.
+    pub fn raw_file_position(&self) -> u64 {
+        self.file_pos
+    }
+
+    /// Returns the size of the contents of this entry in the archive.
+    ///
+    /// For sparse files this is the size of their data as stored in the
+    /// archive, not the size of the file.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the type of this entry.
+    pub fn entry_type(&self) -> EntryType {
+        self.entry_type
+    }
+
+    /// Returns the mode bits of this entry, or `None` if the header of the
+    /// entry doesn't hold valid ones.
+    pub fn mode(&self) -> Option<u32> {
+        self.mode
+    }
+
+    /// Returns whether this entry is a sparse file.
+    pub fn is_sparse(&self) -> bool {
+        self.sparse
+    }
+
+    /// Returns the digest of the contents of this entry, if the index was
+    /// built with [`ArchiveIndex::build_with_digests`] and this is a regular
+    /// file.
+    pub fn digest(&self) -> Option<&str> {
+        self.digest.as_deref()
+    }
+
+    /// Opens the contents of this entry for reading, by seeking `reader` to
+    /// them.
+    ///
+    /// `reader` must read the same archive that the index was built from,
+    /// with the archive starting at offset 0.
+    ///
+    /// # Errors
+    ///
+    /// The contents of sparse files aren't stored contiguously in the
+    /// archive, so they can't be opened this way and an error is returned.
+    pub fn open<'r, R: Read + Seek>(&self, reader: &'r mut R) -> io::Result<io::Take<&'r mut R>> {
+        if self.sparse {
+            return Err(other(&format!(
+                "`{}` is a sparse file which can't be opened from an index",
+                String::from_utf8_lossy(&self.path)
+            )));
+        }
+        reader.seek(SeekFrom::Start(self.file_pos))?;
+        Ok(reader.take(self.size))
+    }
+}
+
+/// Normalizes a path in an archive for lookups, dropping `.` components and
+/// redundant slashes.
+fn normalize(path: &[u8]) -> Cow<'_, [u8]> {
+    let is_normal = |c: &&[u8]| !c.is_empty() && *c != b".";
+    if path.split(|b| *b == b'/').all(|c| is_normal(&c)) {
+        return Cow::Borrowed(path);
+    }
+    Cow::Owned(
+        path.split(|b| *b == b'/')
+            .filter(is_normal)
+            .collect::<Vec<_>>()
+            .join(&b'/'),
+    )
+}
+
+/// Parses a line of a serialized index, in the format written by
+/// `ArchiveIndex::to_text`.
+fn parse_entry(line: &str) -> Option<IndexEntry> {
+    let mut fields = line.split(' ');
+    let mut next = || fields.next();
+    let header_pos = next()?.parse().ok()?;
+    let file_pos = next()?.parse().ok()?;
+    let size = next()?.parse().ok()?;
+    let cksum = u32::from_str_radix(next()?, 8).ok()?;
+    let entry_type = match unescape(next()?)?[..] {
+        [b] => EntryType::new(b),
+        _ => return None,
+    };
+    let sparse = match next()? {
+        "sparse" => true,
+        "-" => false,
+        _ => return None,
+    };
+    let mode = match next()? {
+        "-" => None,
+        mode => Some(u32::from_str_radix(mode, 8).ok()?),
+    };
+    let digest = match next()? {
+        "-" => None,
+        digest => Some(digest.to_string()),
+    };
+    let path = unescape(next()?)?;
+    let link_name = match next() {
+        Some(link_name) => Some(unescape(link_name)?),
+        None => None,
+    };
+    if next().is_some() {
+        return None;
+    }
+    Some(IndexEntry {
+        path,
+        link_name,
+        header_pos,
+        file_pos,
+        size,
+        cksum,
+        entry_type,
+        mode,
+        sparse,
+        digest,
+    })
+}
+
+/// Percent-encodes a field of a serialized index so that it contains no
+/// whitespace. An empty field is written as a lone `%`.
+fn escape(dst: &mut String, bytes: &[u8]) {
+    if bytes.is_empty() {
+        dst.push('%');
+    }
+    for &b in bytes {
+        if b <= b' ' || b >= 0x7f || b == b'%' {
+            let _ = write!(dst, "%{:02X}", b);
+        } else {
+            dst.push(b as char);
+        }
+    }
+}
+
+fn unescape(field: &str) -> Option<Vec<u8>> {
+    if field == "%" {
+        return Some(Vec::new());
+    }
+    url_decode(field.as_bytes())
+}
+
+fn valid_digest(digest: &str) -> bool {
+    !digest.is_empty() && digest != "-" && !digest.bytes().any(|b| b <= b' ' || b == 0x7f)
+}
@@ -23,21 +23,63 @@
 
 use std::io::{Error, ErrorKind};
 
-pub use crate::archive::{Archive, Entries};
+pub use crate::archive::{Archive, Entries, UnpackAction, UnpackVisitor};
+#[cfg(feature = "tokio")]
+pub use crate::async_archive::{AsyncArchive, AsyncEntries, AsyncEntry};
+#[cfg(feature = "tokio")]
+pub use crate::async_builder::AsyncBuilder;
 pub use crate::builder::Builder;
-pub use crate::entry::{Entry, Unpacked};
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+pub use crate::builder::CompressedBuilder;
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+pub use crate::compression::Compressor;
+#[cfg(any(
+    feature = "bzip2",
+    feature = "flate2",
+    feature = "ruzstd",
+    feature = "xz2",
+    feature = "zstd"
+))]
+pub use crate::compression::{Compression, Decompressor};
+pub use crate::entry::{
+    Durability, Entry, OverwritePolicy, SparseMap, Unpacked, XattrFilter, XattrWarning,
+};
 pub use crate::entry_type::EntryType;
 pub use crate::header::GnuExtSparseHeader;
 pub use crate::header::{GnuHeader, GnuSparseHeader, Header, HeaderMode, OldHeader, UstarHeader};
-pub use crate::pax::{PaxExtension, PaxExtensions};
+pub use crate::index::{ArchiveIndex, IndexEntry};
+pub use crate::memory::{MemoryEntry, MemoryFile, MemoryTarget};
+pub use crate::pax::{PaxBuilder, PaxExtension, PaxExtensions, PaxMap};
+pub use crate::plan::{PlannedAction, PlannedEntry};
+pub use crate::shared::{ReadAt, SharedArchive, SharedEntry};
+pub use crate::target::{FsTarget, TargetMetadata, UnpackTarget};
 
+#[cfg(all(feature = "acl", target_os = "linux"))]
+mod acl;
 mod archive;
+#[cfg(feature = "tokio")]
+mod async_archive;
+#[cfg(feature = "tokio")]
+mod async_builder;
 mod builder;
+#[cfg(any(
+    feature = "bzip2",
+    feature = "flate2",
+    feature = "ruzstd",
+    feature = "xz2",
+    feature = "zstd"
+))]
+mod compression;
 mod entry;
 mod entry_type;
 mod error;
 mod header;
+mod index;
+mod memory;
 mod pax;
+mod plan;
+mod shared;
+mod target;
 
 fn other(msg: &str) -> Error {
     Error::new(ErrorKind::Other, msg)
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::io::{self, Error, ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::other;
+use crate::target::{self, TargetMetadata, UnpackTarget};
+
+/// An [`UnpackTarget`] which keeps everything it's given in memory.
+///
+/// This is useful for inspecting the contents of an archive as it would be
+/// unpacked, for example in tests, without touching the filesystem. All
+/// paths are relative to the root of the target, so `/foo` and `foo` refer
+/// to the same entry, and symlinks are resolved within the target.
+///
+/// # Examples
+///
+/// ```
+/// use tar::{Archive, Builder, Header, MemoryTarget};
+///
+/// let mut builder = Builder::new(Vec::new());
+/// let mut header = Header::new_gnu();
+/// header.set_size(5);
+/// header.set_cksum();
+/// builder.append_data(&mut header, "dir/hello.txt", &b"hello"[..]).unwrap();
+/// let data = builder.into_inner().unwrap();
+///
+/// let mut target = MemoryTarget::new();
+/// Archive::new(&data[..]).unpack_into(&mut target, "/").unwrap();
+///
+/// assert!(target.get("dir").unwrap().is_dir());
+/// assert_eq!(target.get("dir/hello.txt").unwrap().contents(), Some(&b"hello"[..]));
+/// ```
+#[derive(Debug)]
+pub struct MemoryTarget {
+    nodes: BTreeMap<PathBuf, Arc<Mutex<MemoryEntry>>>,
+}
+
+/// A file, directory or symlink in a [`MemoryTarget`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryEntry {
+    kind: Kind,
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    atime: u64,
+    mtime: u64,
+    xattrs: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    File(Vec<u8>),
+    Dir,
+    Symlink(PathBuf),
+}
+
+/// A handle to a regular file being written to a [`MemoryTarget`].
+#[derive(Debug)]
+pub struct MemoryFile {
+    entry: Arc<Mutex<MemoryEntry>>,
+    pos: usize,
+}
+
+impl MemoryTarget {
+    /// Creates a new target containing only an empty root directory.
+    pub fn new() -> MemoryTarget {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::new(), MemoryEntry::new(Kind::Dir, 0o755));
+        MemoryTarget { nodes }
+    }
+
+    /// Returns a copy of the entry at `path`, if there is one.
+    ///
+    /// Symlinks in the parent directories of `path` are followed, but a
+    /// symlink at `path` itself is returned as is.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<MemoryEntry> {
+        let key = self.resolve(path.as_ref(), false).ok()?;
+        self.nodes.get(&key).map(|e| e.lock().unwrap().clone())
+    }
+
+    /// Returns the paths of all entries in this target, in sorted order.
+    ///
+    /// The paths are relative to the root of the target, which itself isn't
+    /// included.
+    pub fn paths(&self) -> impl Iterator<Item = &Path> + '_ {
+        self.nodes
+            .keys()
+            .filter(|p| *p != Path::new(""))
+            .map(|p| p.as_path())
+    }
+
+    /// Resolves `path` to the key of its entry, following symlinks in all of
+    /// its parents and, if `follow` is true, a symlink at `path` itself.
+    ///
+    /// The entry doesn't need to exist, but all of its parents do.
+    fn resolve(&self, path: &Path, follow: bool) -> io::Result<PathBuf> {
+        target::resolve_symlinks(path, Path::new(""), |candidate, last| {
+            let entry = match self.nodes.get(candidate) {
+                Some(entry) => entry.lock().unwrap(),
+                None if last => return Ok(None),
+                None => return Err(not_found(path)),
+            };
+            match entry.kind {
+                Kind::Symlink(ref target) if follow || !last => Ok(Some(target.clone())),
+                Kind::File(_) | Kind::Symlink(_) if !last => {
+                    Err(other(&format!("not a directory: `{}`", path.display())))
+                }
+                _ => Ok(None),
+            }
+        })
+    }
+
+    fn lookup(&self, path: &Path, follow: bool) -> io::Result<&Arc<Mutex<MemoryEntry>>> {
+        let key = self.resolve(path, follow)?;
+        self.nodes.get(&key).ok_or_else(|| not_found(path))
+    }
+
+    fn insert(&mut self, path: &Path, entry: Arc<Mutex<MemoryEntry>>) -> io::Result<()> {
+        let key = self.resolve(path, false)?;
+        if key == Path::new("") || self.nodes.contains_key(&key) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("`{}` already exists", path.display()),
+            ));
+        }
+        self.nodes.insert(key, entry);
+        Ok(())
+    }
+
+    fn entry(
+        &self,
+        path: &Path,
+        file: Option<&mut MemoryFile>,
+    ) -> io::Result<Arc<Mutex<MemoryEntry>>> {
+        match file {
+            Some(file) => Ok(file.entry.clone()),
+            None => self.lookup(path, false).cloned(),
+        }
+    }
+}
+
+impl Default for MemoryTarget {
+    fn default() -> MemoryTarget {
+        MemoryTarget::new()
+    }
+}
+
+impl UnpackTarget for MemoryTarget {
+    type File = MemoryFile;
+
+    fn create_file(&mut self, path: &Path) -> io::Result<MemoryFile> {
+        let entry = MemoryEntry::new(Kind::File(Vec::new()), 0o644);
+        self.insert(path, entry.clone())?;
+        Ok(MemoryFile { entry, pos: 0 })
+    }
+
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        self.insert(path, MemoryEntry::new(Kind::Dir, 0o755))
+    }
+
+    fn symlink(&mut self, src: &Path, dst: &Path) -> io::Result<()> {
+        let entry = MemoryEntry::new(Kind::Symlink(src.to_path_buf()), 0o777);
+        self.insert(dst, entry)
+    }
+
+    fn hard_link(&mut self, src: &Path, dst: &Path) -> io::Result<()> {
+        let entry = self.lookup(src, false)?.clone();
+        if entry.lock().unwrap().is_dir() {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("`{}` is a directory", src.display()),
+            ));
+        }
+        self.insert(dst, entry)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<TargetMetadata> {
+        Ok(self.lookup(path, false)?.lock().unwrap().metadata())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<TargetMetadata> {
+        Ok(self.lookup(path, true)?.lock().unwrap().metadata())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let key = self.resolve(path, true)?;
+        if !self.nodes.contains_key(&key) {
+            return Err(not_found(path));
+        }
+        Ok(Path::new(Component::RootDir.as_os_str()).join(key))
+    }
+
+    fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        let key = self.resolve(path, false)?;
+        match self.nodes.get(&key) {
+            Some(entry) if entry.lock().unwrap().is_dir() => {
+                Err(other(&format!("`{}` is a directory", path.display())))
+            }
+            Some(_) => {
+                self.nodes.remove(&key);
+                Ok(())
+            }
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> io::Result<()> {
+        let key = self.resolve(path, false)?;
+        match self.nodes.get(&key) {
+            Some(entry) if !entry.lock().unwrap().is_dir() => {
+                return Err(other(&format!("not a directory: `{}`", path.display())));
+            }
+            Some(_) => {}
+            None => return Err(not_found(path)),
+        }
+        let has_children = matches!(
+            self.nodes.range(key.clone()..).nth(1),
+            Some((child, _)) if child.starts_with(&key)
+        );
+        if key == Path::new("") || has_children {
+            return Err(other(&format!("directory not empty: `{}`", path.display())));
+        }
+        self.nodes.remove(&key);
+        Ok(())
+    }
+
+    fn remove_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let key = self.resolve(path, false)?;
+        if !self.nodes.contains_key(&key) {
+            return Err(not_found(path));
+        }
+        if key == Path::new("") {
+            return Err(other("cannot remove the root directory"));
+        }
+        self.nodes.retain(|p, _| !p.starts_with(&key));
+        Ok(())
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let from_key = self.resolve(from, false)?;
+        let to_key = self.resolve(to, false)?;
+        if !self.nodes.contains_key(&from_key) {
+            return Err(not_found(from));
+        }
+        if from_key == to_key {
+            return Ok(());
+        }
+        if let Some(entry) = self.nodes.get(&to_key) {
+            if entry.lock().unwrap().is_dir() {
+                return Err(other(&format!("`{}` is a directory", to.display())));
+            }
+        }
+        if to_key.starts_with(&from_key) {
+            return Err(other(&format!(
+                "cannot move `{}` inside of itself",
+                from.display()
+            )));
+        }
+        let moved = self
+            .nodes
+            .keys()
+            .filter(|p| p.starts_with(&from_key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in moved {
+            let entry = self.nodes.remove(&path).unwrap();
+            let rest = path.strip_prefix(&from_key).unwrap();
+            self.nodes.insert(to_key.join(rest), entry);
+        }
+        Ok(())
+    }
+
+    fn set_times(
+        &mut self,
+        path: &Path,
+        file: Option<&mut MemoryFile>,
+        atime: Option<u64>,
+        mtime: Option<u64>,
+    ) -> io::Result<()> {
+        let entry = self.entry(path, file)?;
+        let mut entry = entry.lock().unwrap();
+        if let Some(atime) = atime {
+            entry.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            entry.mtime = mtime;
+        }
+        Ok(())
+    }
+
+    fn set_ownership(
+        &mut self,
+        path: &Path,
+        file: Option<&mut MemoryFile>,
+        uid: u64,
+        gid: u64,
+    ) -> io::Result<()> {
+        let entry = self.entry(path, file)?;
+        let mut entry = entry.lock().unwrap();
+        entry.uid = uid;
+        entry.gid = gid;
+        Ok(())
+    }
+
+    fn set_permissions(
+        &mut self,
+        path: &Path,
+        file: Option<&mut MemoryFile>,
+        mode: u32,
+    ) -> io::Result<()> {
+        self.entry(path, file)?.lock().unwrap().mode = mode;
+        Ok(())
+    }
+
+    fn set_xattr(&mut self, path: &Path, name: &[u8], value: &[u8]) -> io::Result<()> {
+        let entry = self.lookup(path, true)?;
+        let mut entry = entry.lock().unwrap();
+        entry.xattrs.insert(name.to_vec(), value.to_vec());
+        Ok(())
+    }
+}
+
+impl MemoryEntry {
+    fn new(kind: Kind, mode: u32) -> Arc<Mutex<MemoryEntry>> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Arc::new(Mutex::new(MemoryEntry {
+            kind,
+            mode,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            xattrs: BTreeMap::new(),
+        }))
+    }
+
+    fn metadata(&self) -> TargetMetadata {
+        TargetMetadata::new(self.is_dir(), self.mtime as i64)
+    }
+
+    /// Returns whether this is a regular file.
+    pub fn is_file(&self) -> bool {
+        matches!(self.kind, Kind::File(_))
+    }
+
+    /// Returns whether this is a directory.
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, Kind::Dir)
+    }
+
+    /// Returns whether this is a symlink.
+    pub fn is_symlink(&self) -> bool {
+        matches!(self.kind, Kind::Symlink(_))
+    }
+
+    /// Returns the contents of this entry if it's a regular file.
+    pub fn contents(&self) -> Option<&[u8]> {
+        match self.kind {
+            Kind::File(ref data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Returns the target of this entry if it's a symlink.
+    pub fn link_target(&self) -> Option<&Path> {
+        match self.kind {
+            Kind::Symlink(ref target) => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Returns the permission bits of this entry.
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Returns the numeric owner of this entry.
+    pub fn uid(&self) -> u64 {
+        self.uid
+    }
+
+    /// Returns the numeric group of this entry.
+    pub fn gid(&self) -> u64 {
+        self.gid
+    }
+
+    /// Returns the access time of this entry, in seconds since the Unix
+    /// epoch.
+    pub fn atime(&self) -> u64 {
+        self.atime
+    }
+
+    /// Returns the modification time of this entry, in seconds since the Unix
+    /// epoch.
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    /// Returns the value of the extended attribute `name` of this entry.
+    pub fn xattr(&self, name: &[u8]) -> Option<&[u8]> {
+        self.xattrs.get(name).map(|v| &v[..])
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut entry = self.entry.lock().unwrap();
+        let data = match entry.kind {
+            Kind::File(ref mut data) => data,
+            _ => return Err(other("not a regular file")),
+        };
+        let end = self.pos + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[self.pos..end].copy_from_slice(buf);
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("`{}` does not exist", path.display()),
+    )
+}
@@ -1,7 +1,8 @@
 #![allow(dead_code)]
+use std::collections::BTreeMap;
 use std::io;
-use std::slice;
 use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::other;
 
@@ -19,8 +20,12 @@ pub const PAX_ATIME: &str = "atime";
 pub const PAX_CTIME: &str = "ctime"; // Removed from later revision of PAX spec, but was valid
 pub const PAX_CHARSET: &str = "charset"; // Currently unused
 pub const PAX_COMMENT: &str = "comment"; // Currently unused
+pub const PAX_HDRCHARSET: &str = "hdrcharset";
 
 pub const PAX_SCHILYXATTR: &str = "SCHILY.xattr.";
+// Written by libarchive alongside `SCHILY.xattr.`, with a URL-encoded name and
+// a base64-encoded value.
+pub const PAX_LIBARCHIVEXATTR: &str = "LIBARCHIVE.xattr.";
 
 // Keywords for GNU sparse files in a PAX extended header.
 pub const PAX_GNUSPARSE: &str = "GNU.sparse.";
@@ -39,18 +44,13 @@ pub const PAX_GNUSPARSEREALSIZE: &str = "GNU.sparse.realsize";
 /// This iterator yields structures which can themselves be parsed into
 /// key/value pairs.
 pub struct PaxExtensions<'entry> {
-    data: slice::Split<'entry, u8, fn(&u8) -> bool>,
+    data: &'entry [u8],
 }
 
 impl<'entry> PaxExtensions<'entry> {
     /// Create new pax extensions iterator from the given entry data.
     pub fn new(a: &'entry [u8]) -> Self {
-        fn is_newline(a: &u8) -> bool {
-            *a == b'\n'
-        }
-        PaxExtensions {
-            data: a.split(is_newline),
-        }
+        PaxExtensions { data: a }
     }
 }
 
@@ -60,64 +60,434 @@ pub struct PaxExtension<'entry> {
     value: &'entry [u8],
 }
 
-pub fn pax_extensions_value(a: &[u8], key: &str) -> Option<u64> {
-    for extension in PaxExtensions::new(a) {
-        let current_extension = match extension {
-            Ok(ext) => ext,
-            Err(_) => return None,
-        };
-        if current_extension.key() != Ok(key) {
+/// The pax extended header records which apply to an entry, parsed into a
+/// map.
+///
+/// Records from global pax headers earlier in the archive are layered with
+/// the records from the local pax header of the entry, which take precedence.
+/// As specified by POSIX a record with an empty value removes the keyword,
+/// so that the value in the entry's header is used instead.
+///
+/// # Examples
+///
+/// ```
+/// use tar::{Archive, Builder, Header};
+///
+/// let mut ar = Builder::new(Vec::new());
+/// ar.append_pax_global_extensions(vec![("comment", &b"shared"[..])]).unwrap();
+/// ar.append_pax_extensions(vec![("mtime", &b"1453146164.953123768"[..])]).unwrap();
+/// let mut header = Header::new_ustar();
+/// header.set_size(0);
+/// ar.append_data(&mut header, "foo", &[][..]).unwrap();
+/// let data = ar.into_inner().unwrap();
+///
+/// let mut ar = Archive::new(&data[..]);
+/// let entry = ar.entries().unwrap().nth(1).unwrap().unwrap();
+/// let pax = entry.pax().unwrap();
+/// assert_eq!(pax.comment(), Some("shared"));
+/// assert!(pax.mtime().is_some());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaxMap {
+    records: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PaxMap {
+    /// Creates an empty map.
+    pub fn new() -> PaxMap {
+        PaxMap::default()
+    }
+
+    /// Layers the records of a pax extended header on top of this map.
+    ///
+    /// Records up to the first malformed one are taken into account.
+    pub fn update(&mut self, data: &[u8]) {
+        for ext in PaxExtensions::new(data).filter_map(|e| e.ok()) {
+            if ext.value_bytes().is_empty() {
+                self.records.remove(ext.key_bytes());
+            } else {
+                self.records
+                    .insert(ext.key_bytes().to_vec(), ext.value_bytes().to_vec());
+            }
+        }
+    }
+
+    /// Returns the raw value of the record for `key`.
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.records.get(key.as_bytes()).map(|v| &v[..])
+    }
+
+    /// Returns the value of the record for `key`, if it is valid UTF-8.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(|v| str::from_utf8(v).ok())
+    }
+
+    /// Returns the value of the record for `key` parsed as a decimal number.
+    pub fn get_u64(&self, key: &str) -> Option<u64> {
+        self.get_str(key).and_then(|v| v.parse().ok())
+    }
+
+    /// Returns the value of the record for `key` parsed as a timestamp, in
+    /// seconds since the Unix epoch with an optional fractional part.
+    pub fn get_time(&self, key: &str) -> Option<SystemTime> {
+        let (negative, secs, nanos) = parse_time(self.get(key)?)?;
+        let offset = Duration::new(secs, nanos);
+        if negative {
+            UNIX_EPOCH.checked_sub(offset)
+        } else {
+            UNIX_EPOCH.checked_add(offset)
+        }
+    }
+
+    /// Returns the `path` record, the path name of the entry.
+    pub fn path(&self) -> Option<&[u8]> {
+        self.get(PAX_PATH)
+    }
+
+    /// Returns the `linkpath` record, the target of a link.
+    pub fn linkpath(&self) -> Option<&[u8]> {
+        self.get(PAX_LINKPATH)
+    }
+
+    /// Returns the `size` record, the size of the entry in bytes.
+    pub fn size(&self) -> Option<u64> {
+        self.get_u64(PAX_SIZE)
+    }
+
+    /// Returns the `uid` record, the numeric user ID of the owner.
+    pub fn uid(&self) -> Option<u64> {
+        self.get_u64(PAX_UID)
+    }
+
+    /// Returns the `gid` record, the numeric group ID of the owner.
+    pub fn gid(&self) -> Option<u64> {
+        self.get_u64(PAX_GID)
+    }
+
+    /// Returns the `uname` record, the user name of the owner.
+    pub fn uname(&self) -> Option<&[u8]> {
+        self.get(PAX_UNAME)
+    }
+
+    /// Returns the `gname` record, the group name of the owner.
+    pub fn gname(&self) -> Option<&[u8]> {
+        self.get(PAX_GNAME)
+    }
+
+    /// Returns the `mtime` record, the modification time.
+    pub fn mtime(&self) -> Option<SystemTime> {
+        self.get_time(PAX_MTIME)
+    }
+
+    /// Returns the `atime` record, the access time.
+    pub fn atime(&self) -> Option<SystemTime> {
+        self.get_time(PAX_ATIME)
+    }
+
+    /// Returns the `ctime` record, the status change time.
+    pub fn ctime(&self) -> Option<SystemTime> {
+        self.get_time(PAX_CTIME)
+    }
+
+    /// Returns the `comment` record.
+    pub fn comment(&self) -> Option<&str> {
+        self.get_str(PAX_COMMENT)
+    }
+
+    /// Returns the `charset` record, the character set of the entry's
+    /// contents.
+    pub fn charset(&self) -> Option<&str> {
+        self.get_str(PAX_CHARSET)
+    }
+
+    /// Returns the `hdrcharset` record, the character set of the other
+    /// records.
+    pub fn hdrcharset(&self) -> Option<&str> {
+        self.get_str(PAX_HDRCHARSET)
+    }
+
+    /// Returns an iterator over the names and values of the extended
+    /// attributes stored in `SCHILY.xattr.` records.
+    pub fn xattrs(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        let prefix = PAX_SCHILYXATTR.as_bytes();
+        self.iter()
+            .filter(move |(key, _)| key.starts_with(prefix))
+            .map(move |(key, value)| (&key[prefix.len()..], value))
+    }
+
+    /// Returns an iterator over all records, ordered by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.records.iter().map(|(k, v)| (&k[..], &v[..]))
+    }
+
+    /// Returns an iterator over the records which have no typed getter on
+    /// this map, and which aren't interpreted by this crate otherwise.
+    pub fn unknown(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.iter().filter(|(key, _)| !is_known(key))
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns whether there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+fn is_known(key: &[u8]) -> bool {
+    const KEYS: &[&str] = &[
+        PAX_PATH,
+        PAX_LINKPATH,
+        PAX_SIZE,
+        PAX_UID,
+        PAX_GID,
+        PAX_UNAME,
+        PAX_GNAME,
+        PAX_MTIME,
+        PAX_ATIME,
+        PAX_CTIME,
+        PAX_CHARSET,
+        PAX_COMMENT,
+        PAX_HDRCHARSET,
+    ];
+    const PREFIXES: &[&str] = &[PAX_SCHILYXATTR, PAX_LIBARCHIVEXATTR, PAX_GNUSPARSE];
+    KEYS.iter().any(|k| k.as_bytes() == key)
+        || PREFIXES.iter().any(|p| key.starts_with(p.as_bytes()))
+}
+
+/// Parses a pax timestamp of the form `[-]<seconds>[.<fraction>]`, returning
+/// whether it's negative along with its magnitude. Digits of the fraction
+/// beyond nanoseconds are dropped.
+pub fn parse_time(a: &[u8]) -> Option<(bool, u64, u32)> {
+    let s = str::from_utf8(a).ok()?;
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let (secs, fraction) = match s.find('.') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, ""),
+    };
+    if secs.is_empty() || !secs.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut nanos = 0;
+    for i in 0..9 {
+        let digit = fraction.as_bytes().get(i).map_or(0, |b| b - b'0');
+        nanos = nanos * 10 + digit as u32;
+    }
+    Some((negative, secs.parse().ok()?, nanos))
+}
+
+/// A builder for the data of a pax extended header.
+///
+/// Each record is serialized as `"<length> <key>=<value>\n"`, where the
+/// length in decimal counts the whole record including its own digits.
+/// Values are stored as raw bytes and may contain newlines or other binary
+/// data.
+///
+/// # Examples
+///
+/// ```
+/// use tar::{PaxBuilder, PaxExtensions};
+///
+/// let mut pax = PaxBuilder::new();
+/// pax.add("path", "a/very/long/path");
+/// pax.add_bytes("SCHILY.xattr.user.data", b"\x00\n\x01");
+/// assert!(pax.as_bytes().starts_with(b"25 path=a/very/long/path\n"));
+///
+/// let records = PaxExtensions::new(pax.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+/// assert_eq!(records[1].value_bytes(), b"\x00\n\x01");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaxBuilder {
+    data: Vec<u8>,
+}
+
+impl PaxBuilder {
+    /// Creates a new builder without any records.
+    pub fn new() -> PaxBuilder {
+        PaxBuilder::default()
+    }
+
+    /// Adds a record with a UTF-8 value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty or contains `=` or a newline.
+    pub fn add(&mut self, key: &str, value: &str) -> &mut PaxBuilder {
+        self.add_bytes(key, value.as_bytes())
+    }
+
+    /// Adds a record with an arbitrary binary value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is empty or contains `=` or a newline.
+    pub fn add_bytes(&mut self, key: &str, value: &[u8]) -> &mut PaxBuilder {
+        assert!(valid_key(key), "invalid pax extension key {:?}", key);
+
+        // The length of the record includes the digits of the length itself,
+        // which may need one more digit once they are taken into account.
+        let rest = key.len() + value.len() + 3;
+        let mut len = rest + 1;
+        while len.to_string().len() + rest != len {
+            len = len.to_string().len() + rest;
+        }
+        self.data.extend_from_slice(len.to_string().as_bytes());
+        self.data.push(b' ');
+        self.data.extend_from_slice(key.as_bytes());
+        self.data.push(b'=');
+        self.data.extend_from_slice(value);
+        self.data.push(b'\n');
+        self
+    }
+
+    /// Returns whether no records have been added.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the serialized records, which form the contents of a pax
+    /// extended header.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consumes this builder, returning the serialized records.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Returns whether `key` can be the key of a pax record.
+pub fn valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains(&['=', '\n'][..])
+}
+
+/// Decodes a name which was URL-encoded by libarchive, returning `None` if an
+/// escape sequence is malformed.
+pub fn url_decode(a: &[u8]) -> Option<Vec<u8>> {
+    let mut ret = Vec::with_capacity(a.len());
+    let mut bytes = a.iter();
+    while let Some(&b) = bytes.next() {
+        if b != b'%' {
+            ret.push(b);
             continue;
         }
+        let hi = bytes.next().and_then(|b| (*b as char).to_digit(16))?;
+        let lo = bytes.next().and_then(|b| (*b as char).to_digit(16))?;
+        ret.push((hi * 16 + lo) as u8);
+    }
+    Some(ret)
+}
 
-        let value = match current_extension.value() {
-            Ok(value) => value,
-            Err(_) => return None,
-        };
-        let result = match value.parse::<u64>() {
-            Ok(result) => result,
-            Err(_) => return None,
-        };
-        return Some(result);
+/// Decodes a base64 value, with or without trailing padding, returning `None`
+/// if it isn't valid base64.
+pub fn base64_decode(a: &[u8]) -> Option<Vec<u8>> {
+    fn digit(b: u8) -> Option<u32> {
+        Some(match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as u32)
+    }
+
+    let a = match a.iter().position(|b| *b == b'=') {
+        Some(i) if a[i..].len() <= 2 && a[i..].iter().all(|b| *b == b'=') => &a[..i],
+        Some(_) => return None,
+        None => a,
+    };
+    if a.len() % 4 == 1 {
+        return None;
+    }
+    let mut ret = Vec::with_capacity(a.len() / 4 * 3 + 2);
+    for chunk in a.chunks(4) {
+        let mut bits = 0;
+        for (i, b) in chunk.iter().enumerate() {
+            bits |= digit(*b)? << (18 - 6 * i);
+        }
+        let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        ret.extend_from_slice(&bytes[..chunk.len() - 1]);
     }
-    None
+    Some(ret)
 }
 
 impl<'entry> Iterator for PaxExtensions<'entry> {
     type Item = io::Result<PaxExtension<'entry>>;
 
     fn next(&mut self) -> Option<io::Result<PaxExtension<'entry>>> {
-        let line = match self.data.next() {
-            Some(line) if line.is_empty() => return None,
-            Some(line) => line,
-            None => return None,
+        if self.data.is_empty() {
+            return None;
+        }
+        let ret = parse_record(self.data);
+        // Records are delimited by their length only, so nothing after a
+        // malformed record can be trusted.
+        self.data = match ret {
+            Ok((_, rest)) => rest,
+            Err(_) => &[],
         };
+        Some(ret.map(|(ext, _)| ext))
+    }
+}
 
-        Some(
-            line.iter()
-                .position(|b| *b == b' ')
-                .and_then(|i| {
-                    str::from_utf8(&line[..i])
-                        .ok()
-                        .and_then(|len| len.parse::<usize>().ok().map(|j| (i + 1, j)))
-                })
-                .and_then(|(kvstart, reported_len)| {
-                    if line.len() + 1 == reported_len {
-                        line[kvstart..]
-                            .iter()
-                            .position(|b| *b == b'=')
-                            .map(|equals| (kvstart, equals))
-                    } else {
-                        None
-                    }
-                })
-                .map(|(kvstart, equals)| PaxExtension {
-                    key: &line[kvstart..kvstart + equals],
-                    value: &line[kvstart + equals + 1..],
-                })
-                .ok_or_else(|| other("malformed pax extension")),
-        )
+/// Parses the record of the form `"<length> <key>=<value>\n"` at the start of
+/// `data`, where the length is in decimal and counts the whole record,
+/// returning it along with the rest of the data.
+fn parse_record(data: &[u8]) -> io::Result<(PaxExtension<'_>, &[u8])> {
+    let space = data
+        .iter()
+        .position(|b| *b == b' ')
+        .ok_or_else(|| other("malformed pax extension: missing length"))?;
+    let len = &data[..space];
+    if len.is_empty() || !len.iter().all(|b| b.is_ascii_digit()) {
+        return Err(other("malformed pax extension: invalid length"));
     }
+    let len = str::from_utf8(len)
+        .unwrap()
+        .parse::<usize>()
+        .map_err(|_| other("malformed pax extension: invalid length"))?;
+    if len <= space + 1 {
+        return Err(other(&format!(
+            "malformed pax extension: length {} is too short",
+            len
+        )));
+    }
+    if len > data.len() {
+        return Err(other(&format!(
+            "truncated pax extension: length {} exceeds the remaining {} bytes",
+            len,
+            data.len()
+        )));
+    }
+    let (record, rest) = data.split_at(len);
+    let kv = match record.split_last() {
+        Some((b'\n', record)) => &record[space + 1..],
+        _ => {
+            return Err(other(&format!(
+                "malformed pax extension: record of length {} does not end in a newline",
+                len
+            )))
+        }
+    };
+    let equals = kv
+        .iter()
+        .position(|b| *b == b'=')
+        .ok_or_else(|| other("malformed pax extension: missing `=`"))?;
+    let ext = PaxExtension {
+        key: &kv[..equals],
+        value: &kv[equals + 1..],
+    };
+    Ok((ext, rest))
 }
 
 impl<'entry> PaxExtension<'entry> {
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+
+use crate::entry::{EntryFields, Replace, UnpackKind};
+use crate::target;
+
+/// An entry of an archive along with what unpacking it would do, as returned
+/// by [`Archive::unpack_plan`](crate::Archive::unpack_plan).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedEntry {
+    path: PathBuf,
+    action: PlannedAction,
+}
+
+/// What unpacking a single entry of an archive would do.
+///
+/// Destination paths are the locations inside of the destination directory
+/// which the entry would be unpacked to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PlannedAction {
+    /// A regular file of `size` bytes would be written. If `overwrite` is
+    /// true then an existing path would be replaced by it.
+    CreateFile {
+        /// Where the file would be written.
+        dst: PathBuf,
+        /// The size of the file's contents.
+        size: u64,
+        /// Whether an existing path would be replaced.
+        overwrite: bool,
+    },
+    /// A directory would be created, or an existing directory reused. If
+    /// `overwrite` is true then something other than a directory would be
+    /// replaced by it.
+    CreateDir {
+        /// Where the directory would be created.
+        dst: PathBuf,
+        /// Whether an existing path would be replaced.
+        overwrite: bool,
+    },
+    /// A symlink pointing to `target` would be created.
+    Symlink {
+        /// Where the symlink would be created.
+        dst: PathBuf,
+        /// The target of the symlink, exactly as listed in the archive.
+        target: PathBuf,
+        /// Whether an existing path would be replaced.
+        overwrite: bool,
+    },
+    /// A hard link to `target` would be created.
+    HardLink {
+        /// Where the hard link would be created.
+        dst: PathBuf,
+        /// The path being linked to, inside the destination directory.
+        target: PathBuf,
+        /// Whether an existing path would be replaced.
+        overwrite: bool,
+    },
+    /// The entry would be skipped because something already exists at `dst`
+    /// and the overwrite policy is to keep it.
+    KeepExisting {
+        /// The path which already exists.
+        dst: PathBuf,
+    },
+    /// Unpacking would fail at this entry, and no further entries would be
+    /// unpacked.
+    Fail {
+        /// Where the entry would have been unpacked to.
+        dst: PathBuf,
+        /// A description of why unpacking would fail.
+        reason: String,
+    },
+    /// The entry would have been written outside of the destination
+    /// directory, for example through a symlink, so unpacking would fail.
+    Escape {
+        /// Where the entry would have been unpacked to.
+        dst: PathBuf,
+    },
+    /// The entry would be skipped because its path contains a `..`
+    /// component.
+    SkipParentDir,
+    /// The entry would be skipped because its path is empty or only refers
+    /// to the destination directory itself.
+    SkipEmptyPath,
+    /// The entry only carries metadata, such as global pax extensions, and
+    /// nothing would be unpacked for it.
+    Ignore,
+}
+
+impl PlannedEntry {
+    /// Returns the path of this entry in the archive.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns what unpacking this entry would do.
+    pub fn action(&self) -> &PlannedAction {
+        &self.action
+    }
+}
+
+enum Node {
+    Dir,
+    File { mtime: i64 },
+    Symlink(PathBuf),
+}
+
+/// Tracks the changes that unpacking the entries planned so far would make,
+/// layered on top of the current state of the filesystem.
+pub struct Planner {
+    dst: PathBuf,
+    nodes: HashMap<PathBuf, Node>,
+    plan: Vec<PlannedEntry>,
+}
+
+impl Planner {
+    pub fn new(dst: &Path) -> Planner {
+        Planner {
+            dst: dst.canonicalize().unwrap_or(dst.to_path_buf()),
+            nodes: HashMap::new(),
+            plan: Vec::new(),
+        }
+    }
+
+    pub fn into_plan(self) -> Vec<PlannedEntry> {
+        self.plan
+    }
+
+    /// Plans the unpacking of one entry, returning `false` if unpacking would
+    /// stop at it.
+    pub fn plan(&mut self, fields: &EntryFields<'_>) -> io::Result<bool> {
+        let action = self.action(fields)?;
+        let proceed = !matches!(
+            action,
+            PlannedAction::Fail { .. } | PlannedAction::Escape { .. }
+        );
+        self.plan.push(PlannedEntry {
+            path: fields.path()?.into_owned(),
+            action,
+        });
+        Ok(proceed)
+    }
+
+    fn action(&mut self, fields: &EntryFields<'_>) -> io::Result<PlannedAction> {
+        let kind = fields.unpack_kind();
+        if let UnpackKind::Skip = kind {
+            return Ok(PlannedAction::Ignore);
+        }
+
+        let file_dst = match fields.unpack_in_dst(&self.dst, None)? {
+            Some(file_dst) => file_dst,
+            None => return Ok(PlannedAction::SkipParentDir),
+        };
+        if file_dst == self.dst {
+            return Ok(PlannedAction::SkipEmptyPath);
+        }
+        let (parent, name) = match (file_dst.parent(), file_dst.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return Ok(PlannedAction::SkipParentDir),
+        };
+
+        // Mirror `validate_inside_dst` on the parent, which would have been
+        // created as needed.
+        let parent = match self.resolve(parent) {
+            Ok(parent) => parent,
+            Err(e) => {
+                return Ok(PlannedAction::Fail {
+                    dst: file_dst,
+                    reason: e.to_string(),
+                });
+            }
+        };
+        if !parent.starts_with(&self.dst) {
+            return Ok(PlannedAction::Escape { dst: file_dst });
+        }
+        let key = parent.join(name);
+
+        let is_dir = matches!(kind, UnpackKind::Dir);
+        let prev = self.existing(&key);
+        let overwrite = match prev {
+            None => false,
+            Some(_) if is_dir && self.resolve(&key).map(|r| self.is_dir(&r)).unwrap_or(false) => {
+                false
+            }
+            Some(prev_mtime) => match fields.replace_existing(prev_mtime) {
+                Replace::Fail => {
+                    return Ok(PlannedAction::Fail {
+                        dst: file_dst,
+                        reason: "path already exists".to_string(),
+                    });
+                }
+                Replace::Keep => return Ok(PlannedAction::KeepExisting { dst: file_dst }),
+                // Directories are only removed when they're empty, unless
+                // the removal is recursive.
+                Replace::Unlink if self.has_contents(&key) => {
+                    return Ok(PlannedAction::Fail {
+                        dst: file_dst,
+                        reason: "directory not empty".to_string(),
+                    });
+                }
+                Replace::Unlink | Replace::UnlinkRecursive => true,
+            },
+        };
+
+        self.create_dirs(&parent);
+        let mtime = match fields.header.mtime() {
+            Ok(mtime) if fields.preserve_mtime => mtime as i64,
+            // The file will be modified right now, which is newer than
+            // anything in the archive.
+            _ => i64::MAX,
+        };
+
+        if is_dir {
+            self.nodes.insert(key, Node::Dir);
+            return Ok(PlannedAction::CreateDir {
+                dst: file_dst,
+                overwrite,
+            });
+        }
+
+        if let UnpackKind::HardLink | UnpackKind::Symlink = kind {
+            let target = match fields.link_src() {
+                Ok(target) => target.into_owned(),
+                Err(e) => {
+                    return Ok(PlannedAction::Fail {
+                        dst: file_dst,
+                        reason: e.to_string(),
+                    });
+                }
+            };
+
+            if let UnpackKind::Symlink = kind {
+                self.nodes.insert(key, Node::Symlink(target.clone()));
+                return Ok(PlannedAction::Symlink {
+                    dst: file_dst,
+                    target,
+                    overwrite,
+                });
+            }
+
+            let link_src = self.dst.join(&target);
+            let resolved = match self.resolve(&link_src) {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    return Ok(PlannedAction::Fail {
+                        dst: file_dst,
+                        reason: e.to_string(),
+                    });
+                }
+            };
+            if !resolved.starts_with(&self.dst) {
+                return Ok(PlannedAction::Escape { dst: file_dst });
+            }
+            if self.existing(&resolved).is_none() {
+                return Ok(PlannedAction::Fail {
+                    dst: file_dst,
+                    reason: format!("hard link target {} does not exist", link_src.display()),
+                });
+            }
+            self.nodes.insert(key, Node::File { mtime });
+            return Ok(PlannedAction::HardLink {
+                dst: file_dst,
+                target: link_src,
+                overwrite,
+            });
+        }
+
+        self.nodes.insert(key, Node::File { mtime });
+        Ok(PlannedAction::CreateFile {
+            dst: file_dst,
+            size: fields.size,
+            overwrite,
+        })
+    }
+
+    /// Returns the mtime of whatever exists at `path`, after the entries
+    /// planned so far, without following a symlink at `path` itself.
+    fn existing(&self, path: &Path) -> Option<i64> {
+        match self.nodes.get(path) {
+            Some(Node::File { mtime }) => Some(*mtime),
+            Some(Node::Dir) | Some(Node::Symlink(_)) => Some(i64::MAX),
+            None => path
+                .symlink_metadata()
+                .ok()
+                .map(|m| FileTime::from_last_modification_time(&m).unix_seconds()),
+        }
+    }
+
+    /// Returns whether `path` is a directory which isn't empty, after the
+    /// entries planned so far, without following a symlink at `path` itself.
+    fn has_contents(&self, path: &Path) -> bool {
+        let is_dir = match self.nodes.get(path) {
+            Some(node) => matches!(node, Node::Dir),
+            None => path.symlink_metadata().map(|m| m.is_dir()).unwrap_or(false),
+        };
+        is_dir
+            && (self.nodes.keys().any(|p| p.parent() == Some(path))
+                || fs::read_dir(path)
+                    .map(|mut d| d.next().is_some())
+                    .unwrap_or(false))
+    }
+
+    fn is_dir(&self, resolved: &Path) -> bool {
+        match self.nodes.get(resolved) {
+            Some(node) => matches!(node, Node::Dir),
+            None => fs::metadata(resolved).map(|m| m.is_dir()).unwrap_or(false),
+        }
+    }
+
+    /// Records that all missing directories up to `dir` would be created.
+    fn create_dirs(&mut self, dir: &Path) {
+        for ancestor in dir.ancestors() {
+            if ancestor == self.dst || self.existing(ancestor).is_some() {
+                break;
+            }
+            self.nodes.insert(ancestor.to_path_buf(), Node::Dir);
+        }
+    }
+
+    /// Resolves all symlinks in `path`, both those already on the filesystem
+    /// and those which would be created by the entries planned so far.
+    ///
+    /// Unlike `canonicalize` this works for paths which don't exist yet.
+    fn resolve(&self, path: &Path) -> io::Result<PathBuf> {
+        let root = self.dst.ancestors().last().unwrap_or(&self.dst);
+        target::resolve_symlinks(path, root, |candidate, _| {
+            Ok(match self.nodes.get(candidate) {
+                Some(Node::Symlink(target)) => Some(target.clone()),
+                Some(_) => None,
+                None => fs::read_link(candidate).ok(),
+            })
+        })
+    }
+}
@@ -0,0 +1,365 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::fs;
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::entry::{EntryMeta, SparseMap};
+use crate::header::bytes2path;
+use crate::{other, Archive, Header, PaxMap};
+
+/// A source of bytes which can be read at arbitrary offsets through a shared
+/// reference, like `std::os::unix::fs::FileExt::read_at`.
+///
+/// Unlike `Read`, reading doesn't move a cursor shared by all readers, so a
+/// source can be read from several threads at the same time.
+pub trait ReadAt {
+    /// Reads bytes starting at `offset` into `buf`, returning the number of
+    /// bytes read. Returns 0 if `offset` is at or past the end of the source.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+}
+
+#[cfg(unix)]
+impl ReadAt for fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+}
+
+/// Note that on Windows reading also moves the position of the file, so it
+/// shouldn't be read in any other way at the same time.
+#[cfg(windows)]
+impl ReadAt for fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.len() as u64 {
+            return Ok(0);
+        }
+        let src = &self[offset as usize..];
+        let n = cmp::min(src.len(), buf.len());
+        buf[..n].copy_from_slice(&src[..n]);
+        Ok(n)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self[..].read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Box<T> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for Arc<T> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+/// An archive which can be read from several threads at the same time.
+///
+/// Unlike [`Archive`], which reads through a single cursor, this reads its
+/// source with [`ReadAt`], and hands out its entries as owned
+/// [`SharedEntry`] handles. Each handle knows where its contents are stored,
+/// so handles can be moved to other threads and read in parallel, in any
+/// order.
+///
+/// The archive must start at offset 0 of the source.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::Read;
+/// use std::thread;
+/// use tar::SharedArchive;
+///
+/// let ar = SharedArchive::new(File::open("foo.tar").unwrap());
+/// let threads = ar
+///     .entries()
+///     .unwrap()
+///     .into_iter()
+///     .map(|mut entry| {
+///         thread::spawn(move || {
+///             let mut contents = Vec::new();
+///             entry.read_to_end(&mut contents).unwrap();
+///             contents.len()
+///         })
+///     })
+///     .collect::<Vec<_>>();
+/// for thread in threads {
+///     println!("{}", thread.join().unwrap());
+/// }
+/// ```
+pub struct SharedArchive<R: ?Sized + ReadAt> {
+    ignore_zeros: bool,
+    src: Arc<R>,
+}
+
+/// An entry of a [`SharedArchive`], which can be read independently of all
+/// other entries.
+///
+/// Reading through `Read` moves the position of this handle only; clones of
+/// a handle have their own position. [`SharedEntry::read_at`] doesn't move
+/// any position and can be called through a shared reference.
+pub struct SharedEntry<R: ?Sized + ReadAt> {
+    src: Arc<R>,
+    meta: EntryMeta,
+    pos: u64,
+}
+
+impl<R: ReadAt> SharedArchive<R> {
+    /// Creates a new shared archive reading from `src`.
+    pub fn new(src: R) -> SharedArchive<R> {
+        SharedArchive::from_arc(Arc::new(src))
+    }
+}
+
+impl<R: ?Sized + ReadAt> SharedArchive<R> {
+    /// Creates a new shared archive reading from a source which is already
+    /// shared.
+    pub fn from_arc(src: Arc<R>) -> SharedArchive<R> {
+        SharedArchive {
+            ignore_zeros: false,
+            src,
+        }
+    }
+
+    /// Returns a reference to the source of this archive.
+    pub fn get_ref(&self) -> &R {
+        &self.src
+    }
+
+    /// Ignore zeroed headers, which would otherwise indicate to the archive
+    /// that it has no more entries, as with [`Archive::set_ignore_zeros`].
+    pub fn set_ignore_zeros(&mut self, ignore_zeros: bool) {
+        self.ignore_zeros = ignore_zeros;
+    }
+
+    /// Reads the headers of all entries of this archive, returning a handle
+    /// for each of them.
+    ///
+    /// Only the headers are read, the contents of the entries are skipped.
+    /// Entries are resolved like those of [`Archive::entries`], taking long
+    /// names and pax records into account.
+    pub fn entries(&self) -> io::Result<Vec<SharedEntry<R>>> {
+        let mut ar = Archive::new(ReadAtCursor {
+            src: &*self.src,
+            pos: 0,
+        });
+        ar.set_ignore_zeros(self.ignore_zeros);
+        let mut entries = Vec::new();
+        for entry in ar.entries_with_seek()? {
+            entries.push(SharedEntry {
+                src: self.src.clone(),
+                meta: entry?.meta(),
+                pos: 0,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+impl<R: ?Sized + ReadAt> Clone for SharedArchive<R> {
+    fn clone(&self) -> SharedArchive<R> {
+        SharedArchive {
+            ignore_zeros: self.ignore_zeros,
+            src: self.src.clone(),
+        }
+    }
+}
+
+impl<R: ?Sized + ReadAt> SharedEntry<R> {
+    /// Returns the path name of this entry, as [`Entry::path`] does.
+    ///
+    /// [`Entry::path`]: crate::Entry::path
+    pub fn path(&self) -> io::Result<Cow<'_, Path>> {
+        bytes2path(Cow::Borrowed(&self.meta.path))
+    }
+
+    /// Returns the path name of this entry in bytes.
+    pub fn path_bytes(&self) -> &[u8] {
+        &self.meta.path
+    }
+
+    /// Returns the link name of this entry, if any, as
+    /// [`Entry::link_name`] does.
+    ///
+    /// [`Entry::link_name`]: crate::Entry::link_name
+    pub fn link_name(&self) -> io::Result<Option<Cow<'_, Path>>> {
+        match self.meta.link_name {
+            Some(ref bytes) => bytes2path(Cow::Borrowed(bytes)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the link name of this entry in bytes, if any.
+    pub fn link_name_bytes(&self) -> Option<&[u8]> {
+        self.meta.link_name.as_deref()
+    }
+
+    /// Returns the header of this entry in the archive.
+    pub fn header(&self) -> &Header {
+        &self.meta.header
+    }
+
+    /// Returns the pax records which apply to this entry, if any.
+    pub fn pax(&self) -> Option<&PaxMap> {
+        self.meta.pax.as_ref()
+    }
+
+    /// Returns the size of the contents of this entry.
+    pub fn size(&self) -> u64 {
+        self.meta.size
+    }
+
+    /// Returns the position of the header of this entry in the archive.
+    pub fn raw_header_position(&self) -> u64 {
+        self.meta.header_pos
+    }
+
+    /// Returns the position of the contents of this entry in the archive.
+    pub fn raw_file_position(&self) -> u64 {
+        self.meta.file_pos
+    }
+
+    /// Returns the layout of the data and holes of this entry, if it's a
+    /// sparse file.
+    pub fn sparse_map(&self) -> Option<SparseMap> {
+        self.meta.sparse_map()
+    }
+
+    /// Returns the metadata of this entry.
+    pub(crate) fn meta(&self) -> &EntryMeta {
+        &self.meta
+    }
+
+    pub(crate) fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+
+    /// Reads the contents of this entry at `offset` into `buf`, returning the
+    /// number of bytes read.
+    ///
+    /// Holes of sparse files read as zeros without reading the source. This
+    /// doesn't move the position of this handle.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.meta.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let regions = &self.meta.regions;
+        let i = match regions.binary_search_by(|r| r.offset.cmp(&offset)) {
+            Ok(i) => i,
+            Err(0) => return Ok(0),
+            Err(i) => i - 1,
+        };
+        let region = &regions[i];
+        let skip = offset - region.offset;
+        let n = cmp::min(buf.len() as u64, region.len - skip) as usize;
+        match region.archive_pos {
+            Some(pos) => {
+                let n = self.src.read_at(&mut buf[..n], pos + skip)?;
+                if n == 0 {
+                    return Err(other("unexpected EOF reading the contents of an entry"));
+                }
+                Ok(n)
+            }
+            None => {
+                for b in &mut buf[..n] {
+                    *b = 0;
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+impl<R: ?Sized + ReadAt> Read for SharedEntry<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: ?Sized + ReadAt> Seek for SharedEntry<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(n) => (0, n as i64),
+            SeekFrom::Current(n) => (self.pos, n),
+            SeekFrom::End(n) => (self.meta.size, n),
+        };
+        match (base as i64).checked_add(delta) {
+            Some(pos) if pos >= 0 => {
+                self.pos = pos as u64;
+                Ok(self.pos)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl<R: ?Sized + ReadAt> Clone for SharedEntry<R> {
+    fn clone(&self) -> SharedEntry<R> {
+        SharedEntry {
+            src: self.src.clone(),
+            meta: self.meta.clone(),
+            pos: self.pos,
+        }
+    }
+}
+
+/// Adapts a `ReadAt` source to `Read` and `Seek` with a cursor of its own, to
+/// iterate over the headers of an archive.
+struct ReadAtCursor<'a, R: ?Sized> {
+    src: &'a R,
+    pos: u64,
+}
+
+impl<'a, R: ?Sized + ReadAt> Read for ReadAtCursor<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.src.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a, R: ?Sized + ReadAt> Seek for ReadAtCursor<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => (self.pos as i64).checked_add(n).map(|n| n as u64),
+            SeekFrom::End(_) => return Err(other("cannot seek from the end of the archive")),
+        };
+        match pos {
+            Some(pos) if pos as i64 >= 0 => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, ErrorKind, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use filetime::{self, FileTime};
+
+use crate::other;
+
+/// A destination which the entries of an archive can be unpacked into.
+///
+/// All of the unpacking logic of this crate, such as the protection against
+/// writing outside of the destination directory, the handling of sparse
+/// files, the overwrite policy and the deferral of directories, is expressed
+/// in terms of the operations of this trait. [`FsTarget`] implements it on
+/// top of `std::fs` and is what [`Archive::unpack`](crate::Archive::unpack)
+/// uses, while [`MemoryTarget`](crate::MemoryTarget) keeps everything in
+/// memory. Other implementations can unpack into, for example, overlay or
+/// content-addressed stores.
+///
+/// Paths passed to these methods are the full paths inside of the target,
+/// starting from the destination directory passed to
+/// [`Archive::unpack_into`](crate::Archive::unpack_into). Implementations
+/// should mirror the semantics of the corresponding `std::fs` functions, in
+/// particular by returning errors of kind `AlreadyExists` and `NotFound`,
+/// which the unpacking logic relies on.
+pub trait UnpackTarget {
+    /// A handle to a regular file which is being written.
+    type File: Write;
+
+    /// Creates a new, empty regular file at `path`, failing with
+    /// `ErrorKind::AlreadyExists` if anything already exists there.
+    fn create_file(&mut self, path: &Path) -> io::Result<Self::File>;
+
+    /// Appends `len` zero bytes to `file`, as found in the holes of sparse
+    /// files.
+    ///
+    /// The default implementation writes the zeros out, but implementations
+    /// may leave a hole instead.
+    fn write_zeros(&mut self, file: &mut Self::File, len: u64) -> io::Result<()> {
+        io::copy(&mut io::repeat(0).take(len), file)?;
+        Ok(())
+    }
+
+    /// Creates a new directory at `path`, failing with
+    /// `ErrorKind::AlreadyExists` if anything already exists there.
+    fn create_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Creates the directory `path` along with all of its missing parents.
+    ///
+    /// Unlike `create_dir` this succeeds if `path` is already a directory.
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        if self.metadata(path).map(|m| m.is_dir()).unwrap_or(false) {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            if parent != Path::new("") {
+                self.create_dir_all(parent)?;
+            }
+        }
+        match self.create_dir(path) {
+            Err(ref e)
+                if e.kind() == ErrorKind::AlreadyExists
+                    && self.metadata(path).map(|m| m.is_dir()).unwrap_or(false) =>
+            {
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Creates a symlink at `dst` pointing to `src`.
+    fn symlink(&mut self, src: &Path, dst: &Path) -> io::Result<()>;
+
+    /// Creates a hard link at `dst` to the existing path `src`.
+    fn hard_link(&mut self, src: &Path, dst: &Path) -> io::Result<()>;
+
+    /// Returns the metadata of `path` without following a symlink at `path`
+    /// itself, failing with `ErrorKind::NotFound` if it doesn't exist.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<TargetMetadata>;
+
+    /// Returns the metadata of `path`, following symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<TargetMetadata>;
+
+    /// Returns the canonical form of the existing `path` with all symlinks
+    /// resolved.
+    ///
+    /// This is used to ensure that nothing is written outside of the
+    /// destination directory.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Removes the file or symlink at `path`.
+    fn remove_file(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Removes the empty directory at `path`.
+    fn remove_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Removes the directory at `path` along with all of its contents.
+    fn remove_dir_all(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Renames `from` to `to`, replacing anything but a directory at `to`.
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Sets the access and modification times of `path`, in seconds since
+    /// the Unix epoch. A time which is `None` is left unchanged.
+    ///
+    /// If `file` is given it's the handle which `path` was written through,
+    /// otherwise a symlink at `path` should not be followed.
+    fn set_times(
+        &mut self,
+        path: &Path,
+        file: Option<&mut Self::File>,
+        atime: Option<u64>,
+        mtime: Option<u64>,
+    ) -> io::Result<()>;
+
+    /// Sets the numeric owner and group of `path`.
+    ///
+    /// If `file` is given it's the handle which `path` was written through,
+    /// otherwise a symlink at `path` should not be followed.
+    fn set_ownership(
+        &mut self,
+        path: &Path,
+        file: Option<&mut Self::File>,
+        uid: u64,
+        gid: u64,
+    ) -> io::Result<()>;
+
+    /// Sets the permission bits of `path` to `mode`, which already had the
+    /// mask of the archive applied.
+    ///
+    /// If `file` is given it's the handle which `path` was written through.
+    fn set_permissions(
+        &mut self,
+        path: &Path,
+        file: Option<&mut Self::File>,
+        mode: u32,
+    ) -> io::Result<()>;
+
+    /// Sets the extended attribute `name` of `path` to `value`.
+    fn set_xattr(&mut self, path: &Path, name: &[u8], value: &[u8]) -> io::Result<()>;
+
+    /// Flushes the contents and metadata of `file` to stable storage.
+    ///
+    /// The default implementation does nothing.
+    fn sync_file(&mut self, file: &mut Self::File) -> io::Result<()> {
+        let _ = file;
+        Ok(())
+    }
+
+    /// Flushes the entries of the directory `path` to stable storage.
+    ///
+    /// The default implementation does nothing.
+    fn sync_dir(&mut self, path: &Path) -> io::Result<()> {
+        let _ = path;
+        Ok(())
+    }
+}
+
+// Upper bound of the number of symlinks followed while resolving a path,
+// matching Linux.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves the symlinks in `path` without asking the OS to, starting over
+/// from `root` at absolute components.
+///
+/// `link` is called with each path resolved so far, and whether it's the
+/// last component of `path`, and returns the target of the symlink there if
+/// it's to be followed. Following too many symlinks fails like the OS does.
+pub(crate) fn resolve_symlinks(
+    path: &Path,
+    root: &Path,
+    mut link: impl FnMut(&Path, bool) -> io::Result<Option<PathBuf>>,
+) -> io::Result<PathBuf> {
+    fn parts(path: &Path) -> impl Iterator<Item = OsString> + '_ {
+        path.components()
+            .rev()
+            .map(|c| c.as_os_str().to_os_string())
+    }
+
+    let mut resolved = PathBuf::new();
+    let mut pending = parts(path).collect::<Vec<_>>();
+    let mut followed = 0;
+    while let Some(part) = pending.pop() {
+        match Path::new(&part).components().next() {
+            Some(Component::Normal(name)) => {
+                let candidate = resolved.join(name);
+                match link(&candidate, pending.is_empty())? {
+                    Some(target) => {
+                        followed += 1;
+                        if followed > MAX_SYMLINKS {
+                            return Err(other(&format!(
+                                "too many levels of symbolic links in `{}`",
+                                path.display()
+                            )));
+                        }
+                        pending.extend(parts(&target));
+                    }
+                    None => resolved = candidate,
+                }
+            }
+            Some(Component::ParentDir) => {
+                resolved.pop();
+            }
+            Some(Component::Prefix(_)) | Some(Component::RootDir) => resolved = root.to_path_buf(),
+            Some(Component::CurDir) | None => {}
+        }
+    }
+    Ok(resolved)
+}
+
+/// The metadata of a path in an [`UnpackTarget`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetMetadata {
+    is_dir: bool,
+    mtime: i64,
+}
+
+impl TargetMetadata {
+    /// Creates metadata for a path which is a directory if `is_dir` is true,
+    /// last modified at `mtime` seconds since the Unix epoch.
+    pub fn new(is_dir: bool, mtime: i64) -> TargetMetadata {
+        TargetMetadata { is_dir, mtime }
+    }
+
+    /// Returns whether the path is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Returns the modification time of the path, in seconds since the Unix
+    /// epoch.
+    pub fn mtime(&self) -> i64 {
+        self.mtime
+    }
+}
+
+impl From<fs::Metadata> for TargetMetadata {
+    fn from(m: fs::Metadata) -> TargetMetadata {
+        let mtime = FileTime::from_last_modification_time(&m).unix_seconds();
+        TargetMetadata::new(m.is_dir(), mtime)
+    }
+}
+
+/// An [`UnpackTarget`] which unpacks into the local filesystem through
+/// `std::fs`.
+///
+/// This is the target used by [`Archive::unpack`](crate::Archive::unpack)
+/// and [`Entry::unpack_in`](crate::Entry::unpack_in).
+#[derive(Clone, Debug, Default)]
+pub struct FsTarget {
+    _priv: (),
+}
+
+impl FsTarget {
+    /// Creates a new target for the local filesystem.
+    pub fn new() -> FsTarget {
+        FsTarget { _priv: () }
+    }
+}
+
+impl UnpackTarget for FsTarget {
+    type File = fs::File;
+
+    fn create_file(&mut self, path: &Path) -> io::Result<fs::File> {
+        OpenOptions::new().write(true).create_new(true).open(path)
+    }
+
+    fn write_zeros(&mut self, file: &mut fs::File, len: u64) -> io::Result<()> {
+        // TODO: checked cast to i64
+        let to = SeekFrom::Current(len as i64);
+        let size = file.seek(to)?;
+        file.set_len(size)
+    }
+
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn symlink(&mut self, src: &Path, dst: &Path) -> io::Result<()> {
+        return symlink(src, dst);
+
+        #[cfg(target_arch = "wasm32")]
+        #[allow(unused_variables)]
+        fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
+        }
+
+        #[cfg(windows)]
+        fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+            ::std::os::windows::fs::symlink_file(src, dst)
+        }
+
+        #[cfg(unix)]
+        fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+            ::std::os::unix::fs::symlink(src, dst)
+        }
+    }
+
+    fn hard_link(&mut self, src: &Path, dst: &Path) -> io::Result<()> {
+        fs::hard_link(src, dst)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<TargetMetadata> {
+        path.symlink_metadata().map(TargetMetadata::from)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<TargetMetadata> {
+        fs::metadata(path).map(TargetMetadata::from)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+
+    fn remove_file(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn set_times(
+        &mut self,
+        path: &Path,
+        file: Option<&mut fs::File>,
+        atime: Option<u64>,
+        mtime: Option<u64>,
+    ) -> io::Result<()> {
+        let atime = atime.map(|t| FileTime::from_unix_time(t as i64, 0));
+        let mtime = mtime.map(|t| FileTime::from_unix_time(t as i64, 0));
+        match file {
+            Some(f) => filetime::set_file_handle_times(f, atime, mtime),
+            None => {
+                // Both times of a symlink have to be set at once, so keep the
+                // current value of whichever one isn't given.
+                let (atime, mtime) = match (atime, mtime) {
+                    (Some(atime), Some(mtime)) => (atime, mtime),
+                    _ => {
+                        let meta = path.symlink_metadata()?;
+                        (
+                            atime.unwrap_or_else(|| FileTime::from_last_access_time(&meta)),
+                            mtime.unwrap_or_else(|| FileTime::from_last_modification_time(&meta)),
+                        )
+                    }
+                };
+                filetime::set_symlink_file_times(path, atime, mtime)
+            }
+        }
+    }
+
+    fn set_ownership(
+        &mut self,
+        path: &Path,
+        file: Option<&mut fs::File>,
+        uid: u64,
+        gid: u64,
+    ) -> io::Result<()> {
+        return _set_ownerships(path, file, uid, gid);
+
+        #[cfg(unix)]
+        fn _set_ownerships(
+            dst: &Path,
+            f: Option<&mut fs::File>,
+            uid: u64,
+            gid: u64,
+        ) -> io::Result<()> {
+            use std::convert::TryInto;
+            use std::os::unix::prelude::*;
+
+            let uid: libc::uid_t = uid.try_into().map_err(|_| {
+                io::Error::new(io::ErrorKind::Other, format!("UID {} is too large!", uid))
+            })?;
+            let gid: libc::gid_t = gid.try_into().map_err(|_| {
+                io::Error::new(io::ErrorKind::Other, format!("GID {} is too large!", gid))
+            })?;
+            match f {
+                Some(f) => unsafe {
+                    let fd = f.as_raw_fd();
+                    if libc::fchown(fd, uid, gid) != 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(())
+                    }
+                },
+                None => unsafe {
+                    let path = std::ffi::CString::new(dst.as_os_str().as_bytes()).map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::Other,
+                            format!("path contains null character: {:?}", e),
+                        )
+                    })?;
+                    if libc::lchown(path.as_ptr(), uid, gid) != 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(())
+                    }
+                },
+            }
+        }
+
+        // Windows does not support posix numeric ownership IDs
+        #[cfg(any(windows, target_arch = "wasm32"))]
+        fn _set_ownerships(_: &Path, _: Option<&mut fs::File>, _: u64, _: u64) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn set_permissions(
+        &mut self,
+        path: &Path,
+        file: Option<&mut fs::File>,
+        mode: u32,
+    ) -> io::Result<()> {
+        return _set_perms(path, file, mode);
+
+        #[cfg(unix)]
+        fn _set_perms(dst: &Path, f: Option<&mut fs::File>, mode: u32) -> io::Result<()> {
+            use std::os::unix::prelude::*;
+
+            let perm = fs::Permissions::from_mode(mode as _);
+            match f {
+                Some(f) => f.set_permissions(perm),
+                None => fs::set_permissions(dst, perm),
+            }
+        }
+
+        #[cfg(windows)]
+        fn _set_perms(dst: &Path, f: Option<&mut fs::File>, mode: u32) -> io::Result<()> {
+            if mode & 0o200 == 0o200 {
+                return Ok(());
+            }
+            match f {
+                Some(f) => {
+                    let mut perm = f.metadata()?.permissions();
+                    perm.set_readonly(true);
+                    f.set_permissions(perm)
+                }
+                None => {
+                    let mut perm = fs::metadata(dst)?.permissions();
+                    perm.set_readonly(true);
+                    fs::set_permissions(dst, perm)
+                }
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        #[allow(unused_variables)]
+        fn _set_perms(dst: &Path, f: Option<&mut fs::File>, mode: u32) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Other, "Not implemented"))
+        }
+    }
+
+    #[cfg(all(unix, feature = "xattr"))]
+    fn set_xattr(&mut self, path: &Path, name: &[u8], value: &[u8]) -> io::Result<()> {
+        use std::ffi::OsStr;
+        use std::os::unix::prelude::*;
+
+        xattr::set(path, OsStr::from_bytes(name), value)
+    }
+
+    // Windows does not completely support posix xattrs
+    // https://en.wikipedia.org/wiki/Extended_file_attributes#Windows_NT
+    #[cfg(any(windows, not(feature = "xattr"), target_arch = "wasm32"))]
+    fn set_xattr(&mut self, _: &Path, _: &[u8], _: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn sync_file(&mut self, file: &mut fs::File) -> io::Result<()> {
+        file.sync_all()
+    }
+
+    #[cfg(unix)]
+    fn sync_dir(&mut self, path: &Path) -> io::Result<()> {
+        fs::File::open(path).and_then(|f| f.sync_all())
+    }
+
+    // Directories can't be opened as files to be synced on other platforms
+    #[cfg(not(unix))]
+    fn sync_dir(&mut self, _: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
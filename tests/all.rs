@@ -6,12 +6,17 @@ extern crate xattr;
 
 use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::{self, Cursor};
+use std::io::{self, Cursor, SeekFrom};
 use std::iter::repeat;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
 use filetime::FileTime;
-use tar::{Archive, Builder, Entries, EntryType, Header, HeaderMode};
+use tar::{
+    Archive, Builder, Durability, Entries, Entry, EntryType, Header, HeaderMode, MemoryTarget,
+    OverwritePolicy, PaxBuilder, PaxExtensions, PlannedAction, UnpackAction, UnpackVisitor,
+    Unpacked, XattrFilter,
+};
 use tempfile::{Builder as TempBuilder, TempDir};
 
 macro_rules! t {
@@ -129,6 +134,117 @@ fn reading_files() {
     assert!(entries.next().is_none());
 }
 
+#[test]
+#[cfg(any(
+    feature = "bzip2",
+    feature = "flate2",
+    feature = "ruzstd",
+    feature = "xz2",
+    feature = "zstd"
+))]
+fn open_auto() {
+    use tar::Compression;
+
+    let mut archives = vec![(tar!("reading_files.tar"), Compression::None)];
+    #[cfg(feature = "flate2")]
+    archives.push((tar!("reading_files.tar.gz"), Compression::Gzip));
+    #[cfg(feature = "bzip2")]
+    archives.push((tar!("reading_files.tar.bz2"), Compression::Bzip2));
+    #[cfg(feature = "xz2")]
+    archives.push((tar!("reading_files.tar.xz"), Compression::Xz));
+    #[cfg(any(feature = "zstd", feature = "ruzstd"))]
+    archives.push((tar!("reading_files.tar.zst"), Compression::Zstd));
+
+    for (data, compression) in archives {
+        let mut ar = t!(Archive::open_auto(data));
+        assert_eq!(ar.compression(), compression);
+        let mut files = Vec::new();
+        for entry in t!(ar.entries()) {
+            let mut entry = t!(entry);
+            let mut s = String::new();
+            t!(entry.read_to_string(&mut s));
+            files.push((t!(entry.path()).into_owned(), s));
+        }
+        assert_eq!(
+            files,
+            [
+                (PathBuf::from("a"), "a\n".repeat(11)),
+                (PathBuf::from("b"), "b\n".repeat(11)),
+            ]
+        );
+    }
+
+    // The path of the first entry of a plain archive may start like the
+    // magic bytes of a compressed stream
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "BZh91AY", &[][..]));
+    let data = t!(ar.into_inner());
+    let ar = t!(Archive::open_auto(&data[..]));
+    assert_eq!(ar.compression(), Compression::None);
+}
+
+#[test]
+#[cfg(any(feature = "flate2", feature = "xz2", feature = "zstd"))]
+fn compressed_builder() {
+    use tar::{CompressedBuilder, Compression};
+
+    fn build<W: Write>(mut ar: CompressedBuilder<W>) -> W {
+        let mut header = Header::new_gnu();
+        header.set_size(3);
+        t!(ar.append_data(&mut header, "foo", &b"foo"[..]));
+        t!(ar.finish())
+    }
+
+    let mut archives = Vec::new();
+    #[cfg(feature = "flate2")]
+    archives.push((
+        build(t!(CompressedBuilder::new_gzip(Vec::new(), 9))),
+        Compression::Gzip,
+    ));
+    #[cfg(feature = "xz2")]
+    archives.push((
+        build(t!(CompressedBuilder::new_xz(Vec::new(), 6))),
+        Compression::Xz,
+    ));
+    #[cfg(feature = "zstd")]
+    archives.push((
+        build(t!(CompressedBuilder::new_zstd(Vec::new(), 3))),
+        Compression::Zstd,
+    ));
+
+    for (data, compression) in archives {
+        let mut ar = t!(Archive::open_auto(&data[..]));
+        assert_eq!(ar.compression(), compression);
+        let mut entries = t!(ar.entries());
+        let mut entry = t!(entries.next().unwrap());
+        let mut s = String::new();
+        t!(entry.read_to_string(&mut s));
+        assert_eq!(s, "foo");
+        assert!(entries.next().is_none());
+    }
+
+    // Errors from flushing the underlying writer once the compressed stream
+    // is complete are reported
+    struct FailFlush(Vec<u8>);
+    impl Write for FailFlush {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "flush failed"))
+        }
+    }
+    #[cfg(feature = "flate2")]
+    let ar = t!(CompressedBuilder::new_gzip(FailFlush(Vec::new()), 6));
+    #[cfg(all(not(feature = "flate2"), feature = "xz2"))]
+    let ar = t!(CompressedBuilder::new_xz(FailFlush(Vec::new()), 6));
+    #[cfg(all(not(feature = "flate2"), not(feature = "xz2")))]
+    let ar = t!(CompressedBuilder::new_zstd(FailFlush(Vec::new()), 3));
+    assert!(ar.finish().is_err());
+}
+
 #[test]
 fn writing_files() {
     let mut ar = Builder::new(Vec::new());
@@ -282,6 +398,38 @@ fn skipping_entries_with_seek() {
     assert!(seekable_reader.read_bytes < reader.read_bytes);
 }
 
+#[test]
+fn entries_from_offset() {
+    // An archive embedded in a larger file, after some unrelated data.
+    let mut data = vec![0xff; 1000];
+    data.extend_from_slice(tar!("reading_files.tar"));
+    let mut reader = Cursor::new(data);
+    reader.set_position(1000);
+    let mut ar = Archive::new(reader);
+
+    let positions = t!(ar.entries_with_seek())
+        .map(|e| {
+            let e = t!(e);
+            (e.raw_header_position(), t!(e.path()).into_owned())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(positions.len(), 2);
+    assert_eq!(positions[0].0, 0);
+    assert!(ar.entries().is_err());
+
+    let resumed = t!(ar.entries_from(positions[1].0))
+        .map(|e| {
+            let e = t!(e);
+            (e.raw_header_position(), t!(e.path()).into_owned())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(resumed, positions[1..]);
+    reading_entries_common(t!(ar.entries_from(0)));
+
+    let err = ar.entries_from(100).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
 fn check_dirtree(td: &TempDir) {
     let dir_a = td.path().join("a");
     let dir_b = td.path().join("a/b");
@@ -300,6 +448,51 @@ fn extracting_directories() {
     check_dirtree(&td);
 }
 
+#[test]
+fn unpack_with_visitor() {
+    struct Visitor {
+        unpacked: Vec<(PathBuf, bool)>,
+    }
+
+    impl UnpackVisitor for Visitor {
+        fn before_unpack<R: Read>(&mut self, entry: &Entry<R>) -> io::Result<UnpackAction> {
+            let path = entry.path()?;
+            if path == Path::new("a/") {
+                Ok(UnpackAction::Rename(PathBuf::from("renamed/a")))
+            } else if path == Path::new("a/b/") {
+                Ok(UnpackAction::Skip)
+            } else if path == Path::new("a/c") {
+                Ok(UnpackAction::Rename(PathBuf::from("../c")))
+            } else {
+                Ok(UnpackAction::Extract)
+            }
+        }
+
+        fn after_unpack<R: Read>(
+            &mut self,
+            _entry: &Entry<R>,
+            dst: &Path,
+            file: Option<File>,
+        ) -> io::Result<()> {
+            self.unpacked.push((dst.to_path_buf(), file.is_some()));
+            Ok(())
+        }
+    }
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dst = t!(td.path().canonicalize());
+    let mut ar = Archive::new(Cursor::new(tar!("directory.tar")));
+    let mut visitor = Visitor {
+        unpacked: Vec::new(),
+    };
+    t!(ar.unpack_with(&dst, &mut visitor));
+
+    assert!(dst.join("renamed/a").is_dir());
+    assert!(!dst.join("a").exists());
+    assert!(!dst.parent().unwrap().join("c").exists());
+    assert_eq!(visitor.unpacked, vec![(dst.join("renamed/a"), false)]);
+}
+
 #[test]
 fn extracting_duplicate_file_fail() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -370,6 +563,88 @@ fn extracting_duplicate_link_succeed() {
     t!(ar.unpack(td.path()));
 }
 
+#[test]
+fn extracting_duplicate_file_skip_old() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::write(td.path().join("a"), "old"));
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::SkipOld);
+    t!(ar.unpack(td.path()));
+
+    assert_eq!(t!(fs::read_to_string(td.path().join("a"))), "old");
+    assert_eq!(
+        t!(fs::read_to_string(td.path().join("b"))),
+        "b\nb\nb\nb\nb\nb\nb\nb\nb\nb\nb\n"
+    );
+
+    // Kept files aren't reported as unpacked
+    struct Visitor(Vec<(PathBuf, bool)>);
+    impl UnpackVisitor for Visitor {
+        fn after_unpack<R: Read>(
+            &mut self,
+            _entry: &Entry<R>,
+            dst: &Path,
+            file: Option<File>,
+        ) -> io::Result<()> {
+            self.0.push((dst.to_path_buf(), file.is_some()));
+            Ok(())
+        }
+    }
+    t!(fs::remove_file(td.path().join("b")));
+    let dst = t!(td.path().canonicalize());
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::SkipOld);
+    let mut visitor = Visitor(Vec::new());
+    t!(ar.unpack_with(&dst, &mut visitor));
+    assert_eq!(visitor.0, vec![(dst.join("b"), true)]);
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::SkipOld);
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    match t!(entry.unpack(td.path().join("a"))) {
+        Unpacked::Kept => {}
+        unpacked => panic!("unexpected {:?}", unpacked),
+    }
+}
+
+#[test]
+fn extracting_duplicate_file_keep_newer() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    // `a` is newer than the archive, `b` is older
+    t!(fs::write(td.path().join("a"), "newer"));
+    t!(fs::write(td.path().join("b"), "older"));
+    let old = FileTime::from_unix_time(1_000_000, 0);
+    t!(filetime::set_file_times(td.path().join("b"), old, old));
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::KeepNewer);
+    t!(ar.unpack(td.path()));
+
+    assert_eq!(t!(fs::read_to_string(td.path().join("a"))), "newer");
+    assert_eq!(
+        t!(fs::read_to_string(td.path().join("b"))),
+        "b\nb\nb\nb\nb\nb\nb\nb\nb\nb\nb\n"
+    );
+}
+
+#[test]
+fn extracting_file_over_directory() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::create_dir_all(td.path().join("a/sub")));
+    t!(fs::write(td.path().join("a/sub/file"), "contents"));
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::Unlink);
+    assert!(ar.unpack(td.path()).is_err());
+    assert!(td.path().join("a/sub/file").is_file());
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::RecursiveUnlink);
+    t!(ar.unpack(td.path()));
+    assert!(td.path().join("a").is_file());
+}
+
 #[test]
 #[cfg(all(unix, feature = "xattr"))]
 fn xattrs() {
@@ -402,6 +677,191 @@ fn no_xattrs() {
     );
 }
 
+#[test]
+#[cfg(all(target_os = "linux", feature = "acl"))]
+fn acls() {
+    fn append_pax(ar: &mut Builder<Vec<u8>>, records: &[(&str, &str)]) {
+        let mut data = String::new();
+        for (key, value) in records {
+            let record = format!(" {}={}\n", key, value);
+            let len = record.len() + 2;
+            assert!(len < 100);
+            data.push_str(&format!("{}{}", len, record));
+        }
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::XHeader);
+        header.set_size(data.len() as u64);
+        t!(ar.append_data(&mut header, "pax", data.as_bytes()));
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    append_pax(
+        &mut ar,
+        &[(
+            "SCHILY.acl.access",
+            "user::rw-,user:nobody:r--:1000,group::r--,mask::r--,other::---",
+        )],
+    );
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "f", io::empty()));
+    append_pax(
+        &mut ar,
+        &[
+            ("SCHILY.acl.access", "u::rwx,g::r-x,o::r-x"),
+            (
+                "SCHILY.acl.default",
+                "user::rwx,group:0:rwx,mask::rwx,other::---",
+            ),
+        ],
+    );
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "d", io::empty()));
+    let data = t!(ar.into_inner());
+
+    fn acl(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut data = vec![2, 0, 0, 0];
+        for (tag, perm, id) in entries {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&perm.to_le_bytes());
+            data.extend_from_slice(&id.to_le_bytes());
+        }
+        data
+    }
+    let none = u32::MAX;
+
+    let mut target = MemoryTarget::new();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_acls(true);
+    t!(ar.unpack_into(&mut target, "/"));
+    let f = target.get("f").unwrap();
+    assert_eq!(
+        f.xattr(b"system.posix_acl_access").unwrap(),
+        &acl(&[
+            (0x01, 6, none),
+            (0x02, 4, 1000),
+            (0x04, 4, none),
+            (0x10, 4, none),
+            (0x20, 0, none)
+        ])[..]
+    );
+    assert!(f.xattr(b"system.posix_acl_default").is_none());
+    let d = target.get("d").unwrap();
+    assert_eq!(
+        d.xattr(b"system.posix_acl_access").unwrap(),
+        &acl(&[(0x01, 7, none), (0x04, 5, none), (0x20, 5, none)])[..]
+    );
+    assert_eq!(
+        d.xattr(b"system.posix_acl_default").unwrap(),
+        &acl(&[
+            (0x01, 7, none),
+            (0x08, 7, 0),
+            (0x10, 7, none),
+            (0x20, 0, none)
+        ])[..]
+    );
+
+    // ACLs are ignored unless requested
+    let mut target = MemoryTarget::new();
+    t!(Archive::new(&data[..]).unpack_into(&mut target, "/"));
+    assert!(target
+        .get("f")
+        .unwrap()
+        .xattr(b"system.posix_acl_access")
+        .is_none());
+
+    // ACLs on the filesystem are archived by the builder
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir_in("/var/tmp"));
+    let path = td.path().join("f");
+    t!(File::create(&path));
+    let access = acl(&[
+        (0x01, 6, none),
+        (0x02, 4, 1000),
+        (0x04, 4, none),
+        (0x10, 4, none),
+        (0x20, 0, none),
+    ]);
+    if xattr::set(&path, "system.posix_acl_access", &access).is_err() {
+        // the filesystem doesn't support ACLs
+        return;
+    }
+    let mut ar = Builder::new(Vec::new());
+    ar.preserve_acls(true);
+    t!(ar.append_path_with_name(&path, "f"));
+    let data = t!(ar.into_inner());
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let mut entry = t!(entries.next().unwrap());
+    assert_eq!(&*t!(entry.path()), Path::new("f"));
+    let mut exts = t!(entry.pax_extensions()).unwrap();
+    let ext = t!(exts.next().unwrap());
+    assert_eq!(ext.key(), Ok("SCHILY.acl.access"));
+    assert_eq!(
+        ext.value(),
+        Ok("user::rw-,user:1000:r--,group::r--,mask::r--,other::---")
+    );
+}
+
+#[test]
+fn xattr_filter_and_best_effort() {
+    let mut ar = Builder::new(Vec::new());
+    let data = [
+        ("SCHILY.xattr.user.a", "1"),
+        ("SCHILY.xattr.trusted.b", "2"),
+        ("SCHILY.xattr.security.selinux", "3"),
+        ("SCHILY.xattr.user.bin", "lossy"),
+        ("LIBARCHIVE.xattr.user.bin", "YQpiAA=="),
+        ("LIBARCHIVE.xattr.user.sp%20ace", "eA"),
+        ("LIBARCHIVE.xattr.user.bad", "!!"),
+    ]
+    .iter()
+    .map(|(key, value)| {
+        let record = format!(" {}={}\n", key, value);
+        format!("{}{}", record.len() + 2, record)
+    })
+    .collect::<String>();
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::XHeader);
+    header.set_size(data.len() as u64);
+    t!(ar.append_data(&mut header, "pax", data.as_bytes()));
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "f", io::empty()));
+    let data = t!(ar.into_inner());
+
+    // Invalid attributes fail the unpack by default
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_xattrs(true);
+    assert!(ar.unpack_into(&mut MemoryTarget::new(), "/").is_err());
+
+    let mut target = MemoryTarget::new();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_unpack_xattrs(true);
+    ar.set_xattr_best_effort(true);
+    let mut filter = XattrFilter::new();
+    filter
+        .include("user")
+        .include("security")
+        .exclude("security.selinux");
+    ar.set_xattr_filter(filter);
+    t!(ar.unpack_into(&mut target, "/"));
+    let f = target.get("f").unwrap();
+    assert_eq!(f.xattr(b"user.a"), Some(&b"1"[..]));
+    assert_eq!(f.xattr(b"trusted.b"), None);
+    assert_eq!(f.xattr(b"security.selinux"), None);
+    assert_eq!(f.xattr(b"user.bin"), Some(&b"a\nb\0"[..]));
+    assert_eq!(f.xattr(b"user.sp ace"), Some(&b"x"[..]));
+    assert_eq!(f.xattr(b"user.bad"), None);
+
+    let warnings = ar.take_xattr_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].name(), b"user.bad");
+    assert_eq!(warnings[0].path(), Path::new("/f"));
+    assert!(ar.take_xattr_warnings().is_empty());
+}
+
 #[test]
 fn writing_and_extracting_directories() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -451,6 +911,48 @@ fn writing_directories_recursively() {
         .unwrap_or(false));
 }
 
+#[test]
+fn append_to() {
+    fn names(data: &[u8]) -> Vec<String> {
+        let mut ar = Archive::new(data);
+        t!(ar.entries())
+            .map(|e| String::from_utf8_lossy(&t!(e).path_bytes()).into_owned())
+            .collect()
+    }
+    fn append(data: Vec<u8>) -> Vec<u8> {
+        let mut ar = t!(Builder::append_to(Cursor::new(data)));
+        let mut header = Header::new_gnu();
+        header.set_size(3);
+        t!(ar.append_data(&mut header, "d", &b"ddd"[..]));
+        t!(ar.into_inner()).into_inner()
+    }
+
+    // An archive padded past its end, one missing a block of zeros, one
+    // missing them all, and an empty one
+    let mut no_trailer = tar!("simple.tar").to_vec();
+    no_trailer.truncate(3 * 512);
+    for data in [
+        tar!("simple.tar").to_vec(),
+        tar!("simple_missing_last_header.tar").to_vec(),
+        no_trailer,
+    ] {
+        assert_eq!(names(&append(data)), ["a", "b", "c", "d"]);
+    }
+    assert_eq!(names(&append(append(Vec::new()))), ["d", "d"]);
+
+    let data = append(tar!("reading_files.tar").to_vec());
+    let mut ar = Archive::new(&data[..]);
+    let mut entry = t!(t!(ar.entries()).nth(2).unwrap());
+    let mut s = String::new();
+    t!(entry.read_to_string(&mut s));
+    assert_eq!(s, "ddd");
+
+    // An archive cut in the middle of the data of an entry
+    let mut truncated = tar!("reading_files.tar").to_vec();
+    truncated.truncate(512);
+    assert!(Builder::append_to(Cursor::new(truncated)).is_err());
+}
+
 #[test]
 fn append_dir_all_blank_dest() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -557,6 +1059,114 @@ fn handling_incorrect_file_size() {
     assert!(t!(ar.entries()).any(|fr| fr.is_err()));
 }
 
+#[test]
+fn extracting_durably() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut ar = Archive::new(Cursor::new(tar!("directory.tar")));
+    ar.set_durability(Durability::SyncAll);
+    t!(ar.unpack(td.path()));
+    check_dirtree(&td);
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_durability(Durability::SyncFiles);
+    ar.set_atomic(true);
+    t!(ar.unpack(td.path().join("atomic")));
+    assert!(td.path().join("atomic/a").is_file());
+
+    t!(fs::create_dir_all(td.path().join("single/nested")));
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        entry.set_durability(Durability::SyncAll);
+        assert!(t!(entry.unpack_in(td.path().join("single/nested"))));
+    }
+    assert!(td.path().join("single/nested/b").is_file());
+}
+
+#[test]
+fn extracting_atime() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+
+    let mut ar = Archive::new(tar!("pax.tar"));
+    ar.set_preserve_atime(true);
+    t!(ar.unpack(td.path()));
+    let meta = t!(fs::metadata(td.path().join("Cargo.toml")));
+    let atime = FileTime::from_last_access_time(&meta);
+    let mtime = FileTime::from_last_modification_time(&meta);
+    assert_eq!(atime.unix_seconds(), 1453251915);
+    assert_eq!(mtime.unix_seconds(), 1453146164);
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_mtime(2_000_000);
+    header.as_gnu_mut().unwrap().set_atime(1_000_000);
+    t!(ar.append_data(&mut header, "gnu", io::empty()));
+    let mut header = Header::new_ustar();
+    header.set_size(0);
+    header.set_mtime(2_000_000);
+    t!(ar.append_data(&mut header, "ustar", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let mut target = MemoryTarget::new();
+    let mut ar = Archive::new(&data[..]);
+    ar.set_preserve_atime(true);
+    t!(ar.unpack_into(&mut target, "/"));
+    let gnu = target.get("gnu").unwrap();
+    assert_eq!((gnu.atime(), gnu.mtime()), (1_000_000, 2_000_000));
+    let ustar = target.get("ustar").unwrap();
+    assert_eq!((ustar.atime(), ustar.mtime()), (2_000_000, 2_000_000));
+
+    // by default the atime is just the mtime
+    let mut target = MemoryTarget::new();
+    t!(Archive::new(&data[..]).unpack_into(&mut target, "/"));
+    let gnu = target.get("gnu").unwrap();
+    assert_eq!((gnu.atime(), gnu.mtime()), (2_000_000, 2_000_000));
+}
+
+#[test]
+fn extracting_atomically() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::write(td.path().join("a"), "old"));
+    let mut old = t!(File::open(td.path().join("a")));
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_atomic(true);
+    t!(ar.unpack(td.path()));
+
+    // the old file was replaced rather than truncated and rewritten
+    let mut s = String::new();
+    t!(old.read_to_string(&mut s));
+    assert_eq!(s, "old");
+    assert_eq!(
+        t!(fs::read_to_string(td.path().join("a"))),
+        "a\na\na\na\na\na\na\na\na\na\na\n"
+    );
+    let mut names = t!(fs::read_dir(td.path()))
+        .map(|e| t!(e).file_name())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["a", "b"]);
+
+    // a file which can't be read completely leaves the old one in place
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(2048);
+    header.set_cksum();
+    t!(ar.append_data(&mut header, "a", &[1; 512][..]));
+    let mut data = t!(ar.into_inner());
+    data.truncate(1024);
+    let mut ar = Archive::new(&data[..]);
+    ar.set_atomic(true);
+    assert!(ar.unpack(td.path()).is_err());
+    assert_eq!(
+        t!(fs::read_to_string(td.path().join("a"))),
+        "a\na\na\na\na\na\na\na\na\na\na\n"
+    );
+    assert_eq!(t!(fs::read_dir(td.path())).count(), 2);
+}
+
 #[test]
 fn extracting_malicious_tarball() {
     let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
@@ -652,6 +1262,708 @@ fn extracting_malicious_tarball() {
         .unwrap_or(false));
 }
 
+#[test]
+#[cfg(unix)]
+fn unpack_plan() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dst = t!(td.path().canonicalize());
+    t!(fs::write(dst.join("exists"), "old"));
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(2);
+    t!(ar.append_data(&mut header, "a", &b"hi"[..]));
+    t!(ar.append_data(&mut header, "exists", &b"hi"[..]));
+    let mut header = Header::new_gnu();
+    header.as_gnu_mut().unwrap().name[..5].copy_from_slice(b"../up");
+    header.set_size(0);
+    header.set_cksum();
+    t!(ar.append(&header, io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "d/", io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "hl", "a"));
+    header.set_entry_type(EntryType::Symlink);
+    t!(ar.append_link(&mut header, "link", ".."));
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "link/escape", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let plan = t!(ar.unpack_plan(&dst));
+    let plan = plan
+        .iter()
+        .map(|e| (e.path().to_path_buf(), e.action().clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        plan,
+        [
+            (
+                PathBuf::from("a"),
+                PlannedAction::CreateFile {
+                    dst: dst.join("a"),
+                    size: 2,
+                    overwrite: false,
+                }
+            ),
+            (
+                PathBuf::from("exists"),
+                PlannedAction::CreateFile {
+                    dst: dst.join("exists"),
+                    size: 2,
+                    overwrite: true,
+                }
+            ),
+            (PathBuf::from("../up"), PlannedAction::SkipParentDir),
+            (
+                PathBuf::from("hl"),
+                PlannedAction::HardLink {
+                    dst: dst.join("hl"),
+                    target: dst.join("a"),
+                    overwrite: false,
+                }
+            ),
+            (
+                PathBuf::from("link"),
+                PlannedAction::Symlink {
+                    dst: dst.join("link"),
+                    target: PathBuf::from(".."),
+                    overwrite: false,
+                }
+            ),
+            (
+                PathBuf::from("link/escape"),
+                PlannedAction::Escape {
+                    dst: dst.join("link/escape"),
+                }
+            ),
+        ]
+    );
+    // nothing was touched on the filesystem
+    assert_eq!(t!(fs::read_dir(&dst)).count(), 1);
+    assert_eq!(t!(fs::read_to_string(dst.join("exists"))), "old");
+
+    // the plan agrees with what actually happens
+    let mut ar = Archive::new(&data[..]);
+    assert!(ar.unpack(&dst).is_err());
+    assert!(dst.join("hl").is_file());
+    assert!(!dst.join("d").exists());
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::SkipOld);
+    let plan = t!(ar.unpack_plan(&dst));
+    assert_eq!(
+        *plan[0].action(),
+        PlannedAction::KeepExisting { dst: dst.join("a") }
+    );
+    assert_eq!(
+        *plan[1].action(),
+        PlannedAction::CreateFile {
+            dst: dst.join("b"),
+            size: 22,
+            overwrite: false,
+        }
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn unpack_plan_failures() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let dst = t!(td.path().canonicalize());
+
+    // Unlinking a directory which isn't empty fails without recursion
+    t!(fs::create_dir_all(dst.join("a/sub")));
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::Unlink);
+    let plan = t!(ar.unpack_plan(&dst));
+    assert_eq!(
+        *plan[0].action(),
+        PlannedAction::Fail {
+            dst: dst.join("a"),
+            reason: "directory not empty".to_string(),
+        }
+    );
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::Unlink);
+    assert!(ar.unpack(&dst).is_err());
+
+    let mut ar = Archive::new(Cursor::new(tar!("reading_files.tar")));
+    ar.set_overwrite_policy(OverwritePolicy::RecursiveUnlink);
+    let plan = t!(ar.unpack_plan(&dst));
+    assert_eq!(
+        *plan[0].action(),
+        PlannedAction::CreateFile {
+            dst: dst.join("a"),
+            size: 22,
+            overwrite: true,
+        }
+    );
+
+    // Symlink loops fail like they do when unpacking
+    t!(std::os::unix::fs::symlink("loop", dst.join("loop")));
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "loop/file", io::empty()));
+    let data = t!(ar.into_inner());
+    let plan = t!(Archive::new(&data[..]).unpack_plan(&dst));
+    match plan[0].action() {
+        PlannedAction::Fail { reason, .. } => {
+            assert!(reason.contains("too many levels"), "{}", reason)
+        }
+        action => panic!("unexpected {:?}", action),
+    }
+    assert!(Archive::new(&data[..]).unpack(&dst).is_err());
+}
+
+#[test]
+fn unpack_into_memory() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(2);
+    header.set_mode(0o640);
+    header.set_mtime(1_000_000);
+    t!(ar.append_data(&mut header, "dir/a", &b"hi"[..]));
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Link);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "dir/hl", "dir/a"));
+    header.set_entry_type(EntryType::Symlink);
+    t!(ar.append_link(&mut header, "link", "dir"));
+    let mut header = Header::new_gnu();
+    header.set_size(3);
+    t!(ar.append_data(&mut header, "link/b", &b"bye"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut target = MemoryTarget::new();
+    let mut ar = Archive::new(&data[..]);
+    t!(ar.unpack_into(&mut target, "/out"));
+
+    assert_eq!(
+        target.paths().collect::<Vec<_>>(),
+        [
+            Path::new("out"),
+            Path::new("out/dir"),
+            Path::new("out/dir/a"),
+            Path::new("out/dir/b"),
+            Path::new("out/dir/hl"),
+            Path::new("out/link"),
+        ]
+    );
+    let a = target.get("/out/dir/a").unwrap();
+    assert_eq!(a.contents(), Some(&b"hi"[..]));
+    assert_eq!(a.mode(), 0o640);
+    assert_eq!(a.mtime(), 1_000_000);
+    assert_eq!(target.get("out/dir/hl"), Some(a));
+    assert_eq!(
+        target.get("out/link").unwrap().link_target(),
+        Some(Path::new("dir"))
+    );
+    assert_eq!(
+        target.get("out/link/b").unwrap().contents(),
+        Some(&b"bye"[..])
+    );
+
+    // symlinks can't be used to escape the destination in memory either
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Symlink);
+    header.set_size(0);
+    t!(ar.append_link(&mut header, "up", ".."));
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "up/escape", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    assert!(ar.unpack_into(&mut target, "/out").is_err());
+    assert!(target.get("/escape").is_none());
+}
+
+#[test]
+fn unpack_sparse_into_memory() {
+    let rdr = Cursor::new(tar!("sparse.tar"));
+    let mut ar = Archive::new(rdr);
+    let mut target = MemoryTarget::new();
+    t!(ar.unpack_into(&mut target, "/"));
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let rdr = Cursor::new(tar!("sparse.tar"));
+    t!(Archive::new(rdr).unpack(td.path()));
+
+    for name in [
+        "sparse_begin.txt",
+        "sparse_end.txt",
+        "sparse_ext.txt",
+        "sparse.txt",
+    ]
+    .iter()
+    {
+        let expected = t!(fs::read(td.path().join(name)));
+        assert_eq!(target.get(name).unwrap().contents(), Some(&expected[..]));
+    }
+}
+
+#[test]
+fn archive_index() {
+    let long = format!("dir/{}", "x".repeat(150));
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "./dir/", io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_size(5);
+    t!(ar.append_data(&mut header, "dir/a", &b"first"[..]));
+    t!(ar.append_data(&mut header, &long, &b"long!"[..]));
+    t!(ar.append_pax_extensions(vec![("path", &b"dir/pax"[..])]));
+    t!(ar.append_data(&mut header, "ignored", &b"pax!!"[..]));
+    header.set_size(6);
+    t!(ar.append_data(&mut header, "dir/a", &b"second"[..]));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(Cursor::new(data));
+    let index = t!(tar::ArchiveIndex::build(&mut ar));
+    let mut reader = ar.into_inner();
+    assert_eq!(index.len(), 5);
+    assert_eq!(index.entries()[0].entry_type(), EntryType::Directory);
+
+    let read = |reader: &mut Cursor<Vec<u8>>, path: &str| {
+        let mut contents = String::new();
+        t!(t!(index.open(reader, path)).read_to_string(&mut contents));
+        contents
+    };
+    assert_eq!(read(&mut reader, "dir/a"), "second");
+    assert_eq!(read(&mut reader, &long), "long!");
+    assert_eq!(read(&mut reader, "./dir//pax"), "pax!!");
+    assert_eq!(read(&mut reader, "dir/a"), "second");
+
+    let dir = index.get("dir").unwrap();
+    assert_eq!(dir.path_bytes(), b"dir/");
+    let a = index.get("dir/a").unwrap();
+    assert_eq!(a.size(), 6);
+    assert_eq!(a.mode(), None);
+    assert_eq!(index.get(&long).unwrap().mode(), None);
+    assert_eq!(a.raw_file_position(), a.raw_header_position() + 512);
+    assert!(index.get("ignored").is_none());
+    let err = index.open(&mut reader, "missing").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn archive_index_persisted() {
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(5);
+    t!(ar.append_data(&mut header, "a file\n", &b"hello"[..]));
+    let mut header = Header::new_gnu();
+    header.set_mode(0o777);
+    header.set_size(0);
+    header.set_entry_type(EntryType::Symlink);
+    t!(ar.append_link(&mut header, "link", "a file\n"));
+    let data = t!(ar.into_inner());
+
+    // Archives without an embedded index are left alone.
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    assert!(t!(ar.load_embedded_index()).is_none());
+
+    let digest = |r: &mut dyn Read| -> io::Result<String> {
+        let mut contents = Vec::new();
+        r.read_to_end(&mut contents)?;
+        Ok(format!("len:{}", contents.len()))
+    };
+    let index = t!(tar::ArchiveIndex::build_with_digests(&mut ar, digest));
+    let file = &index.entries()[0];
+    assert_eq!(file.path_bytes(), b"a file\n");
+    assert_eq!(file.digest(), Some("len:5"));
+    assert_eq!(index.entries()[1].digest(), None);
+
+    let mut sidecar = Vec::new();
+    t!(index.write_to(&mut sidecar));
+    assert!(sidecar.starts_with(b"%tar-index 2\n0 512 5 5435 0 - 644 len:5 a%20file%0A\n"));
+    assert!(sidecar.ends_with(b"\n%end 1536\n"));
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let loaded = t!(ar.load_index(&sidecar[..]));
+    assert_eq!(loaded.entries(), index.entries());
+    assert_eq!(
+        loaded.get("link").unwrap().link_name_bytes(),
+        Some(&b"a file\n"[..])
+    );
+    assert_eq!(t!(ar.entries()).count(), 2);
+
+    // An index of a different archive is rejected, even if only a path
+    // differs.
+    let mut other = data.clone();
+    let rewrite = |other: &mut Vec<u8>, f: &dyn Fn(&mut Header)| {
+        let mut header = Header::new_old();
+        header.as_mut_bytes().copy_from_slice(&other[..512]);
+        f(&mut header);
+        header.set_cksum();
+        other[..512].copy_from_slice(header.as_bytes());
+    };
+    rewrite(&mut other, &|h| h.as_mut_bytes()[0] = b'b');
+    let mut ar = Archive::new(Cursor::new(&other[..]));
+    assert!(ar.load_index(&sidecar[..]).is_err());
+    let mut other = data.clone();
+    rewrite(&mut other, &|h| h.set_size(4));
+    let mut ar = Archive::new(Cursor::new(&other[..]));
+    assert!(ar.load_index(&sidecar[..]).is_err());
+
+    // Embedding an index replaces a larger one embedded before.
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let big = t!(tar::ArchiveIndex::build_with_digests(&mut ar, |_| Ok(
+        "x".repeat(2000)
+    )));
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let path = td.path().join("embedded.tar");
+    t!(fs::write(&path, &data));
+    let mut file = t!(fs::OpenOptions::new().read(true).write(true).open(&path));
+    t!(big.embed(&mut file));
+    let big_len = t!(file.metadata()).len();
+    t!(index.embed(&mut file));
+    t!(index.embed(&mut file));
+    let data = t!(fs::read(&path));
+    assert!((data.len() as u64) < big_len);
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let embedded = t!(ar.load_embedded_index()).unwrap();
+    assert_eq!(embedded.entries(), index.entries());
+    let paths = t!(ar.entries())
+        .map(|e| t!(e).path_bytes().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [&b"a file\n"[..], b"link", b".tar-index"]);
+
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let rebuilt = t!(tar::ArchiveIndex::build(&mut ar));
+    assert_eq!(rebuilt.len(), 2);
+
+    // The extension blocks of the last sparse file sit before its data.
+    let mut ar = Archive::new(Cursor::new(&tar!("sparse.tar")[..6144]));
+    let index = t!(tar::ArchiveIndex::build(&mut ar));
+    t!(fs::write(&path, &tar!("sparse.tar")[..6144]));
+    t!(index.embed(&mut t!(fs::OpenOptions::new().write(true).open(&path))));
+    let data = t!(fs::read(&path));
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let paths = t!(ar.entries())
+        .map(|e| t!(e).path_bytes().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            &b"sparse_begin.txt"[..],
+            b"sparse_end.txt",
+            b"sparse_ext.txt",
+            b".tar-index"
+        ]
+    );
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    assert_eq!(t!(ar.load_embedded_index()).unwrap().len(), 3);
+}
+
+#[test]
+fn shared_archive() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<tar::SharedEntry<Vec<u8>>>();
+
+    let mut ar = Builder::new(Vec::new());
+    let mut expected = Vec::new();
+    for i in 0..8 {
+        let contents = format!("{}", i).repeat(1000 * i);
+        let mut header = Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        t!(ar.append_data(&mut header, format!("file{}", i), contents.as_bytes()));
+        expected.push(contents.into_bytes());
+    }
+    let mut header = Header::new_ustar();
+    header.set_size(5);
+    t!(ar.append_pax_extensions(vec![
+        ("GNU.sparse.size", &b"10000"[..]),
+        ("GNU.sparse.map", b"4096,5"),
+    ]));
+    t!(ar.append_data(&mut header, "sparse", &b"hello"[..]));
+    let ar = tar::SharedArchive::new(t!(ar.into_inner()));
+
+    let mut entries = t!(ar.entries());
+    let sparse = entries.pop().unwrap();
+    assert_eq!(sparse.size(), 10000);
+    assert_eq!(sparse.sparse_map().unwrap().data_regions(), [(4096, 5)]);
+    let mut buf = [1; 8];
+    assert_eq!(t!(sparse.read_at(&mut buf, 4092)), 4);
+    assert_eq!(buf[..4], [0; 4]);
+    assert_eq!(t!(sparse.read_at(&mut buf, 4094)), 2);
+    assert_eq!(t!(sparse.read_at(&mut buf, 4096)), 5);
+    assert_eq!(&buf[..5], b"hello");
+    assert_eq!(t!(sparse.read_at(&mut buf, 10000)), 0);
+
+    let threads = entries
+        .into_iter()
+        .rev()
+        .map(|mut entry| {
+            std::thread::spawn(move || {
+                let mut contents = Vec::new();
+                t!(entry.read_to_end(&mut contents));
+                t!(entry.seek(SeekFrom::Start(0)));
+                (entry.path_bytes().to_vec(), contents)
+            })
+        })
+        .collect::<Vec<_>>();
+    for (i, thread) in threads.into_iter().rev().enumerate() {
+        let (path, contents) = thread.join().unwrap();
+        assert_eq!(path, format!("file{}", i).into_bytes());
+        assert!(contents == expected[i]);
+    }
+}
+
+#[cfg(feature = "tokio")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    t!(tokio::runtime::Builder::new_current_thread().build()).block_on(future)
+}
+
+#[test]
+#[cfg(feature = "tokio")]
+fn async_entries() {
+    use tokio::io::AsyncReadExt;
+
+    for data in &[
+        tar!("sparse.tar"),
+        tar!("sparse-1.tar"),
+        tar!("pax.tar"),
+        tar!("7z_long_path.tar"),
+        tar!("link.tar"),
+    ] {
+        let mut ar = Archive::new(*data);
+        let mut expected = Vec::new();
+        for entry in t!(ar.entries()) {
+            let mut entry = t!(entry);
+            let mut contents = Vec::new();
+            t!(entry.read_to_end(&mut contents));
+            expected.push((
+                entry.path_bytes().into_owned(),
+                entry.link_name_bytes().map(|l| l.into_owned()),
+                entry.size(),
+                entry.sparse_map().map(|m| m.data_regions().to_vec()),
+                contents,
+            ));
+        }
+
+        let found = block_on(async {
+            let mut ar = tar::AsyncArchive::new(*data);
+            let mut entries = t!(ar.entries());
+            let mut found = Vec::new();
+            while let Some(entry) = entries.next().await {
+                let mut entry = t!(entry);
+                let mut contents = Vec::new();
+                t!(entry.read_to_end(&mut contents).await);
+                found.push((
+                    entry.path_bytes().to_vec(),
+                    entry.link_name_bytes().map(|l| l.to_vec()),
+                    entry.size(),
+                    entry.sparse_map().map(|m| m.data_regions().to_vec()),
+                    contents,
+                ));
+            }
+            found
+        });
+        assert_eq!(found, expected);
+    }
+
+    // Entries have to be read in order.
+    block_on(async {
+        let mut ar = tar::AsyncArchive::new(tar!("reading_files.tar"));
+        let mut entries = t!(ar.entries());
+        let mut a = t!(entries.next().await.unwrap());
+        let _b = t!(entries.next().await.unwrap());
+        assert!(a.read(&mut [0; 10]).await.is_err());
+    });
+}
+
+#[test]
+#[cfg(all(unix, feature = "tokio"))] // making symlinks on windows is hard
+fn async_unpack() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o750);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "d", io::empty()));
+    let mut header = Header::new_gnu();
+    header.set_mode(0o600);
+    header.set_mtime(1_000_000_000);
+    header.set_size(5);
+    let long = format!("d/{}", "a".repeat(150));
+    t!(ar.append_data(&mut header, &long, &b"hello"[..]));
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(EntryType::Symlink);
+    t!(ar.append_link(&mut header, "s", &long));
+    header.set_entry_type(EntryType::Link);
+    t!(ar.append_link(&mut header, "h", &long));
+    header.set_entry_type(EntryType::Symlink);
+    t!(ar.append_link(&mut header, "escape", "/tmp"));
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(4);
+    t!(ar.append_data(&mut header, "escape/evil", &b"evil"[..]));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = tar::AsyncArchive::new(&data[..]);
+    let unpack = ar.unpack(td.path());
+    fn assert_send<T: Send>(_: &T) {}
+    assert_send(&unpack);
+    let err = block_on(unpack).unwrap_err();
+    assert!(
+        err.to_string().contains("outside of destination"),
+        "{}",
+        err
+    );
+    assert!(!Path::new("/tmp/evil").exists());
+
+    let file = td.path().join(&long);
+    assert_eq!(t!(fs::read_to_string(&file)), "hello");
+    let md = t!(fs::metadata(&file));
+    assert_eq!(md.permissions().mode() & 0o777, 0o600);
+    assert_eq!(
+        FileTime::from_last_modification_time(&md).unix_seconds(),
+        1_000_000_000
+    );
+    assert_eq!(t!(fs::read_to_string(td.path().join("s"))), "hello");
+    assert_eq!(t!(fs::read_to_string(td.path().join("h"))), "hello");
+
+    // Sparse files are unpacked with their holes.
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = tar::AsyncArchive::new(tar!("sparse.tar"));
+    block_on(ar.unpack(td.path())).unwrap();
+    let mut sync = Archive::new(tar!("sparse.tar"));
+    let expected = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(sync.unpack(expected.path()));
+    for name in &[
+        "sparse_begin.txt",
+        "sparse_end.txt",
+        "sparse.txt",
+        "sparse_ext.txt",
+    ] {
+        assert_eq!(
+            t!(fs::read(td.path().join(name))),
+            t!(fs::read(expected.path().join(name)))
+        );
+    }
+
+    // Large files are streamed through, and existing files are kept
+    // according to the overwrite policy.
+    let big = (0..1_000_000u32).map(|i| i as u8).collect::<Vec<_>>();
+    let mut ar = Builder::new(Vec::new());
+    for name in &["big", "kept"] {
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(big.len() as u64);
+        t!(ar.append_data(&mut header, name, &big[..]));
+    }
+    let data = t!(ar.into_inner());
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    t!(fs::write(td.path().join("kept"), "old"));
+    let mut ar = tar::AsyncArchive::new(&data[..]);
+    ar.set_overwrite_policy(OverwritePolicy::SkipOld);
+    block_on(ar.unpack(td.path())).unwrap();
+    assert_eq!(t!(fs::read(td.path().join("big"))), big);
+    assert_eq!(t!(fs::read_to_string(td.path().join("kept"))), "old");
+}
+
+#[test]
+#[cfg(feature = "tokio")]
+fn async_builder() {
+    let long_name = "abcd".repeat(50);
+    let long_link = "efgh".repeat(50);
+    let header = || {
+        let mut header = Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header
+    };
+    let link = || {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        header
+    };
+
+    let long_user = "u".repeat(40);
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_pax_global_extensions(vec![("comment", &b"global"[..])]));
+    t!(ar.append_pax_extensions(vec![("comment", &b"hi"[..])]));
+    t!(ar.append_data(&mut header(), &long_name, &b"foo"[..]));
+    let mut owned = header();
+    t!(ar.set_owner_names(&mut owned, &long_user, "wheel"));
+    t!(ar.append_data(&mut owned, "owned", &b"bar"[..]));
+    t!(ar.append_link(&mut link(), "link", &long_link));
+    let expected = t!(ar.into_inner());
+
+    let actual = block_on(async {
+        let mut ar = tar::AsyncBuilder::new(Vec::new());
+        t!(ar
+            .append_pax_global_extensions(vec![("comment", &b"global"[..])])
+            .await);
+        t!(ar.append_pax_extensions(vec![("comment", &b"hi"[..])]));
+        t!(ar.append_data(&mut header(), &long_name, &b"foo"[..]).await);
+        let mut owned = header();
+        t!(ar.set_owner_names(&mut owned, &long_user, "wheel"));
+        t!(ar.append_data(&mut owned, "owned", &b"bar"[..]).await);
+        t!(ar.append_link(&mut link(), "link", &long_link).await);
+        t!(ar.into_inner().await)
+    });
+    assert!(expected == actual);
+}
+
+#[test]
+#[cfg(feature = "tokio")]
+fn async_builder_dir_all() {
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let src = td.path().join("src");
+    t!(fs::create_dir_all(src.join("a/b")));
+    t!(t!(File::create(src.join("a/b/c.txt"))).write_all(b"contents"));
+    t!(t!(File::create(src.join("d.txt"))).write_all(&[1; 1000]));
+
+    let data = block_on(async {
+        let mut ar = tar::AsyncBuilder::new(Vec::new());
+        t!(ar.append_dir_all("root", &src).await);
+        t!(ar.append_path_with_name(src.join("d.txt"), "e.txt").await);
+        t!(ar.into_inner().await)
+    });
+    assert_eq!(data.len() % 512, 0);
+
+    let mut ar = Archive::new(&data[..]);
+    let mut files = Vec::new();
+    for entry in t!(ar.entries()) {
+        let mut entry = t!(entry);
+        let mut contents = Vec::new();
+        t!(entry.read_to_end(&mut contents));
+        let path = t!(entry.path()).into_owned();
+        files.push((path, entry.header().entry_type(), contents));
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    let file = |path: &str, kind, contents: &[u8]| (PathBuf::from(path), kind, contents.to_vec());
+    assert_eq!(
+        files,
+        vec![
+            file("e.txt", EntryType::Regular, &[1; 1000]),
+            file("root/", EntryType::Directory, b""),
+            file("root/a", EntryType::Directory, b""),
+            file("root/a/b", EntryType::Directory, b""),
+            file("root/a/b/c.txt", EntryType::Regular, b"contents"),
+            file("root/d.txt", EntryType::Regular, &[1; 1000]),
+        ]
+    );
+}
+
 #[test]
 fn octal_spaces() {
     let rdr = Cursor::new(tar!("spaces.tar"));
@@ -848,6 +2160,83 @@ fn unpack_links() {
     t!(File::open(td.path().join("lnk")));
 }
 
+#[test]
+#[cfg(unix)] // making symlinks on windows is hard
+fn unpack_parallel() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Directory);
+    header.set_mode(0o750);
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "d", io::empty()));
+    for i in 0..40 {
+        let contents = format!("{}", i).repeat(i);
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(contents.len() as u64);
+        let path = format!("d/sub{}/f{}", i % 4, i);
+        t!(ar.append_data(&mut header, path, contents.as_bytes()));
+    }
+    for contents in &["first", "second"] {
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(contents.len() as u64);
+        t!(ar.append_data(&mut header, "dup", contents.as_bytes()));
+    }
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(EntryType::Symlink);
+    t!(ar.append_link(&mut header, "s", "d/sub0/f4"));
+    header.set_entry_type(EntryType::Link);
+    t!(ar.append_link(&mut header, "h", "d/sub1/f5"));
+    let mut header = Header::new_ustar();
+    header.set_mode(0o644);
+    header.set_size(5);
+    t!(ar.append_pax_extensions(vec![
+        ("GNU.sparse.size", &b"10000"[..]),
+        ("GNU.sparse.map", b"4096,5"),
+    ]));
+    t!(ar.append_data(&mut header, "sparse", &b"hello"[..]));
+    let data = t!(ar.into_inner());
+
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    t!(ar.unpack_parallel(td.path(), 4));
+
+    for i in 0..40 {
+        let path = td.path().join(format!("d/sub{}/f{}", i % 4, i));
+        assert_eq!(t!(fs::read_to_string(path)), format!("{}", i).repeat(i));
+    }
+    assert_eq!(t!(fs::read_to_string(td.path().join("dup"))), "second");
+    assert_eq!(t!(fs::read_to_string(td.path().join("s"))), "4444");
+    assert_eq!(t!(fs::read_to_string(td.path().join("h"))), "55555");
+    let sparse = t!(fs::read(td.path().join("sparse")));
+    assert_eq!(sparse.len(), 10000);
+    assert_eq!(&sparse[4096..4101], b"hello");
+    let md = t!(fs::metadata(td.path().join("d")));
+    assert_eq!(md.permissions().mode() & 0o777, 0o750);
+
+    // Files can't be written through symlinks pointing outside of the
+    // destination, just like when unpacking sequentially.
+    let outside = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_entry_type(EntryType::Symlink);
+    t!(ar.append_link(&mut header, "link", outside.path()));
+    let mut header = Header::new_gnu();
+    header.set_mode(0o644);
+    header.set_size(4);
+    t!(ar.append_data(&mut header, "link/evil", &b"evil"[..]));
+    let data = t!(ar.into_inner());
+    let td = t!(TempBuilder::new().prefix("tar-rs").tempdir());
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    assert!(ar.unpack_parallel(td.path(), 4).is_err());
+    assert!(!outside.path().join("evil").exists());
+}
+
 #[test]
 fn pax_size() {
     let mut ar = Archive::new(tar!("pax_size.tar"));
@@ -888,6 +2277,178 @@ fn pax_simple() {
     assert_eq!(third.value(), Ok("1453146164.953123768"));
 }
 
+#[test]
+fn pax_records_with_newlines() {
+    let data = b"16 path=one\ntwo\n21 SCHILY.xattr.a=\n\n\n11 uid=100\n";
+    let exts = t!(PaxExtensions::new(data).collect::<io::Result<Vec<_>>>());
+    assert_eq!(exts.len(), 3);
+    assert_eq!(exts[0].key(), Ok("path"));
+    assert_eq!(exts[0].value_bytes(), b"one\ntwo");
+    assert_eq!(exts[1].key(), Ok("SCHILY.xattr.a"));
+    assert_eq!(exts[1].value_bytes(), b"\n\n");
+    assert_eq!(exts[2].key(), Ok("uid"));
+    assert_eq!(exts[2].value(), Ok("100"));
+
+    for bad in [
+        &b"30 path=foo\n"[..],
+        b"5 path=foo\n",
+        b"11 path=foo\n",
+        b"11 pathfoo\n",
+        b"x1 path=fo\n",
+        b"path=foo\n",
+    ]
+    .iter()
+    {
+        let mut exts = PaxExtensions::new(bad);
+        assert!(exts.next().unwrap().is_err());
+        assert!(exts.next().is_none());
+    }
+}
+
+#[test]
+fn pax_builder() {
+    // Records whose length gains a digit once the digits are counted
+    for n in 90..110 {
+        let value = "x".repeat(n);
+        let mut pax = PaxBuilder::new();
+        pax.add("a", &value);
+        let ext = t!(PaxExtensions::new(pax.as_bytes()).next().unwrap());
+        assert_eq!(ext.value(), Ok(&value[..]));
+    }
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_pax_global_extensions(vec![("comment", &b"global"[..])]));
+    t!(ar.append_pax_extensions(vec![("SCHILY.xattr.user.a", &b"1\n2"[..])]));
+    t!(ar.append_pax_extensions(vec![("comment", &b"local"[..])]));
+    assert!(ar
+        .append_pax_extensions(vec![("uid", &b"0"[..]), ("a=b", b"")])
+        .is_err());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.append_data(&mut header, "a", io::empty()));
+    t!(ar.append_data(&mut header, "b", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let mut global = t!(entries.next().unwrap());
+    assert_eq!(global.header().entry_type(), EntryType::XGlobalHeader);
+    let mut contents = Vec::new();
+    t!(global.read_to_end(&mut contents));
+    assert_eq!(contents, b"18 comment=global\n");
+
+    let mut a = t!(entries.next().unwrap());
+    assert_eq!(&*t!(a.path()), Path::new("a"));
+    let exts = t!(t!(a.pax_extensions())
+        .unwrap()
+        .map(|e| e.map(|e| (e.key_bytes().to_vec(), e.value_bytes().to_vec())))
+        .collect::<io::Result<Vec<_>>>());
+    assert_eq!(
+        exts,
+        vec![
+            (b"SCHILY.xattr.user.a".to_vec(), b"1\n2".to_vec()),
+            (b"comment".to_vec(), b"local".to_vec()),
+        ]
+    );
+
+    let mut b = t!(entries.next().unwrap());
+    assert!(t!(b.pax_extensions()).is_none());
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn pax_map() {
+    let mut ar = Archive::new(tar!("pax.tar"));
+    let first = t!(t!(ar.entries()).next().unwrap());
+    let pax = first.pax().unwrap();
+    assert_eq!(
+        pax.mtime(),
+        Some(UNIX_EPOCH + Duration::new(1453146164, 953123768))
+    );
+    assert_eq!(
+        pax.atime(),
+        Some(UNIX_EPOCH + Duration::new(1453251915, 248924860))
+    );
+    assert_eq!(pax.get_u64("mtime"), None);
+    assert_eq!(pax.unknown().count(), 0);
+
+    let mut ar = Builder::new(Vec::new());
+    t!(ar.append_pax_global_extensions(vec![
+        ("comment", &b"global"[..]),
+        ("uid", b"1000"),
+        ("mtime", b"-1.5"),
+        ("VENDOR.key", b"value"),
+    ]));
+    t!(ar.append_pax_extensions(vec![
+        ("uid", &b""[..]),
+        ("gid", b"2000"),
+        ("SCHILY.xattr.user.a", b"1"),
+    ]));
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    header.set_uid(7);
+    t!(ar.append_data(&mut header, "a", io::empty()));
+    t!(ar.append_pax_global_extensions(vec![("comment", &b""[..])]));
+    t!(ar.append_data(&mut header, "b", io::empty()));
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let entries = t!(t!(ar.entries()).collect::<io::Result<Vec<_>>>());
+    assert_eq!(entries.len(), 4);
+    assert!(entries[0].pax().is_none());
+
+    let a = &entries[1];
+    let pax = a.pax().unwrap();
+    assert_eq!(pax.comment(), Some("global"));
+    assert_eq!(pax.uid(), None);
+    assert_eq!(pax.gid(), Some(2000));
+    assert_eq!(pax.mtime(), Some(UNIX_EPOCH - Duration::from_millis(1500)));
+    assert_eq!(
+        pax.xattrs().collect::<Vec<_>>(),
+        vec![(&b"user.a"[..], &b"1"[..])]
+    );
+    assert_eq!(
+        pax.unknown().collect::<Vec<_>>(),
+        vec![(&b"VENDOR.key"[..], &b"value"[..])]
+    );
+    assert_eq!(t!(a.header().uid()), 7);
+    assert_eq!(t!(a.header().gid()), 2000);
+
+    // Global records keep applying, and are removed by empty values
+    let b = &entries[3];
+    let pax = b.pax().unwrap();
+    assert_eq!(pax.comment(), None);
+    assert_eq!(pax.uid(), Some(1000));
+    assert_eq!(pax.gid(), None);
+    assert_eq!(t!(b.header().uid()), 1000);
+}
+
+#[test]
+fn pax_owner_names() {
+    let long = "a-rather-long-user-name-for-a-header";
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_gnu();
+    header.set_size(0);
+    t!(ar.set_owner_names(&mut header, long, "gr\u{fc}ppe"));
+    t!(ar.append_data(&mut header, "a", io::empty()));
+    t!(ar.set_owner_names(&mut header, "root", "wheel"));
+    t!(ar.append_data(&mut header, "b", io::empty()));
+    assert!(ar.set_owner_names(&mut header, "ro\0ot", "wheel").is_err());
+    let data = t!(ar.into_inner());
+
+    let mut ar = Archive::new(&data[..]);
+    let mut entries = t!(ar.entries());
+    let a = t!(entries.next().unwrap());
+    assert_eq!(a.username(), Ok(Some(long)));
+    assert_eq!(a.groupname(), Ok(Some("gr\u{fc}ppe")));
+    assert_eq!(a.header().username(), Ok(Some(&long[..32])));
+    assert_eq!(a.header().groupname(), Ok(Some("gr\u{fc}ppe")));
+    let b = t!(entries.next().unwrap());
+    assert!(b.pax().is_none());
+    assert_eq!(b.username(), Ok(Some("root")));
+    assert_eq!(b.groupname_bytes(), Some(&b"wheel"[..]));
+}
+
 #[test]
 fn pax_path() {
     let mut ar = Archive::new(tar!("pax2.tar"));
@@ -1079,6 +2640,137 @@ fn reading_sparse() {
     assert!(entries.next().is_none());
 }
 
+#[test]
+fn sparse_maps() {
+    let mut ar = Archive::new(Cursor::new(tar!("sparse.tar")));
+    let mut entries = t!(ar.entries());
+    let entry = t!(entries.next().unwrap());
+    let map = entry.sparse_map().unwrap();
+    assert_eq!(map.real_size(), entry.size());
+    assert_eq!(map.data_regions()[0].0, 0);
+    let entry = t!(entries.next().unwrap());
+    let map = entry.sparse_map().unwrap();
+    let last = *map.data_regions().last().unwrap();
+    assert_eq!(last.0 + last.1, map.real_size());
+
+    let mut ar = Archive::new(Cursor::new(tar!("sparse.tar")));
+    let index = t!(tar::ArchiveIndex::build(&mut ar));
+    let mut sidecar = Vec::new();
+    t!(index.write_to(&mut sidecar));
+    t!(ar.load_index(&sidecar[..]));
+
+    // The pax formats 0.0, 0.1 and 1.0 of GNU tar, all holding the same file.
+    let mut ar = Builder::new(Vec::new());
+    let mut header = Header::new_ustar();
+    header.set_size(10);
+    t!(ar.append_pax_extensions(vec![
+        ("GNU.sparse.size", &b"20000"[..]),
+        ("GNU.sparse.numblocks", b"2"),
+        ("GNU.sparse.offset", b"4096"),
+        ("GNU.sparse.numbytes", b"5"),
+        ("GNU.sparse.offset", b"10000"),
+        ("GNU.sparse.numbytes", b"5"),
+    ]));
+    t!(ar.append_data(&mut header, "sparse.txt", &b"helloworld"[..]));
+    t!(ar.append_pax_extensions(vec![
+        ("GNU.sparse.size", &b"20000"[..]),
+        ("GNU.sparse.numblocks", b"2"),
+        ("GNU.sparse.map", b"4096,5,10000,5"),
+        ("GNU.sparse.name", b"sparse.txt"),
+    ]));
+    t!(ar.append_data(&mut header, "GNUSparseFile.0/a", &b"helloworld"[..]));
+    let mut data = b"2\n4096\n5\n10000\n5\n".to_vec();
+    data.resize(512, 0);
+    data.extend_from_slice(b"helloworld");
+    header.set_size(data.len() as u64);
+    t!(ar.append_pax_extensions(vec![
+        ("GNU.sparse.major", &b"1"[..]),
+        ("GNU.sparse.minor", b"0"),
+        ("GNU.sparse.realsize", b"20000"),
+        ("GNU.sparse.name", b"sparse.txt"),
+    ]));
+    t!(ar.append_data(&mut header, "GNUSparseFile.0/b", &data[..]));
+    let data = t!(ar.into_inner());
+
+    let mut expected = vec![0; 20000];
+    expected[4096..4101].copy_from_slice(b"hello");
+    expected[10000..10005].copy_from_slice(b"world");
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let mut n = 0;
+    for entry in t!(ar.entries_with_seek()) {
+        let mut entry = t!(entry);
+        assert_eq!(&*entry.path_bytes(), b"sparse.txt");
+        assert_eq!(entry.size(), 20000);
+        let map = entry.sparse_map().unwrap();
+        assert_eq!(map.data_regions(), [(4096, 5), (10000, 5)]);
+        assert_eq!(map.real_size(), 20000);
+        let mut buf = [0; 5];
+        assert_eq!(t!(entry.read_at(&mut buf, 10000)), 5);
+        assert_eq!(&buf, b"world");
+        let mut contents = Vec::new();
+        t!(entry.read_to_end(&mut contents));
+        assert!(contents == expected);
+        n += 1;
+    }
+    assert_eq!(n, 3);
+
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let index = t!(tar::ArchiveIndex::build(&mut ar));
+    let last = index.get("sparse.txt").unwrap();
+    assert!(last.is_sparse());
+    assert_eq!(last.size(), 522);
+    let mut ar = Archive::new(Cursor::new(&data[..]));
+    let mut sidecar = Vec::new();
+    t!(index.write_to(&mut sidecar));
+    t!(ar.load_index(&sidecar[..]));
+}
+
+#[test]
+fn seeking_in_entries() {
+    let rdr = Cursor::new(tar!("sparse.tar"));
+    let mut ar = Archive::new(rdr);
+    let mut entries = t!(ar.entries_with_seek());
+
+    let mut begin = t!(entries.next().unwrap());
+    let mut buf = [0; 5];
+    t!(begin.read_exact(&mut buf));
+    assert_eq!(&buf, b"test\n");
+    assert_eq!(t!(begin.seek(SeekFrom::Current(-2))), 3);
+    t!(begin.read_exact(&mut buf[..2]));
+    assert_eq!(&buf[..2], b"t\n");
+    drop(begin);
+
+    t!(entries.next().unwrap());
+    let mut ext = t!(entries.next().unwrap());
+    assert_eq!(t!(ext.read_at(&mut buf, 0x9000)), 5);
+    assert_eq!(&buf, b"text\n");
+    assert_eq!(t!(ext.read_at(&mut buf[..3], 0x3002)), 3);
+    assert_eq!(&buf[..3], b"xt\n");
+    assert_eq!(t!(ext.read_at(&mut buf, 0x2000)), 5);
+    assert_eq!(buf, [0; 5]);
+    assert_eq!(t!(ext.seek(SeekFrom::End(0))), ext.size());
+    assert_eq!(t!(ext.read(&mut buf)), 0);
+    t!(ext.seek(SeekFrom::Start(0xb000)));
+    let mut s = String::new();
+    t!(ext.read_to_string(&mut s));
+    assert_eq!(&s[..5], "text\n");
+    assert!(ext.seek(SeekFrom::Current(-0x10000)).is_err());
+    drop(ext);
+
+    // Reading the rest of the archive is unaffected by seeking around.
+    let mut sparse = t!(entries.next().unwrap());
+    assert_eq!(&*sparse.header().path_bytes(), b"sparse.txt");
+    t!(sparse.seek(SeekFrom::Start(0x2fa0)));
+    let mut s = String::new();
+    t!(sparse.read_to_string(&mut s));
+    assert_eq!(&s[..6], "world\n");
+    assert!(entries.next().is_none());
+
+    let mut ar = Archive::new(Cursor::new(tar!("sparse.tar")));
+    let mut entry = t!(t!(ar.entries()).next().unwrap());
+    assert!(entry.seek(SeekFrom::Start(1)).is_err());
+}
+
 #[test]
 fn extract_sparse() {
     let rdr = Cursor::new(tar!("sparse.tar"));